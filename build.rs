@@ -0,0 +1,16 @@
+use std::process::Command;
+
+fn main() {
+    // the commit that `--version` shows, when built from a git checkout
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=DMITRI_COMMIT={}", commit);
+    }
+}
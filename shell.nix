@@ -6,7 +6,7 @@ pkgs.mkShell {
     rustc cargo freetype pkgconfig expat
   ];
 
-  buildInputs = with pkgs; [ freetype expat fontconfig ];
+  buildInputs = with pkgs; [ freetype expat fontconfig libxkbcommon ];
 
   RUST_BACKTRACE = 1;
 }
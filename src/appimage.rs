@@ -0,0 +1,108 @@
+use crate::{
+    error::DmitriError,
+    items::{spawn_detached, Item, ItemSink, ItemSource},
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Words of AppImage file names that aren't part of the application's name.
+const ARCHITECTURES: [&str; 7] = [
+    "x86_64", "amd64", "i386", "i686", "aarch64", "arm64", "armhf",
+];
+
+/// The `*.AppImage` files in `~/Applications` and the `dirs`, by the names of their
+/// applications, which are output as their paths.
+pub struct AppImageSource {
+    pub dirs: Vec<String>,
+}
+
+impl ItemSource for AppImageSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        let home = env::var("HOME").ok();
+        let mut dirs: Vec<PathBuf> = home
+            .iter()
+            .map(|home| Path::new(home).join("Applications"))
+            .collect();
+        for dir in &self.dirs {
+            dirs.push(match (dir.strip_prefix("~/"), &home) {
+                (Some(dir), Some(home)) => Path::new(home).join(dir),
+                _ => PathBuf::from(dir),
+            });
+        }
+        dirs.dedup();
+
+        let mut items = vec![];
+        for dir in dirs {
+            let read_dir = match fs::read_dir(&dir) {
+                Ok(read_dir) => read_dir,
+                Err(_) => continue,
+            };
+            for entry in read_dir.flatten() {
+                let path = entry.path();
+                let filename = entry.file_name().to_string_lossy().to_string();
+                let stem = match filename.len().checked_sub(".appimage".len()) {
+                    Some(end) if filename[end..].eq_ignore_ascii_case(".appimage") => {
+                        &filename[..end]
+                    }
+                    _ => continue,
+                };
+                if !path.is_file() {
+                    continue;
+                }
+                // versions of the same application are told apart by their file names
+                items.push(
+                    Item::with_value(name(stem), path.to_string_lossy().to_string())
+                        .with_description(Some(filename.clone())),
+                );
+            }
+        }
+        items.sort_by(|a, b| a.display().cmp(b.display()));
+        Ok(items)
+    }
+}
+
+/// The name of the application of an AppImage, from its file name without the extension: the
+/// words before the version and architecture, like `Obsidian` of `Obsidian-1.5.3`, or
+/// `Krita` of `krita-5.2.2-x86_64`, with underscores as spaces.
+fn name(stem: &str) -> String {
+    let words: Vec<&str> = stem
+        .split('-')
+        .take_while(|word| {
+            let version = word.strip_prefix(['v', 'V']).unwrap_or(word);
+            !version.starts_with(|c: char| c.is_ascii_digit())
+                && !ARCHITECTURES.contains(&word.to_lowercase().as_str())
+                && !word.eq_ignore_ascii_case("linux")
+        })
+        .collect();
+    let name = match words.join("-") {
+        name if name.is_empty() => stem.to_string(),
+        name => name,
+    };
+    let name = name.replace('_', " ");
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+
+/// Runs the selected AppImage.
+pub struct AppImageSink;
+
+impl ItemSink for AppImageSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        if !Path::new(&selection).is_file() {
+            eprintln!("No AppImage at {}", selection);
+            return Ok(());
+        }
+        spawn_detached(&mut process::Command::new(&selection)).map_err(|source| {
+            DmitriError::Spawn {
+                command: selection,
+                source,
+            }
+        })
+    }
+}
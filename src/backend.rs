@@ -0,0 +1,169 @@
+use crate::{error::DmitriError, text::Frame};
+use std::{
+    io,
+    ops::Range,
+    os::fd::{AsRawFd, BorrowedFd},
+    str::FromStr,
+    time::Instant,
+};
+use x11rb::image::{Image, PixelLayout};
+
+/// Input and window events, independent of the display server.
+pub enum Event {
+    /// The window contents were lost and have to be presented again.
+    Redraw,
+    Key(Key),
+    /// The pointer moved to `x`, `y` in the window.
+    Motion(i32, i32),
+    /// The primary button was pressed at `x`, `y` in the window.
+    Click(i32, i32),
+    /// The wheel turned by this many steps, positive towards the user.
+    Scroll(i32),
+    /// Text from the clipboard or the primary selection, after `paste` or a middle click.
+    Paste(String),
+    /// The window was closed from the outside, or a button was pressed outside of it.
+    Close,
+    /// The file descriptor given to `next_event` can be read.
+    Wake,
+    /// The deadline given to `next_event` passed.
+    Timeout,
+}
+
+pub struct Key {
+    /// The keysym after applying the keyboard layout and modifiers.
+    pub keysym: u32,
+    /// The text typed by the key, empty for keys that don't produce any.
+    pub text: String,
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+/// Where the window goes on the screen, relative to the selected monitor.
+pub struct Placement {
+    /// Index of the monitor, or `None` for the one with the pointer.
+    pub monitor: Option<usize>,
+    /// Place the window at the bottom edge instead of the top, unless `y` is set.
+    pub bottom: bool,
+    pub x: Option<Length>,
+    pub y: Option<Length>,
+    /// The window spans from `x` to the right edge of the monitor if not set.
+    pub width: Option<Length>,
+}
+
+impl Placement {
+    /// Position and width of a window of the given height on a monitor at `x`, `y` with the size
+    /// `width` by `height`.
+    pub fn resolve(&self, monitor: (i16, i16, u16, u16), height: u16) -> (i16, i16, u16) {
+        let (mx, my, mwidth, mheight) = monitor;
+        let x = self.x.map_or(0, |x| x.resolve(mwidth));
+        let y = match self.y {
+            Some(y) => y.resolve(mheight),
+            None if self.bottom => i32::from(mheight) - i32::from(height),
+            None => 0,
+        };
+        let width = self
+            .width
+            .map_or(i32::from(mwidth) - x, |width| width.resolve(mwidth));
+        (
+            (i32::from(mx) + x) as i16,
+            (i32::from(my) + y) as i16,
+            width.clamp(1, i32::from(u16::MAX)) as u16,
+        )
+    }
+}
+
+/// A distance in pixels, or a percentage of the monitor's width or height.
+#[derive(Clone, Copy)]
+pub enum Length {
+    Pixels(i32),
+    Percent(f32),
+}
+
+impl Length {
+    pub fn resolve(self, total: u16) -> i32 {
+        match self {
+            Length::Pixels(pixels) => pixels,
+            Length::Percent(percent) => (f32::from(total) * percent / 100.) as i32,
+        }
+    }
+}
+
+impl FromStr for Length {
+    type Err = DmitriError;
+
+    fn from_str(s: &str) -> Result<Length, DmitriError> {
+        let length = match s.strip_suffix('%') {
+            Some(percent) => percent.parse().ok().map(Length::Percent),
+            None => s.parse().ok().map(Length::Pixels),
+        };
+        length.ok_or_else(|| DmitriError::Config(format!("Invalid length: {}", s)))
+    }
+}
+
+/// A bar shaped window on some display server that shows rendered images and delivers input.
+pub trait Backend {
+    /// Width and height of the window in pixels.
+    fn size(&self) -> (u16, u16);
+
+    /// Allocate an image of the window's size in a pixel format that can be presented.
+    fn create_image(&mut self) -> Result<(Image<'static>, PixelLayout), DmitriError>;
+
+    /// Whether the backend draws `Frame::Commands` itself, instead of showing images.
+    fn draws_commands(&self) -> bool {
+        false
+    }
+
+    /// Show the frame, of which only the given ranges of rows changed since the last time.
+    fn present(&mut self, frame: Frame, rows: &[Range<u16>]) -> Result<(), DmitriError>;
+
+    /// Request the contents of the clipboard, or of the primary selection. The text arrives as an
+    /// `Event::Paste`, if there is any.
+    fn paste(&mut self, primary: bool) -> Result<(), DmitriError>;
+
+    /// Block until the next event, or until `wake` can be read or the deadline passed.
+    fn next_event(
+        &mut self,
+        wake: BorrowedFd<'_>,
+        deadline: Option<Instant>,
+    ) -> Result<Event, DmitriError>;
+
+    fn hide(&mut self) -> Result<(), DmitriError>;
+
+    /// Show the window again after `hide`.
+    fn show(&mut self) -> Result<(), DmitriError>;
+}
+
+/// Block until the display server's connection or `wake` can be read, or until the deadline.
+/// `Event::Wake` if `wake` can be read, `Event::Timeout` if the deadline passed, and `None` if
+/// only the connection can be read.
+pub fn wait_readable(
+    connection: &impl AsRawFd,
+    wake: &impl AsRawFd,
+    deadline: Option<Instant>,
+) -> Result<Option<Event>, DmitriError> {
+    let mut fds = [connection.as_raw_fd(), wake.as_raw_fd()].map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    loop {
+        // rounded up, so that it doesn't return just before the deadline
+        let timeout = deadline.map_or(-1, |deadline| {
+            let nanos = deadline
+                .saturating_duration_since(Instant::now())
+                .as_nanos();
+            nanos.div_ceil(1_000_000).min(i32::MAX as u128) as i32
+        });
+        // Safety: the file descriptors stay open during the call.
+        match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } {
+            0 => return Ok(Some(Event::Timeout)),
+            n if n > 0 => return Ok((fds[1].revents != 0).then_some(Event::Wake)),
+            _ => (),
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err.into());
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+/// The bangs that are known without a config, by name, with `{query}` where the query goes.
+const DEFAULT_BANGS: [(&str, &str); 4] = [
+    ("ddg", "https://duckduckgo.com/?q={query}"),
+    ("g", "https://www.google.com/search?q={query}"),
+    ("w", "https://en.wikipedia.org/w/index.php?search={query}"),
+    ("gh", "https://github.com/search?q={query}"),
+];
+
+/// The URL templates of the bangs by name: the default ones, replaced by those of the config,
+/// where an empty template removes one.
+pub fn with_defaults(configured: HashMap<String, String>) -> HashMap<String, String> {
+    let mut bangs: HashMap<String, String> = DEFAULT_BANGS
+        .iter()
+        .map(|(name, template)| (name.to_string(), template.to_string()))
+        .collect();
+    for (name, template) in configured {
+        if template.trim().is_empty() {
+            bangs.remove(&name);
+        } else {
+            bangs.insert(name, template);
+        }
+    }
+    bangs
+}
+
+/// The query and the URL of a web search, if the input is a known bang and a query like
+/// `!g rust xcb`.
+pub fn search(input: &str, bangs: &HashMap<String, String>) -> Option<(String, String)> {
+    let (bang, query) = input.strip_prefix('!')?.split_once(char::is_whitespace)?;
+    let query = query.trim();
+    if query.is_empty() {
+        return None;
+    }
+    let template = bangs.get(bang)?;
+    Some((
+        query.to_string(),
+        template.replace("{query}", &encode(query)),
+    ))
+}
+
+/// Escape the query for a URL, with `%20` for spaces.
+fn encode(query: &str) -> String {
+    let mut encoded = String::new();
+    for byte in query.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
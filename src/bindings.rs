@@ -0,0 +1,308 @@
+use crate::{backend::Key, config::Chords, error::DmitriError};
+use std::{collections::HashMap, str::FromStr};
+use xkbcommon::xkb::{self, keysyms};
+
+/// What a key does in the picker.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Run the selected match, or the input if none is selected.
+    Confirm,
+    /// Run the input as typed, even if a match is selected.
+    AltConfirm,
+    /// Run the selected match like `Confirm`, but keep the window open with the same input.
+    ConfirmKeepOpen,
+    /// Run the selected match like `Confirm`, in a terminal emulator.
+    ConfirmInTerminal,
+    /// Run the selected match like `Confirm`, with the other action of the mode, like
+    /// restarting a unit with `--systemd`.
+    ConfirmAlternate,
+    /// Run the selected match like `Confirm`, as root.
+    ConfirmAsRoot,
+    Cancel,
+    /// Complete the input with `--tab-complete`, or else select the next match.
+    Complete,
+    Next,
+    Prev,
+    /// Move the caret, or the selection when the matches are on the input line and one is
+    /// selected.
+    Left,
+    Right,
+    Home,
+    End,
+    Backspace,
+    Delete,
+    DeleteWord,
+    KillToStart,
+    KillToEnd,
+    /// Paste the clipboard.
+    Paste,
+    PastePrimary,
+    /// Run the shown match with this number, from 1.
+    Pick(usize),
+    /// Switch to the next mode of `--modes`, keeping the input.
+    NextMode,
+    PrevMode,
+}
+
+/// The actions by their names in the `[keys]` section of the config.
+const ACTIONS: [(&str, Action); 23] = [
+    ("confirm", Action::Confirm),
+    ("alt-confirm", Action::AltConfirm),
+    ("confirm-keep-open", Action::ConfirmKeepOpen),
+    ("confirm-terminal", Action::ConfirmInTerminal),
+    ("confirm-alternate", Action::ConfirmAlternate),
+    ("confirm-root", Action::ConfirmAsRoot),
+    ("cancel", Action::Cancel),
+    ("complete", Action::Complete),
+    ("next", Action::Next),
+    ("prev", Action::Prev),
+    ("left", Action::Left),
+    ("right", Action::Right),
+    ("home", Action::Home),
+    ("end", Action::End),
+    ("backspace", Action::Backspace),
+    ("delete", Action::Delete),
+    ("delete-word", Action::DeleteWord),
+    ("kill-to-start", Action::KillToStart),
+    ("kill-to-end", Action::KillToEnd),
+    ("paste", Action::Paste),
+    ("paste-primary", Action::PastePrimary),
+    ("next-mode", Action::NextMode),
+    ("prev-mode", Action::PrevMode),
+];
+
+/// The action of a name in the config, where `pick-1` to `pick-9` pick the shown matches.
+fn action_named(name: &str) -> Option<Action> {
+    if let Some(n) = name.strip_prefix("pick-") {
+        return n
+            .parse()
+            .ok()
+            .filter(|n| (1..=9).contains(n))
+            .map(Action::Pick);
+    }
+    ACTIONS
+        .iter()
+        .find(|(action_name, _)| *action_name == name)
+        .map(|(_, action)| *action)
+}
+
+/// A key with the modifiers that are held, like `Ctrl+Shift+Return`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    keysym: u32,
+    shift: bool,
+    ctrl: bool,
+    alt: bool,
+}
+
+impl Chord {
+    fn new(keysym: u32, shift: bool, ctrl: bool) -> Chord {
+        Chord {
+            keysym: normalize(keysym),
+            shift,
+            ctrl,
+            alt: false,
+        }
+    }
+
+    fn of(key: &Key) -> Chord {
+        Chord {
+            keysym: normalize(key.keysym),
+            shift: key.shift,
+            ctrl: key.ctrl,
+            alt: key.alt,
+        }
+    }
+}
+
+/// The keysym that chords are written with: Shift+Tab produces ISO_Left_Tab, and Shift turns
+/// letters uppercase, but Shift is part of the chord already.
+fn normalize(keysym: u32) -> u32 {
+    match keysym {
+        keysyms::KEY_ISO_Left_Tab => keysyms::KEY_Tab,
+        keysyms::KEY_A..=keysyms::KEY_Z => keysym - keysyms::KEY_A + keysyms::KEY_a,
+        keysym => keysym,
+    }
+}
+
+impl FromStr for Chord {
+    type Err = DmitriError;
+
+    /// Modifiers and a keysym name joined by `+`, like `Ctrl+n` or `Shift+Return`.
+    fn from_str(s: &str) -> Result<Chord, DmitriError> {
+        let invalid = || DmitriError::Config(format!("Invalid key: {}", s));
+        let (modifiers, name) = match s.rsplit_once('+') {
+            // `+` itself is a key too
+            Some((modifiers, "")) => (modifiers.strip_suffix('+').unwrap_or(modifiers), "plus"),
+            Some((modifiers, name)) => (modifiers, name),
+            None => ("", s),
+        };
+        let mut keysym = xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS).raw();
+        if keysym == keysyms::KEY_NoSymbol {
+            keysym = xkb::keysym_from_name(name, xkb::KEYSYM_CASE_INSENSITIVE).raw();
+        }
+        if keysym == keysyms::KEY_NoSymbol {
+            return Err(invalid());
+        }
+        let mut chord = Chord::new(keysym, false, false);
+        for modifier in modifiers.split('+').filter(|modifier| !modifier.is_empty()) {
+            match modifier.to_lowercase().as_str() {
+                "shift" => chord.shift = true,
+                "ctrl" | "control" => chord.ctrl = true,
+                "alt" | "mod1" => chord.alt = true,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(chord)
+    }
+}
+
+/// The action of each key chord.
+pub struct Bindings {
+    chords: Vec<(Chord, Action)>,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        use keysyms::*;
+        let chords = [
+            (Chord::new(KEY_Return, false, false), Action::Confirm),
+            (Chord::new(KEY_KP_Enter, false, false), Action::Confirm),
+            (Chord::new(KEY_Return, true, false), Action::AltConfirm),
+            (Chord::new(KEY_Return, false, true), Action::ConfirmKeepOpen),
+            (
+                Chord::new(KEY_KP_Enter, false, true),
+                Action::ConfirmKeepOpen,
+            ),
+            (Chord::new(KEY_Escape, false, false), Action::Cancel),
+            (Chord::new(KEY_Tab, false, false), Action::Complete),
+            (Chord::new(KEY_Tab, true, false), Action::Prev),
+            (Chord::new(KEY_Down, false, false), Action::Next),
+            (Chord::new(KEY_n, false, true), Action::Next),
+            (Chord::new(KEY_Up, false, false), Action::Prev),
+            (Chord::new(KEY_p, false, true), Action::Prev),
+            (Chord::new(KEY_Left, false, false), Action::Left),
+            (Chord::new(KEY_Right, false, false), Action::Right),
+            (Chord::new(KEY_Home, false, false), Action::Home),
+            (Chord::new(KEY_a, false, true), Action::Home),
+            (Chord::new(KEY_End, false, false), Action::End),
+            (Chord::new(KEY_e, false, true), Action::End),
+            (Chord::new(KEY_BackSpace, false, false), Action::Backspace),
+            (Chord::new(KEY_Delete, false, false), Action::Delete),
+            (Chord::new(KEY_w, false, true), Action::DeleteWord),
+            (Chord::new(KEY_u, false, true), Action::KillToStart),
+            (Chord::new(KEY_k, false, true), Action::KillToEnd),
+            (Chord::new(KEY_v, false, true), Action::Paste),
+            (Chord::new(KEY_Insert, true, false), Action::PastePrimary),
+            (Chord::new(KEY_Tab, false, true), Action::NextMode),
+            (Chord::new(KEY_Tab, true, true), Action::PrevMode),
+        ];
+        let mut chords = chords.to_vec();
+        // Alt+1 to Alt+9 pick the shown matches
+        for n in 1..=9 {
+            let mut chord = Chord::new(KEY_0 + n as u32, false, false);
+            chord.alt = true;
+            chords.push((chord, Action::Pick(n)));
+        }
+        Bindings { chords }
+    }
+}
+
+impl Bindings {
+    /// The default bindings, with the chords of the actions in `keys` replaced by the given
+    /// ones. These take precedence where the chords are also bound by default.
+    pub fn new(keys: &HashMap<String, Chords>) -> Result<Bindings, DmitriError> {
+        let mut bindings = Bindings::default();
+        let mut configured = vec![];
+        for (name, chords) in keys {
+            let action = action_named(name)
+                .ok_or_else(|| DmitriError::Config(format!("Unknown key action: {}", name)))?;
+            bindings.chords.retain(|(_, bound)| *bound != action);
+            for chord in chords.iter() {
+                configured.push((chord.parse()?, action));
+            }
+        }
+        configured.append(&mut bindings.chords);
+        bindings.chords = configured;
+        Ok(bindings)
+    }
+
+    /// The action of the key, `None` for keys that type text. Keys that don't type text also
+    /// do what they do without Shift, unless the chord with Shift is bound.
+    pub fn action(&self, key: &Key) -> Option<Action> {
+        let chord = Chord::of(key);
+        let find = |chord: Chord| {
+            self.chords
+                .iter()
+                .find(|(bound, _)| *bound == chord)
+                .map(|(_, action)| *action)
+        };
+        find(chord).or_else(|| {
+            (chord.shift && key.text.is_empty())
+                .then(|| {
+                    find(Chord {
+                        shift: false,
+                        ..chord
+                    })
+                })
+                .flatten()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(keysym: u32, text: &str, shift: bool, ctrl: bool) -> Key {
+        Key {
+            keysym,
+            text: text.to_string(),
+            shift,
+            ctrl,
+            alt: false,
+        }
+    }
+
+    #[test]
+    fn parses_chords() {
+        let chord = |s: &str| s.parse::<Chord>().ok();
+        assert!(chord("Ctrl+n") == Some(Chord::new(keysyms::KEY_n, false, true)));
+        assert!(chord("shift+return") == Some(Chord::new(keysyms::KEY_Return, true, false)));
+        assert!(chord("Control+Shift+Tab") == Some(Chord::new(keysyms::KEY_Tab, true, true)));
+        assert!(chord("+") == Some(Chord::new(keysyms::KEY_plus, false, false)));
+        assert!(chord("Ctrl++") == Some(Chord::new(keysyms::KEY_plus, false, true)));
+        assert!(chord("Hyper+x").is_none());
+        assert!(chord("Ctrl+nothing").is_none());
+    }
+
+    #[test]
+    fn default_actions() {
+        let bindings = Bindings::default();
+        let action = |key: Key| bindings.action(&key);
+        assert!(action(key(keysyms::KEY_Return, "\r", false, false)) == Some(Action::Confirm));
+        assert!(action(key(keysyms::KEY_N, "", true, true)) == Some(Action::Next));
+        assert!(action(key(keysyms::KEY_ISO_Left_Tab, "", true, false)) == Some(Action::Prev));
+        // Shift doesn't change keys that type nothing, but does those that type
+        assert!(action(key(keysyms::KEY_Down, "", true, false)) == Some(Action::Next));
+        assert!(action(key(keysyms::KEY_A, "A", true, false)).is_none());
+        let mut alt_1 = key(keysyms::KEY_1, "1", false, false);
+        alt_1.alt = true;
+        assert!(action(alt_1) == Some(Action::Pick(1)));
+    }
+
+    #[test]
+    fn configured_chords_replace_the_defaults() {
+        let keys = HashMap::from([(
+            String::from("next"),
+            Chords::Many(vec![String::from("Ctrl+j"), String::from("Tab")]),
+        )]);
+        let bindings = Bindings::new(&keys).unwrap();
+        let action = |key: Key| bindings.action(&key);
+        assert!(action(key(keysyms::KEY_j, "", false, true)) == Some(Action::Next));
+        assert!(action(key(keysyms::KEY_Tab, "\t", false, false)) == Some(Action::Next));
+        assert!(action(key(keysyms::KEY_Down, "", false, false)).is_none());
+        let unknown = HashMap::from([(String::from("fly"), Chords::One(String::from("F1")))]);
+        assert!(Bindings::new(&unknown).is_err());
+    }
+}
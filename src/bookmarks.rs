@@ -0,0 +1,245 @@
+use crate::{
+    config::config_home,
+    error::DmitriError,
+    items::{spawn_detached, Item, ItemSink, ItemSource},
+    recent::encode,
+};
+use rusqlite::{Connection, OpenFlags};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// The directories of Firefox and its forks that have a `profiles.ini`, below the home directory.
+const FIREFOX_DIRS: [&str; 2] = [".mozilla/firefox", ".librewolf"];
+
+/// The directories of Chromium and the browsers based on it, below the config directory, with a
+/// directory for each profile.
+const CHROMIUM_DIRS: [&str; 7] = [
+    "chromium",
+    "google-chrome",
+    "google-chrome-beta",
+    "google-chrome-unstable",
+    "BraveSoftware/Brave-Browser",
+    "vivaldi",
+    "microsoft-edge",
+];
+
+/// A bookmark, with the name of the folder that it is in.
+#[derive(Clone)]
+struct Bookmark {
+    title: String,
+    url: String,
+    folder: String,
+}
+
+/// The bookmarks of every profile of Firefox and of Chromium, shown by their titles and found by
+/// their URLs and folders too. Firefox bookmarks come first, the most visited first, and then
+/// Chromium ones in the order of their folders. A URL bookmarked twice is only listed once. The
+/// selected one opens with the `browser` command line, or else with `xdg-open`.
+///
+/// The files are only read: the places database of Firefox is opened as immutable, since Firefox
+/// locks it while it runs.
+#[derive(Clone)]
+pub struct Bookmarks {
+    bookmarks: Vec<Bookmark>,
+    browser: Option<String>,
+}
+
+impl Bookmarks {
+    pub fn load(browser: Option<String>) -> Bookmarks {
+        let mut bookmarks = vec![];
+        for path in firefox_places() {
+            match read_firefox(&path) {
+                Ok(found) => bookmarks.extend(found),
+                Err(err) => eprintln!("Could not read {}: {}", path.display(), err),
+            }
+        }
+        for path in chromium_bookmarks() {
+            match read_chromium(&path) {
+                Ok(found) => bookmarks.extend(found),
+                Err(err) => eprintln!("Could not read {}: {}", path.display(), err),
+            }
+        }
+        let mut urls = HashSet::new();
+        bookmarks.retain(|bookmark| urls.insert(bookmark.url.clone()));
+        Bookmarks { bookmarks, browser }
+    }
+}
+
+impl ItemSource for Bookmarks {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        Ok(self
+            .bookmarks
+            .iter()
+            .map(|bookmark| {
+                let title = if bookmark.title.is_empty() {
+                    bookmark.url.clone()
+                } else {
+                    bookmark.title.clone()
+                };
+                Item::with_value(title, bookmark.url.clone())
+                    .with_description(Some(bookmark.url.clone()))
+                    .with_keywords(vec![bookmark.url.clone(), bookmark.folder.clone()])
+            })
+            .collect())
+    }
+}
+
+impl ItemSink for Bookmarks {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        let mut argv: Vec<String> = match &self.browser {
+            Some(browser) if !browser.trim().is_empty() => {
+                browser.split_whitespace().map(String::from).collect()
+            }
+            _ => vec![String::from("xdg-open")],
+        };
+        argv.push(selection);
+        let mut command = process::Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        spawn_detached(&mut command).map_err(|source| DmitriError::Spawn {
+            command: argv.join(" "),
+            source,
+        })
+    }
+}
+
+/// The `places.sqlite` of each profile of `profiles.ini` in the Firefox directories.
+fn firefox_places() -> Vec<PathBuf> {
+    let home = match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home),
+        None => return vec![],
+    };
+    let mut places = vec![];
+    for dir in FIREFOX_DIRS.iter().map(|dir| home.join(dir)) {
+        let ini = match fs::read_to_string(dir.join("profiles.ini")) {
+            Ok(ini) => ini,
+            Err(_) => continue,
+        };
+        // each section of a profile has its Path, relative to the directory unless IsRelative=0
+        let mut profiles: Vec<(Option<&str>, bool)> = vec![];
+        for line in ini.lines().map(str::trim) {
+            if line.starts_with('[') {
+                profiles.push((None, true));
+            } else if let Some(profile) = profiles.last_mut() {
+                if let Some(path) = line.strip_prefix("Path=") {
+                    profile.0 = Some(path);
+                } else if line == "IsRelative=0" {
+                    profile.1 = false;
+                }
+            }
+        }
+        for (path, relative) in profiles {
+            let profile = match path {
+                Some(path) if relative => dir.join(path),
+                Some(path) => PathBuf::from(path),
+                None => continue,
+            };
+            let path = profile.join("places.sqlite");
+            if path.is_file() && !places.contains(&path) {
+                places.push(path);
+            }
+        }
+    }
+    places
+}
+
+/// The bookmarks of a Firefox places database, the most visited first. The `place:` URLs of
+/// smart folders are left out.
+fn read_firefox(path: &Path) -> Result<Vec<Bookmark>, rusqlite::Error> {
+    let uri = format!("file:{}?immutable=1", encode(&path.to_string_lossy()));
+    let connection = Connection::open_with_flags(
+        uri,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+    )?;
+    let mut statement = connection.prepare(
+        "SELECT bookmark.title, place.url, folder.title
+         FROM moz_bookmarks AS bookmark
+         JOIN moz_places AS place ON place.id = bookmark.fk
+         LEFT JOIN moz_bookmarks AS folder ON folder.id = bookmark.parent
+         WHERE bookmark.type = 1 AND place.url NOT LIKE 'place:%'
+         ORDER BY place.frecency DESC",
+    )?;
+    let rows = statement.query_map([], |row| {
+        Ok(Bookmark {
+            title: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+            url: row.get(1)?,
+            folder: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+        })
+    })?;
+    rows.collect()
+}
+
+/// The `Bookmarks` file of each profile directory in the Chromium directories.
+fn chromium_bookmarks() -> Vec<PathBuf> {
+    let config_home = match config_home() {
+        Some(dir) => dir,
+        None => return vec![],
+    };
+    let mut files = vec![];
+    for dir in CHROMIUM_DIRS.iter().map(|dir| config_home.join(dir)) {
+        let mut profiles: Vec<PathBuf> = match fs::read_dir(&dir) {
+            Ok(read_dir) => read_dir.flatten().map(|entry| entry.path()).collect(),
+            Err(_) => continue,
+        };
+        // "Default" before "Profile 1" and so on
+        profiles.sort();
+        files.extend(
+            profiles
+                .into_iter()
+                .map(|profile| profile.join("Bookmarks"))
+                .filter(|path| path.is_file()),
+        );
+    }
+    files
+}
+
+#[derive(Deserialize)]
+struct ChromiumFile {
+    roots: ChromiumRoots,
+}
+
+#[derive(Deserialize)]
+struct ChromiumRoots {
+    bookmark_bar: Option<ChromiumNode>,
+    other: Option<ChromiumNode>,
+    synced: Option<ChromiumNode>,
+}
+
+/// A bookmark, or a folder of them.
+#[derive(Deserialize)]
+struct ChromiumNode {
+    #[serde(default)]
+    name: String,
+    url: Option<String>,
+    #[serde(default)]
+    children: Vec<ChromiumNode>,
+}
+
+/// The bookmarks of a Chromium `Bookmarks` file, in the order of the bookmarks bar, the other
+/// bookmarks and the mobile ones.
+fn read_chromium(path: &Path) -> io::Result<Vec<Bookmark>> {
+    let file: ChromiumFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let mut bookmarks = vec![];
+    let roots = [file.roots.bookmark_bar, file.roots.other, file.roots.synced];
+    for root in roots.iter().flatten() {
+        collect_chromium(root, &mut bookmarks);
+    }
+    Ok(bookmarks)
+}
+
+fn collect_chromium(folder: &ChromiumNode, bookmarks: &mut Vec<Bookmark>) {
+    for node in &folder.children {
+        match &node.url {
+            Some(url) => bookmarks.push(Bookmark {
+                title: node.name.clone(),
+                url: url.clone(),
+                folder: folder.name.clone(),
+            }),
+            None => collect_chromium(node, bookmarks),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{iter::Peekable, str::Chars};
+
+/// Evaluate the input if it is an arithmetic expression of numbers with `+`, `-`, `*`, `/`, `%`,
+/// `^` and parentheses. Plain numbers are not considered a calculation, so that typing one
+/// doesn't shadow the items.
+pub fn eval(input: &str) -> Option<String> {
+    let mut parser = Parser {
+        chars: input.chars().peekable(),
+        operators: 0,
+    };
+    let value = parser.expr()?;
+    parser.skip_whitespace();
+    if parser.chars.next().is_some() || parser.operators == 0 || !value.is_finite() {
+        return None;
+    }
+    Some(format_number(value))
+}
+
+/// Integers without a fraction, anything else rounded to 10 decimals without trailing zeros.
+fn format_number(value: f64) -> String {
+    if value.fract() == 0. && value.abs() < 1e15 {
+        return format!("{}", value as i64);
+    }
+    let formatted = format!("{:.10}", value);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    match formatted {
+        "-0" => String::from("0"),
+        _ => formatted.to_string(),
+    }
+}
+
+/// Recursive descent over the usual precedence: `+ -`, then `* / %`, then unary minus, then `^`
+/// which is right associative.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// Binary operators seen so far.
+    operators: usize,
+}
+
+impl Parser<'_> {
+    fn expr(&mut self) -> Option<f64> {
+        let mut value = self.term()?;
+        loop {
+            match self.peek() {
+                Some('+') => value += self.operand(Parser::term)?,
+                Some('-') => value -= self.operand(Parser::term)?,
+                _ => return Some(value),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Option<f64> {
+        let mut value = self.unary()?;
+        loop {
+            match self.peek() {
+                Some('*') => value *= self.operand(Parser::unary)?,
+                Some('/') => value /= self.operand(Parser::unary)?,
+                Some('%') => value %= self.operand(Parser::unary)?,
+                _ => return Some(value),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Option<f64> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Some(-self.unary()?)
+            }
+            Some('+') => {
+                self.chars.next();
+                self.unary()
+            }
+            _ => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Option<f64> {
+        let base = self.atom()?;
+        match self.peek() {
+            Some('^') => Some(base.powf(self.operand(Parser::unary)?)),
+            _ => Some(base),
+        }
+    }
+
+    fn atom(&mut self) -> Option<f64> {
+        match self.peek()? {
+            '(' => {
+                self.chars.next();
+                let value = self.expr()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Some(value)
+                    }
+                    _ => None,
+                }
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut number = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if !c.is_ascii_digit() && c != '.' {
+                        break;
+                    }
+                    number.push(c);
+                    self.chars.next();
+                }
+                number.parse().ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Consume the binary operator and parse its right hand side.
+    fn operand(&mut self, parse: fn(&mut Self) -> Option<f64>) -> Option<f64> {
+        self.chars.next();
+        self.operators += 1;
+        parse(self)
+    }
+
+    /// The next character that isn't whitespace.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("12*37+5").as_deref(), Some("449"));
+        assert_eq!(eval("(1 + 2) * 3").as_deref(), Some("9"));
+        assert_eq!(eval("2^3^2").as_deref(), Some("512"));
+        assert_eq!(eval("-2^2").as_deref(), Some("-4"));
+        assert_eq!(eval("7 % 4 - 1").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn fractions() {
+        assert_eq!(eval("7/2").as_deref(), Some("3.5"));
+        assert_eq!(eval("0.1+0.2").as_deref(), Some("0.3"));
+        assert_eq!(eval("1/3").as_deref(), Some("0.3333333333"));
+        assert_eq!(eval("-0.00000000001*1").as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn not_calculations() {
+        assert_eq!(eval("42"), None);
+        assert_eq!(eval("-42"), None);
+        assert_eq!(eval("2+"), None);
+        assert_eq!(eval("2+2 apples"), None);
+        assert_eq!(eval("1/0"), None);
+        assert_eq!(eval("firefox"), None);
+    }
+}
@@ -0,0 +1,476 @@
+use clap::{parser::ValueSource, Arg, ArgAction, ArgMatches, Command};
+use dmitri::error::DmitriError;
+
+/// The subcommands, which each stand for the flag of their mode, and the options that only
+/// apply to them. `run` is the default mode, without a flag.
+const SUBCOMMANDS: [(&str, Option<&str>, &str); 5] = [
+    ("run", None, "run a command of $PATH, the default mode"),
+    (
+        "drun",
+        Some("drun"),
+        "launch applications from their .desktop entries",
+    ),
+    (
+        "pick",
+        Some("dmenu"),
+        "read items from stdin and print the selection to stdout, like -d",
+    ),
+    (
+        "window",
+        Some("window"),
+        "switch to an open window (X11 only)",
+    ),
+    (
+        "daemon",
+        Some("daemon"),
+        "stay in the background and show the window on every --show",
+    ),
+];
+
+/// The parsed command line. Options are looked up by the name of their long option, or by their
+/// letter if they have none, in the subcommand and then in the top level.
+pub struct Args {
+    matches: ArgMatches,
+    subcommand: Option<(String, ArgMatches)>,
+}
+
+impl Args {
+    /// The arguments of dmitri, or none if `--help` or `--version` was printed.
+    pub fn parse() -> Result<Option<Args>, DmitriError> {
+        let args = std::env::args().map(dmenu_flag);
+        let mut matches = match command().try_get_matches_from(args) {
+            Ok(matches) => matches,
+            Err(err) if !err.use_stderr() => {
+                err.print()?;
+                return Ok(None);
+            }
+            Err(err) => {
+                let message = err.render().to_string();
+                let message = message.trim_end();
+                return Err(DmitriError::Config(
+                    message
+                        .strip_prefix("error: ")
+                        .unwrap_or(message)
+                        .to_string(),
+                ));
+            }
+        };
+        let subcommand = matches.remove_subcommand();
+        Ok(Some(Args {
+            matches,
+            subcommand,
+        }))
+    }
+
+    /// The matches of the subcommand, then those of the top level.
+    fn all(&self) -> impl Iterator<Item = &ArgMatches> {
+        self.subcommand
+            .iter()
+            .map(|(_, matches)| matches)
+            .chain([&self.matches])
+    }
+
+    /// Whether the option was given, or the subcommand of its mode.
+    pub fn present(&self, id: &str) -> bool {
+        let subcommand = self.subcommand.as_ref().map(|(name, _)| name.as_str());
+        let mode = SUBCOMMANDS
+            .iter()
+            .find(|(name, ..)| Some(*name) == subcommand)
+            .and_then(|(_, mode, _)| *mode);
+        mode == Some(id)
+            || self.all().any(|matches| {
+                matches.try_contains_id(id).unwrap_or(false)
+                    && matches.value_source(id) == Some(ValueSource::CommandLine)
+            })
+    }
+
+    pub fn value(&self, id: &str) -> Option<String> {
+        self.all()
+            .find_map(|matches| matches.try_get_one::<String>(id).ok().flatten())
+            .cloned()
+    }
+
+    /// All values of an option that can be given several times.
+    pub fn values(&self, id: &str) -> Vec<String> {
+        self.all()
+            .find_map(|matches| matches.try_get_many::<String>(id).ok().flatten())
+            .map(|values| values.cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+fn flag(id: &'static str, help: &'static str) -> Arg {
+    Arg::new(id).long(id).help(help).action(ArgAction::SetTrue)
+}
+
+fn option(id: &'static str, value_name: &'static str, help: &'static str) -> Arg {
+    Arg::new(id).long(id).value_name(value_name).help(help)
+}
+
+/// Options like `--files` that may be given without a value, which then is `default`. A value
+/// is given with `=`, like `--files=~/src`.
+fn optional(
+    id: &'static str,
+    value_name: &'static str,
+    default: &'static str,
+    help: &'static str,
+) -> Arg {
+    option(id, value_name, help)
+        .num_args(0..=1)
+        .require_equals(true)
+        .default_missing_value(default)
+}
+
+/// The options of `pick`, which are also accepted with `-d`.
+fn pick_args() -> [Arg; 3] {
+    [
+        flag(
+            "json",
+            "like -d, but read items as JSON objects with a label and optionally a value",
+        ),
+        optional(
+            "delimiter",
+            "\\t",
+            "\t",
+            "with -d, split lines into shown text and printed value, at a tab by default",
+        ),
+        flag(
+            "multi",
+            "mark several matches with Ctrl+Enter and print them all, one per line",
+        ),
+    ]
+}
+
+/// The options of `run`, which are also accepted without a subcommand.
+fn run_args() -> [Arg; 1] {
+    [flag(
+        "terminal",
+        "run commands in a terminal emulator, as Shift+Enter does for one",
+    )]
+}
+
+/// The options of every mode, which are also accepted after a subcommand.
+fn global_args() -> Vec<Arg> {
+    vec![
+        option("fontname", "mono:bold", "set font as a fontconfig pattern").short('f'),
+        option("fontsize", "32", "set font size").short('s'),
+        option("margin", "7", "set margin").short('m'),
+        option("color", "#ff8800", "set color").short('c'),
+        option("normal-bg", "#000000", "set background color"),
+        option(
+            "normal-fg",
+            "#7f3f00",
+            "set color of the prompt and matches, half of --color by default",
+        ),
+        option(
+            "selected-bg",
+            "#ff8800",
+            "set background color of the selected match, same as --color by default",
+        ),
+        option(
+            "selected-fg",
+            "#000000",
+            "set text color of the selected match",
+        ),
+        option(
+            "description-fg",
+            "#3f1f00",
+            "set text color of the descriptions, between normal-fg and normal-bg by default",
+        ),
+        option("prompt", "Run:", "show a prompt before the input").short('p'),
+        option(
+            "input",
+            "TEXT",
+            "start with this input and search for it right away, also -it",
+        ),
+        option(
+            "precise-wheight",
+            "5.0",
+            "set additional wheight of subtext matching",
+        )
+        .short('w'),
+        option("max-matches", "20", "keep this many of the best matches"),
+        flag(
+            "tab-complete",
+            "Tab completes the input with the selected match or the common prefix of the matches",
+        ),
+        flag(
+            "hints",
+            "number the first nine matches, which Alt and the number runs",
+        ),
+        flag(
+            "counter",
+            "show the number of the selected match and of all matches at the right",
+        ),
+        flag(
+            "no-descriptions",
+            "hide the descriptions of items, like the comments of applications",
+        ),
+        flag(
+            "details",
+            "show details of the selected match at the end of its line, like where a command is",
+        ),
+        flag(
+            "icons",
+            "draw the icons of applications and items before them",
+        ),
+        option(
+            "icon-theme",
+            "Adwaita",
+            "look icons up in this theme before hicolor",
+        ),
+        option(
+            "matcher",
+            "fuzzy|fzf|prefix|substring",
+            "set the matching algorithm",
+        ),
+        option(
+            "case",
+            "smart|insensitive|sensitive",
+            "when to ignore case, smart ignores it unless the input has uppercase",
+        ),
+        Arg::new("i")
+            .short('i')
+            .help("ignore case, like --case insensitive")
+            .action(ArgAction::SetTrue),
+        option("lines", "0", "list matches vertically in this many lines").short('l'),
+        option(
+            "monitor",
+            "0",
+            "show on this monitor instead of the one with the pointer",
+        ),
+        flag("bottom", "place the bar at the bottom of the screen").short('b'),
+        option("x", "10%", "horizontal position, in pixels or percent").short('x'),
+        option("y", "0", "vertical position, in pixels or percent").short('y'),
+        option("width", "80%", "width, in pixels or percent"),
+        flag("only-match", "confirm only matches, not any other input"),
+        flag(
+            "auto-accept",
+            "confirm the only match as soon as the input narrows to it",
+        ),
+        option(
+            "timeout",
+            "10",
+            "close the window without a selection after this many seconds without input",
+        ),
+        option(
+            "terminal-command",
+            "\"alacritty -e {cmd}\"",
+            "run commands in a terminal with this command line, where {cmd} is the command",
+        ),
+        flag(
+            "print",
+            "print the selection to stdout instead of running it, in any mode",
+        ),
+        option(
+            "modes",
+            "run,drun,window,ssh,emoji,appimage,power",
+            "switch between these modes with Ctrl+Tab",
+        ),
+        option(
+            "backend",
+            "x11|wayland",
+            "display server to use, wayland is the default when WAYLAND_DISPLAY is set",
+        ),
+        flag(
+            "xrender",
+            "draw text on the X server with the RENDER extension",
+        ),
+        option(
+            "config",
+            "~/.config/dmitri/config.toml",
+            "read settings from this file",
+        ),
+        option(
+            "filter",
+            "'!*.bak'",
+            "list only commands and applications matching this glob or /regex/, or hide them with !",
+        )
+        .action(ArgAction::Append),
+        option(
+            "env",
+            "GDK_BACKEND=wayland",
+            "set a variable in the environment of launched programs",
+        )
+        .action(ArgAction::Append),
+        option(
+            "unset-env",
+            "NAME",
+            "remove a variable from the environment of launched programs",
+        )
+        .action(ArgAction::Append),
+        flag(
+            "clean-env",
+            "launch programs with only the variables of the session",
+        ),
+        option(
+            "working-dir",
+            "~/src",
+            "start launched programs in this directory",
+        ),
+        flag(
+            "systemd-scope",
+            "launch each program in a systemd scope of its own",
+        ),
+        flag(
+            "notify-failures",
+            "show a notification when a launched program fails right away",
+        ),
+        flag(
+            "wait",
+            "wait for the launched program to exit, and exit with its status",
+        ),
+    ]
+}
+
+/// The flags of the modes, which only the top level takes, as the subcommands are modes.
+fn mode_args() -> Vec<Arg> {
+    vec![
+        flag(
+            "dmenu",
+            "read items from stdin and print the selection to stdout",
+        )
+        .short('d'),
+        flag(
+            "password",
+            "hide the input and print it to stdout, without any items",
+        )
+        .short('P'),
+        option(
+            "echo-char",
+            "*",
+            "show this character for each typed one with -P",
+        ),
+        option(
+            "confirm",
+            "\"Really shut down?\"",
+            "ask this question with yes and no, and exit with 0 only for yes",
+        ),
+        flag("drun", "launch applications from their .desktop entries"),
+        optional(
+            "files",
+            "ROOT",
+            ".",
+            "open a file found below ROOT, the current directory by default",
+        ),
+        flag(
+            "recent",
+            "open a recently used document with the application that used it",
+        ),
+        flag("bookmarks", "open a bookmark of Firefox or Chromium"),
+        flag(
+            "pass",
+            "copy a password of pass(1) to the clipboard, cleared after a while",
+        ),
+        flag(
+            "pass-type",
+            "with --pass, type the password into the focused window instead",
+        ),
+        flag("man", "open a man page in a terminal"),
+        flag(
+            "systemd",
+            "start or stop a service, or restart it with Shift+Enter",
+        ),
+        flag(
+            "tmux",
+            "switch to a tmux session or window, or attach to it in a terminal",
+        ),
+        flag(
+            "appimage",
+            "launch AppImages from ~/Applications and the appimage-dirs of the config",
+        ),
+        option(
+            "menu",
+            "FILE",
+            "pick from a menu file of labels and the commands they run",
+        ),
+        flag(
+            "power",
+            "lock, log out, suspend, hibernate, reboot or power off, asking before ending the session",
+        ),
+        option(
+            "mode",
+            "name:command",
+            "list the lines that a script prints, and run it again with the selection for the next ones",
+        ),
+        flag("window", "switch to an open window (X11 only)"),
+        flag(
+            "emoji",
+            "copy an emoji or symbol, searched by its name, to the clipboard",
+        ),
+        flag(
+            "ssh",
+            "open an ssh session in a terminal to a host from ~/.ssh",
+        ),
+        flag(
+            "daemon",
+            "stay in the background and show the window on every --show",
+        ),
+        flag("show", "show the window of a running --daemon"),
+    ]
+}
+
+fn command() -> Command {
+    let subcommands = SUBCOMMANDS.iter().map(|(name, _, about)| {
+        let command = Command::new(*name).about(*about);
+        match *name {
+            "run" => command.args(run_args()),
+            "pick" => command.args(pick_args()),
+            _ => command,
+        }
+    });
+    Command::new("dmitri")
+        .about("dmitri: a launcher")
+        .version(env!("CARGO_PKG_VERSION"))
+        .long_version(long_version())
+        .args(
+            global_args()
+                .into_iter()
+                .map(|arg| arg.global(true).help_heading("Common options")),
+        )
+        .args(mode_args().into_iter().map(|arg| arg.help_heading("Modes")))
+        .args(pick_args())
+        .args(run_args())
+        .subcommands(subcommands)
+}
+
+/// The version with what the binary was built from and with, for bug reports.
+fn long_version() -> String {
+    let mut features = vec![];
+    if cfg!(feature = "wayland") {
+        features.push("wayland");
+    }
+    if cfg!(feature = "dbus") {
+        features.push("dbus");
+    }
+    format!(
+        "{}\ncommit: {}\ntarget: {}-{}\nprofile: {}\nfeatures: {}",
+        env!("CARGO_PKG_VERSION"),
+        option_env!("DMITRI_COMMIT").unwrap_or("unknown"),
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        },
+        if features.is_empty() {
+            String::from("none")
+        } else {
+            features.join(", ")
+        },
+    )
+}
+
+/// dmenu's options that are a dash and several letters, as the long options of dmitri.
+fn dmenu_flag(arg: String) -> String {
+    match arg.as_str() {
+        "-nb" => "--normal-bg".to_string(),
+        "-nf" => "--normal-fg".to_string(),
+        "-sb" => "--selected-bg".to_string(),
+        "-sf" => "--selected-fg".to_string(),
+        "-fn" => "--fontname".to_string(),
+        "-it" => "--input".to_string(),
+        _ => arg,
+    }
+}
@@ -0,0 +1,192 @@
+use crate::error::DmitriError;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Settings read from `$XDG_CONFIG_HOME/dmitri/config.toml`. Keys are named like the long CLI
+/// options, and any flag given on the command line takes precedence over the file.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub fontname: Option<String>,
+    pub fontsize: Option<u16>,
+    pub color: Option<String>,
+    pub normal_bg: Option<String>,
+    pub normal_fg: Option<String>,
+    pub selected_bg: Option<String>,
+    pub selected_fg: Option<String>,
+    pub margin: Option<u16>,
+    pub precise_wheight: Option<f32>,
+    pub max_matches: Option<usize>,
+    pub tab_complete: Option<bool>,
+    pub hints: Option<bool>,
+    pub counter: Option<bool>,
+    pub icons: Option<bool>,
+    pub icon_theme: Option<String>,
+    pub descriptions: Option<bool>,
+    pub description_fg: Option<String>,
+    pub details: Option<bool>,
+    pub lines: Option<u16>,
+    pub prompt: Option<String>,
+    pub echo_char: Option<String>,
+    pub monitor: Option<usize>,
+    pub bottom: Option<bool>,
+    pub x: Option<String>,
+    pub y: Option<String>,
+    pub width: Option<String>,
+    pub backend: Option<String>,
+    pub xrender: Option<bool>,
+    pub matcher: Option<String>,
+    pub case: Option<String>,
+    pub terminal_command: Option<String>,
+    /// What commands start with to run as root, `#` by default, and the command line that runs
+    /// them, instead of `pkexec` or `sudo -A`.
+    pub root_prefix: Option<String>,
+    pub root_command: Option<String>,
+    /// Variables to set in the environment of launched programs, and to remove from it.
+    pub env: HashMap<String, String>,
+    pub unset_env: Vec<String>,
+    pub clean_env: Option<bool>,
+    pub working_dir: Option<String>,
+    pub systemd_scope: Option<bool>,
+    pub notify_failures: Option<bool>,
+    /// The command line that `--bookmarks` opens URLs with, instead of `xdg-open`.
+    pub browser: Option<String>,
+    /// Whether `--pass` types the password instead of copying it.
+    pub pass_type: Option<bool>,
+    /// Directories with AppImages besides `~/Applications`.
+    pub appimage_dirs: Vec<String>,
+    /// How many directories deep `--files` looks for files, and how many it finds at most.
+    pub files_max_depth: Option<usize>,
+    pub files_max_count: Option<usize>,
+    /// Shell commands of the actions of `--power` by name, replacing the default ones.
+    pub power: HashMap<String, String>,
+    /// URL templates of web searches by their bangs, added to the default ones.
+    pub bangs: HashMap<String, String>,
+    /// Shell commands of `--run` by short names that are listed with the executables.
+    pub aliases: HashMap<String, String>,
+    /// Patterns of the commands and applications to list only, and of those to leave out, see
+    /// `Filter`.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Key chords by action, replacing the default ones of each action.
+    pub keys: HashMap<String, Chords>,
+}
+
+/// One key chord or a list of them, like `"Ctrl+n"` or `["Down", "Ctrl+n"]`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Chords {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Chords {
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        let chords = match self {
+            Chords::One(chord) => std::slice::from_ref(chord),
+            Chords::Many(chords) => chords.as_slice(),
+        };
+        chords.iter().map(String::as_str)
+    }
+}
+
+impl Config {
+    /// Load the config from `path`, or from the default location. A missing file at the default
+    /// location is not an error.
+    pub fn load(path: Option<&str>) -> Result<Config, DmitriError> {
+        let (path, required) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Config::default())
+            }
+            Err(err) => return Err(DmitriError::Config(format!("{}: {}", path.display(), err))),
+        };
+        toml::from_str(&contents)
+            .map_err(|err| DmitriError::Config(format!("{}: {}", path.display(), err)))
+    }
+
+    /// Fill in the settings that are not set yet from X resources, in the `RESOURCE_MANAGER`
+    /// format of `xrdb -query`, like `dmitri.background: #282828`. Values that don't parse are
+    /// ignored, as are resources of other programs.
+    pub fn with_xresources(mut self, resources: &str) -> Config {
+        for line in resources.lines() {
+            let (name, value) = match line.split_once(':') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+            let name = match name
+                .strip_prefix("dmitri.")
+                .or_else(|| name.strip_prefix("dmitri*"))
+            {
+                Some(name) => name,
+                None => continue,
+            };
+            let value = Some(value.to_string());
+            match name {
+                "font" => self.fontname = self.fontname.or(value),
+                "fontsize" => self.fontsize = self.fontsize.or(value.and_then(|v| v.parse().ok())),
+                "color" => self.color = self.color.or(value),
+                "background" => self.normal_bg = self.normal_bg.or(value),
+                "foreground" => self.normal_fg = self.normal_fg.or(value),
+                "selbackground" => self.selected_bg = self.selected_bg.or(value),
+                "selforeground" => self.selected_fg = self.selected_fg.or(value),
+                "margin" => self.margin = self.margin.or(value.and_then(|v| v.parse().ok())),
+                "lines" => self.lines = self.lines.or(value.and_then(|v| v.parse().ok())),
+                "prompt" => self.prompt = self.prompt.or(value),
+                _ => (),
+            }
+        }
+        self
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    Some(config_home()?.join("dmitri").join("config.toml"))
+}
+
+/// `$XDG_CONFIG_HOME`, or `~/.config` if it's not set.
+pub(crate) fn config_home() -> Option<PathBuf> {
+    match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(Path::new(&env::var("HOME").ok()?).join(".config")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_from_xresources() {
+        let config = Config {
+            prompt: Some(String::from("run")),
+            ..Config::default()
+        }
+        .with_xresources(
+            "URxvt.font: xft:Mono\n\
+             dmitri.background:\t#282828\n\
+             dmitri*fontsize: 14\n\
+             dmitri.margin: wide\n\
+             dmitri.prompt: >\n\
+             dmitri.unknown: 1\n\
+             no colon here",
+        );
+        assert_eq!(config.normal_bg.as_deref(), Some("#282828"));
+        assert_eq!(config.fontsize, Some(14));
+        assert_eq!(config.margin, None);
+        assert_eq!(config.fontname, None);
+        // the config file takes precedence
+        assert_eq!(config.prompt.as_deref(), Some("run"));
+    }
+}
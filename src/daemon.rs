@@ -0,0 +1,86 @@
+use crate::error::DmitriError;
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+/// The unix socket that `dmitri --show` uses to wake up `dmitri --daemon`, in
+/// `$XDG_RUNTIME_DIR`, or the temp dir if that isn't set.
+pub fn socket_path() -> PathBuf {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("dmitri.sock"),
+        _ => env::temp_dir().join(format!(
+            "dmitri-{}.sock",
+            env::var("USER").unwrap_or_default()
+        )),
+    }
+}
+
+/// Listens for show requests of `dmitri --show`.
+pub struct Daemon {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Daemon {
+    /// Bind the socket. A socket file left behind by a daemon that didn't exit cleanly is
+    /// replaced, but a running daemon is an error.
+    pub fn listen() -> Result<Daemon, DmitriError> {
+        let path = socket_path();
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+                if UnixStream::connect(&path).is_ok() {
+                    return Err(DmitriError::Daemon(format!(
+                        "Already running, listening on {}",
+                        path.display()
+                    )));
+                }
+                fs::remove_file(&path)?;
+                UnixListener::bind(&path)?
+            }
+            Err(err) => return Err(DmitriError::Daemon(format!("{}: {}", path.display(), err))),
+        };
+        Ok(Daemon { listener, path })
+    }
+
+    /// Block until a client asks to show the window.
+    pub fn wait_for_show(&self) -> Result<(), DmitriError> {
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            let mut line = String::new();
+            if let Err(err) = BufReader::new(stream).read_line(&mut line) {
+                eprintln!("Could not read request: {}", err);
+                continue;
+            }
+            match line.trim_end() {
+                "show" => return Ok(()),
+                // another daemon checking whether this one is alive
+                "" => (),
+                request => eprintln!("Unknown request: {}", request),
+            }
+        }
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Ask a running daemon to show its window.
+pub fn show() -> Result<(), DmitriError> {
+    let path = socket_path();
+    let mut stream = UnixStream::connect(&path).map_err(|err| {
+        DmitriError::Daemon(format!(
+            "No daemon listening on {}: {}",
+            path.display(),
+            err
+        ))
+    })?;
+    stream.write_all(b"show\n")?;
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use std::collections::HashMap;
+use zbus::{blocking::Connection, zvariant::Value};
+
+/// Ask the application that owns `name` on the session bus to activate, which the bus starts it
+/// for if it isn't running, as for desktop entries with `DBusActivatable=true`. The startup
+/// notification ID is passed on for the application to end it.
+pub fn activate(name: &str, startup_id: Option<&str>) -> zbus::Result<()> {
+    let connection = Connection::session()?;
+    // the object path is the name with dots as slashes, and dashes as underscores
+    let path = format!("/{}", name.replace('.', "/").replace('-', "_"));
+    let mut platform_data: HashMap<&str, Value> = HashMap::new();
+    if let Some(startup_id) = startup_id {
+        platform_data.insert("desktop-startup-id", Value::from(startup_id));
+    }
+    connection.call_method(
+        Some(name),
+        path.as_str(),
+        Some("org.freedesktop.Application"),
+        "Activate",
+        &(platform_data,),
+    )?;
+    Ok(())
+}
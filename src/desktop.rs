@@ -0,0 +1,626 @@
+use crate::{
+    error::DmitriError,
+    filter::Filter,
+    items::{spawn_detached, Item, ItemSink, ItemSource, Terminal},
+    startup::{self, Launch},
+};
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+/// An application from a freedesktop `.desktop` file.
+#[derive(Clone, Debug)]
+pub struct DesktopEntry {
+    /// The desktop file ID, its path below the `applications` directory with dashes for
+    /// slashes, like `kde-konsole.desktop`.
+    pub id: String,
+    pub name: String,
+    /// The command line, which may be empty for applications that are activated over D-Bus.
+    pub exec: Vec<String>,
+    /// The name that the application is activated by over D-Bus, with `DBusActivatable=true`.
+    pub dbus_name: Option<String>,
+    pub terminal: bool,
+    pub working_dir: Option<String>,
+    /// The name of the icon in the icon theme, or its path.
+    pub icon: Option<String>,
+    /// What the application is, like `Web browser`, shown next to its name.
+    pub comment: Option<String>,
+    /// Its other names, its generic name and its keywords, which it is also found by.
+    pub keywords: Vec<String>,
+    /// Whether the application ends a startup notification when its window appears.
+    pub startup_notify: bool,
+    pub wm_class: Option<String>,
+    /// The desktop file.
+    pub path: PathBuf,
+}
+
+impl DesktopEntry {
+    /// Whether the filter keeps the application, by its name or its desktop file ID.
+    fn kept_by(&self, filter: &Filter) -> bool {
+        let id = self.id.strip_suffix(".desktop").unwrap_or(&self.id);
+        filter.keeps([self.name.as_str(), id])
+    }
+}
+
+/// Applications found in `$XDG_DATA_HOME/applications` and `$XDG_DATA_DIRS/applications` that
+/// the filter keeps, and the terminal that those with `Terminal=true` run in. The clones that are the source and the
+/// sink of the mode share the applications, as the source updates them.
+#[derive(Clone)]
+pub struct Desktop {
+    entries: Arc<RwLock<Vec<DesktopEntry>>>,
+    terminal: Terminal,
+    filter: Filter,
+}
+
+impl Desktop {
+    pub fn load(terminal: Terminal, filter: Filter) -> Desktop {
+        let mut ids: HashSet<String> = HashSet::new();
+        let mut entries: Vec<DesktopEntry> = vec![];
+        for dir in applications_dirs() {
+            scan_dir(&dir, "", &mut ids, &mut entries);
+        }
+        entries.retain(|entry| entry.kept_by(&filter));
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Desktop {
+            entries: Arc::new(RwLock::new(entries)),
+            terminal,
+            filter,
+        }
+    }
+
+    /// The applications by name, once for each name.
+    fn list(&self) -> Vec<Item> {
+        let mut entries = self
+            .entries
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        entries.dedup_by(|a, b| a.name == b.name);
+        entries
+            .into_iter()
+            .map(|e| {
+                Item::new(e.name)
+                    .with_icon(e.icon)
+                    .with_description(e.comment)
+                    .with_keywords(e.keywords)
+            })
+            .collect()
+    }
+
+    /// Start the application named `selection`, in the terminal if it asks for one or if
+    /// `in_terminal` is set.
+    fn launch(&self, selection: &str, in_terminal: bool) -> Result<(), DmitriError> {
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+        let entry = match entries.iter().find(|e| e.name == selection) {
+            Some(entry) => entry,
+            None => {
+                eprintln!("No application named {}", selection);
+                return Ok(());
+            }
+        };
+        let in_terminal = entry.terminal || in_terminal;
+        // without X11 there is nothing to notify
+        let startup_id = entry
+            .startup_notify
+            .then(|| {
+                let launch = Launch {
+                    name: &entry.name,
+                    bin: entry
+                        .exec
+                        .first()
+                        .or(entry.dbus_name.as_ref())
+                        .map_or("", String::as_str),
+                    icon: entry.icon.as_deref(),
+                    wm_class: entry.wm_class.as_deref(),
+                    application_id: &entry.path.to_string_lossy(),
+                };
+                startup::begin(&launch).ok()
+            })
+            .flatten();
+        #[cfg(feature = "dbus")]
+        if let Some(name) = entry.dbus_name.as_deref().filter(|_| !in_terminal) {
+            match crate::dbus::activate(name, startup_id.as_deref()) {
+                Ok(()) => return Ok(()),
+                Err(err) if !entry.exec.is_empty() => {
+                    eprintln!("Could not activate {}, running it instead: {}", name, err)
+                }
+                Err(err) => {
+                    return Err(DmitriError::Spawn {
+                        command: name.to_string(),
+                        source: std::io::Error::other(err),
+                    })
+                }
+            }
+        }
+        if entry.exec.is_empty() {
+            eprintln!("{} can only be activated over D-Bus", selection);
+            if let Some(id) = &startup_id {
+                let _ = startup::cancel(id);
+            }
+            return Ok(());
+        }
+        let argv = if in_terminal {
+            self.terminal.command(&entry.exec)
+        } else {
+            entry.exec.clone()
+        };
+        let mut command = process::Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        if let Some(dir) = &entry.working_dir {
+            command.current_dir(dir);
+        }
+        // the ID that dmitri itself may have been started with is not for the application
+        match &startup_id {
+            Some(id) => command.env("DESKTOP_STARTUP_ID", id),
+            None => command.env_remove("DESKTOP_STARTUP_ID"),
+        };
+        spawn_detached(&mut command).map_err(|source| {
+            if let Some(id) = &startup_id {
+                let _ = startup::cancel(id);
+            }
+            DmitriError::Spawn {
+                command: argv.join(" "),
+                source,
+            }
+        })
+    }
+}
+
+impl ItemSource for Desktop {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        Ok(self.list())
+    }
+
+    fn watched(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![];
+        for dir in applications_dirs() {
+            subdirs(dir, &mut dirs);
+        }
+        dirs
+    }
+
+    /// The desktop file of a changed one's ID is looked for again in all the directories, as
+    /// the first one found shadows the others.
+    fn update(&mut self, items: &mut Vec<Item>, changed: &[PathBuf]) -> bool {
+        let dirs = applications_dirs();
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        for file in changed {
+            let relative = match dirs.iter().find_map(|dir| file.strip_prefix(dir).ok()) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            if relative
+                .extension()
+                .and_then(|extension| extension.to_str())
+                != Some("desktop")
+            {
+                continue;
+            }
+            let id = relative.to_string_lossy().replace('/', "-");
+            entries.retain(|entry| entry.id != id);
+            let path = match dirs
+                .iter()
+                .map(|dir| dir.join(relative))
+                .find(|path| path.is_file())
+            {
+                Some(path) => path,
+                None => continue,
+            };
+            if let Some(entry) = fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| parse_entry(&contents, &path, &id))
+                .filter(|entry| entry.kept_by(&self.filter))
+            {
+                entries.push(entry);
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        drop(entries);
+        *items = self.list();
+        true
+    }
+}
+
+impl ItemSink for Desktop {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        self.launch(&selection, false)
+    }
+
+    fn output_in_terminal(&mut self, selection: String) -> Result<(), DmitriError> {
+        self.launch(&selection, true)
+    }
+}
+
+/// Data directories in order of precedence.
+pub(crate) fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = vec![];
+    match env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => dirs.push(PathBuf::from(dir)),
+        _ => {
+            if let Ok(home) = env::var("HOME") {
+                dirs.push(Path::new(&home).join(".local/share"));
+            }
+        }
+    }
+    let data_dirs = match env::var("XDG_DATA_DIRS") {
+        Ok(dirs) if !dirs.is_empty() => dirs,
+        _ => String::from("/usr/local/share:/usr/share"),
+    };
+    dirs.extend(data_dirs.split(':').map(PathBuf::from));
+    dirs
+}
+
+/// The `applications` directories of the data directories, in order of precedence.
+fn applications_dirs() -> Vec<PathBuf> {
+    data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// The directory and the directories below it.
+fn subdirs(dir: PathBuf, dirs: &mut Vec<PathBuf>) {
+    let read_dir = match fs::read_dir(&dir) {
+        Ok(read_dir) => read_dir,
+        Err(_) => return,
+    };
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            subdirs(path, dirs);
+        }
+    }
+    dirs.push(dir);
+}
+
+/// Desktop file IDs of subdirectories are prefixed with the directory name and a dash, and the
+/// first file found for an ID shadows all later ones.
+fn scan_dir(dir: &Path, prefix: &str, ids: &mut HashSet<String>, entries: &mut Vec<DesktopEntry>) {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(_) => return,
+    };
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        let filename = entry.file_name().to_string_lossy().to_string();
+        if path.is_dir() {
+            scan_dir(&path, &format!("{}{}-", prefix, filename), ids, entries);
+            continue;
+        }
+        if !filename.ends_with(".desktop") {
+            continue;
+        }
+        let id = format!("{}{}", prefix, filename);
+        if !ids.insert(id.clone()) {
+            continue;
+        }
+        if let Ok(contents) = fs::read_to_string(&path) {
+            if let Some(entry) = parse_entry(&contents, &path, &id) {
+                entries.push(entry);
+            }
+        }
+    }
+}
+
+fn parse_entry(contents: &str, path: &Path, id: &str) -> Option<DesktopEntry> {
+    let locales = locale_keys();
+    // the names in any of the `locales`, and the best localized values seen so far, ranked by
+    // their index into `locales`
+    let mut names: Vec<(usize, String)> = vec![];
+    let mut generic_name: Option<(usize, String)> = None;
+    let mut comment: Option<(usize, String)> = None;
+    let mut keywords: Option<(usize, String)> = None;
+    let mut exec: Option<String> = None;
+    let mut icon: Option<String> = None;
+    let mut terminal = false;
+    let mut working_dir: Option<String> = None;
+    let mut is_application = false;
+    let mut dbus_activatable = false;
+    let mut startup_notify = false;
+    let mut wm_class: Option<String> = None;
+    let mut hidden = false;
+    let mut only_show_in: Option<Vec<String>> = None;
+    let mut not_show_in: Vec<String> = vec![];
+
+    let mut in_main_group = false;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            in_main_group = line == "[Desktop Entry]";
+            continue;
+        }
+        if !in_main_group {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), unescape(value.trim())),
+            None => continue,
+        };
+        match key {
+            "Type" => is_application = value == "Application",
+            "Exec" => exec = Some(value),
+            "Icon" => icon = Some(value),
+            "Terminal" => terminal = value == "true",
+            "DBusActivatable" => dbus_activatable = value == "true",
+            "StartupNotify" => startup_notify = value == "true",
+            "StartupWMClass" => wm_class = Some(value),
+            "Path" if !value.is_empty() => working_dir = Some(value),
+            "NoDisplay" | "Hidden" => hidden |= value == "true",
+            "OnlyShowIn" => only_show_in = Some(split_list(&value)),
+            "NotShowIn" => not_show_in = split_list(&value),
+            _ => {
+                let (base, locale) = match key.split_once('[') {
+                    Some((base, locale)) => match locale.strip_suffix(']') {
+                        Some(locale) => (base, Some(locale)),
+                        None => continue,
+                    },
+                    None => (key, None),
+                };
+                let rank = match locale {
+                    None => locales.len(),
+                    Some(locale) => match locales.iter().position(|l| l == locale) {
+                        Some(rank) => rank,
+                        None => continue,
+                    },
+                };
+                let localized = match base {
+                    "Name" => {
+                        names.push((rank, value));
+                        continue;
+                    }
+                    "GenericName" => &mut generic_name,
+                    "Comment" => &mut comment,
+                    "Keywords" => &mut keywords,
+                    _ => continue,
+                };
+                if localized.as_ref().is_none_or(|(r, _)| rank < *r) {
+                    *localized = Some((rank, value));
+                }
+            }
+        }
+    }
+
+    if !is_application || hidden || !shown_in_current_desktop(only_show_in, &not_show_in) {
+        return None;
+    }
+    // the best name is shown, and the others are searched like the keywords
+    names.sort_by_key(|(rank, _)| *rank);
+    let mut names = names.into_iter().map(|(_, name)| name);
+    let name = names.next()?;
+    let mut other_names: Vec<String> = names.filter(|other| *other != name).collect();
+    other_names.dedup();
+    let keywords = other_names
+        .into_iter()
+        .chain(generic_name.map(|(_, generic_name)| generic_name))
+        .chain(keywords.map_or(vec![], |(_, keywords)| split_list(&keywords)))
+        .collect();
+    // the bus name is the desktop file ID, and `Exec=` is optional then
+    let dbus_name = (dbus_activatable && cfg!(feature = "dbus"))
+        .then(|| id.strip_suffix(".desktop").map(String::from))
+        .flatten();
+    let exec = exec.map_or(vec![], |exec| {
+        expand_field_codes(
+            split_exec(&exec),
+            &name,
+            icon.as_deref(),
+            &path.to_string_lossy(),
+        )
+    });
+    if exec.is_empty() && dbus_name.is_none() {
+        return None;
+    }
+    Some(DesktopEntry {
+        id: id.to_string(),
+        name,
+        exec,
+        dbus_name,
+        terminal,
+        working_dir,
+        icon,
+        comment: comment.map(|(_, comment)| comment),
+        keywords,
+        startup_notify,
+        wm_class,
+        path: path.to_path_buf(),
+    })
+}
+
+/// The values of a list separated by semicolons, like `OnlyShowIn=GNOME;KDE;`.
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Whether an entry with these `OnlyShowIn` and `NotShowIn` lists is shown in the desktop
+/// environments named in `$XDG_CURRENT_DESKTOP`.
+fn shown_in_current_desktop(only_show_in: Option<Vec<String>>, not_show_in: &[String]) -> bool {
+    let current = env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    let current: Vec<&str> = current.split(':').filter(|d| !d.is_empty()).collect();
+    let listed = |list: &[String]| current.iter().any(|d| list.iter().any(|l| l == d));
+    !listed(not_show_in) && only_show_in.is_none_or(|only_show_in| listed(&only_show_in))
+}
+
+/// Keys to look up localized values with, from most to least specific, derived from
+/// `lang_COUNTRY.ENCODING@MODIFIER`.
+fn locale_keys() -> Vec<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((locale, modifier)) => (locale, Some(modifier)),
+        None => (locale.as_str(), None),
+    };
+    let locale = locale.split('.').next().unwrap_or_default();
+    let (lang, country) = match locale.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (locale, None),
+    };
+    if lang.is_empty() || lang == "C" || lang == "POSIX" {
+        return vec![];
+    }
+
+    let mut keys: Vec<String> = vec![];
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        keys.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = country {
+        keys.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        keys.push(format!("{}@{}", lang, modifier));
+    }
+    keys.push(lang.to_string());
+    keys
+}
+
+/// Resolve the escape sequences of a string value.
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some(c) => {
+                // keep other escapes for the Exec quoting rules
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Split an Exec value into arguments, honoring double quotes.
+pub(crate) fn split_exec(exec: &str) -> Vec<String> {
+    let mut args: Vec<String> = vec![];
+    let mut arg: Option<String> = None;
+    let mut chars = exec.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                arg.get_or_insert_with(String::new);
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    arg.get_or_insert_with(String::new).push(c);
+                }
+            }
+            ' ' | '\t' if !quoted => {
+                if let Some(arg) = arg.take() {
+                    args.push(arg);
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(arg) = arg {
+        args.push(arg);
+    }
+    args
+}
+
+/// Replace or strip the `%` field codes. We never pass files or URLs, so those codes are removed.
+fn expand_field_codes(
+    args: Vec<String>,
+    name: &str,
+    icon: Option<&str>,
+    path: &str,
+) -> Vec<String> {
+    let mut expanded: Vec<String> = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "%f" | "%F" | "%u" | "%U" | "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => continue,
+            "%i" => {
+                if let Some(icon) = icon {
+                    expanded.push(String::from("--icon"));
+                    expanded.push(icon.to_string());
+                }
+                continue;
+            }
+            _ => (),
+        }
+        let mut result = String::with_capacity(arg.len());
+        let mut chars = arg.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('%') => result.push('%'),
+                Some('c') => result.push_str(name),
+                Some('k') => result.push_str(path),
+                _ => (),
+            }
+        }
+        expanded.push(result);
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_exec_values() {
+        assert_eq!(
+            split_exec(r#"env  "FOO=a b" app\ name --flag="x y" """#),
+            ["env", "FOO=a b", "app name", "--flag=x y", ""]
+        );
+        assert!(split_exec("   ").is_empty());
+    }
+
+    #[test]
+    fn expands_field_codes() {
+        let args = split_exec("app %U --name=%c --from %k %i 100%% %z");
+        assert_eq!(
+            expand_field_codes(args.clone(), "App", Some("app-icon"), "/apps/app.desktop"),
+            [
+                "app",
+                "--name=App",
+                "--from",
+                "/apps/app.desktop",
+                "--icon",
+                "app-icon",
+                "100%",
+                ""
+            ]
+        );
+        assert_eq!(
+            expand_field_codes(args, "App", None, "/apps/app.desktop"),
+            [
+                "app",
+                "--name=App",
+                "--from",
+                "/apps/app.desktop",
+                "100%",
+                ""
+            ]
+        );
+    }
+}
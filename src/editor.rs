@@ -0,0 +1,146 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The input line with a caret, edited like readline. The caret moves over, and deletes, whole
+/// grapheme clusters, so a letter and its combining marks are one character.
+#[derive(Default)]
+pub struct Editor {
+    text: String,
+    /// Byte offset of the caret in `text`, always on a grapheme cluster boundary.
+    caret: usize,
+}
+
+impl Editor {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn caret(&self) -> usize {
+        self.caret
+    }
+
+    /// Replace the text, with the caret at its end.
+    pub fn set(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.caret = self.text.len();
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        self.text.insert_str(self.caret, text);
+        self.caret += text.len();
+    }
+
+    pub fn backspace(&mut self) {
+        let start = self.prev_boundary();
+        self.text.replace_range(start..self.caret, "");
+        self.caret = start;
+    }
+
+    pub fn delete(&mut self) {
+        let end = self.next_boundary();
+        self.text.replace_range(self.caret..end, "");
+    }
+
+    pub fn left(&mut self) {
+        self.caret = self.prev_boundary();
+    }
+
+    pub fn right(&mut self) {
+        self.caret = self.next_boundary();
+    }
+
+    pub fn home(&mut self) {
+        self.caret = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.caret = self.text.len();
+    }
+
+    /// Delete the word before the caret, and any whitespace between it and the caret.
+    pub fn delete_word(&mut self) {
+        let before = self.text[..self.caret].trim_end();
+        let start = before
+            .rfind(char::is_whitespace)
+            .map(|i| i + before[i..].chars().next().map_or(1, char::len_utf8))
+            .unwrap_or(0);
+        self.text.replace_range(start..self.caret, "");
+        self.caret = start;
+    }
+
+    pub fn kill_to_start(&mut self) {
+        self.text.replace_range(..self.caret, "");
+        self.caret = 0;
+    }
+
+    pub fn kill_to_end(&mut self) {
+        self.text.truncate(self.caret);
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.text[..self.caret]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.caret..]
+            .graphemes(true)
+            .next()
+            .map_or(self.caret, |cluster| self.caret + cluster.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_at_the_caret() {
+        let mut editor = Editor::default();
+        editor.insert("fox");
+        editor.home();
+        editor.insert("fire");
+        assert_eq!(editor.text(), "firefox");
+        assert_eq!(editor.caret(), 4);
+        editor.delete();
+        editor.backspace();
+        assert_eq!(editor.text(), "firox");
+        editor.end();
+        editor.left();
+        editor.kill_to_end();
+        assert_eq!(editor.text(), "firo");
+        editor.kill_to_start();
+        assert_eq!((editor.text(), editor.caret()), ("", 0));
+    }
+
+    #[test]
+    fn moves_over_grapheme_clusters() {
+        let mut editor = Editor::default();
+        editor.set("cafe\u{301} 👨‍👩‍👧");
+        editor.backspace();
+        assert_eq!(editor.text(), "cafe\u{301} ");
+        editor.left();
+        editor.left();
+        assert_eq!(editor.caret(), 3);
+        editor.delete();
+        assert_eq!(editor.text(), "caf ");
+        // nothing to delete at the ends
+        editor.home();
+        editor.backspace();
+        editor.end();
+        editor.delete();
+        assert_eq!(editor.text(), "caf ");
+    }
+
+    #[test]
+    fn deletes_words() {
+        let mut editor = Editor::default();
+        editor.set("git commit  ");
+        editor.delete_word();
+        assert_eq!(editor.text(), "git ");
+        editor.set("ñandú");
+        editor.delete_word();
+        assert_eq!(editor.text(), "");
+    }
+}
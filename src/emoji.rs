@@ -0,0 +1,36 @@
+use crate::{
+    error::DmitriError,
+    items::{copy_to_clipboard, Item, ItemSink, ItemSource},
+};
+
+/// Emoji and other symbols with their lowercased Unicode names, one `<char>\t<name>` per line.
+/// Generated from the Unicode character database for the blocks of punctuation, currency,
+/// arrows, math, shapes, dingbats and emoji, plus Latin-1 and Greek letters.
+const UNICODE_NAMES: &str = include_str!("unicode.txt");
+
+/// Characters listed as `<char> <name>`, so that they are searched by name.
+pub struct EmojiSource;
+
+impl ItemSource for EmojiSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        Ok(UNICODE_NAMES
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(c, name)| Item::new(format!("{} {}", c, name)))
+            .collect())
+    }
+}
+
+/// Copies the selected character to the clipboard.
+pub struct EmojiSink;
+
+impl ItemSink for EmojiSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        // the typed input may also be confirmed, which is copied as is
+        let character = match selection.split_once(' ') {
+            Some((c, name)) if UNICODE_NAMES.contains(&format!("{}\t{}\n", c, name)) => c,
+            _ => &selection,
+        };
+        copy_to_clipboard(character)
+    }
+}
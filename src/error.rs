@@ -0,0 +1,81 @@
+use std::{error::Error, io};
+use thiserror::Error;
+
+/// Everything that can go wrong, by what the user can do about it.
+#[derive(Debug, Error)]
+pub enum DmitriError {
+    /// The display server can't be reached, or a request to it failed.
+    #[error("Display server: {0}")]
+    Connection(Box<dyn Error>),
+    /// The font can't be found or loaded.
+    #[error("Font: {0}")]
+    Font(String),
+    /// An invalid option, either on the command line or in the config file.
+    #[error("{0}")]
+    Config(String),
+    /// The selected program can't be started.
+    #[error("Could not run {command}: {source}")]
+    Spawn {
+        command: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The window image can't be set up for drawing.
+    #[error("Render: {0}")]
+    Render(String),
+    /// Another daemon is already running, or none is for `--show`.
+    #[error("{0}")]
+    Daemon(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl DmitriError {
+    /// The exit status for the error: 2 for invalid options like most programs, and its own for
+    /// each kind of failure that scripts may want to tell apart.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DmitriError::Config(_) => 2,
+            DmitriError::Connection(_) => 3,
+            DmitriError::Font(_) => 4,
+            DmitriError::Spawn { .. } => 5,
+            DmitriError::Render(_) => 6,
+            DmitriError::Daemon(_) | DmitriError::Io(_) => 1,
+        }
+    }
+}
+
+/// Errors of the display server libraries, which are all connection errors.
+macro_rules! connection_errors {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for DmitriError {
+                fn from(err: $error) -> DmitriError {
+                    DmitriError::Connection(Box::new(err))
+                }
+            }
+        )*
+    };
+}
+
+connection_errors!(breadx::Error);
+
+#[cfg(feature = "wayland")]
+connection_errors!(
+    wayland_client::ConnectError,
+    wayland_client::DispatchError,
+    wayland_client::backend::WaylandError,
+    wayland_client::globals::GlobalError,
+    wayland_client::globals::BindError,
+    smithay_client_toolkit::shm::CreatePoolError,
+    smithay_client_toolkit::shm::slot::CreateBufferError,
+    smithay_client_toolkit::shm::slot::ActivateSlotError,
+    smithay_client_toolkit::data_device_manager::data_offer::DataOfferError,
+);
+
+/// Image formats that don't fit the server's.
+impl From<x11rb::errors::ParseError> for DmitriError {
+    fn from(err: x11rb::errors::ParseError) -> DmitriError {
+        DmitriError::Render(err.to_string())
+    }
+}
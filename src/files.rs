@@ -0,0 +1,187 @@
+use crate::{
+    error::DmitriError,
+    gitignore::Gitignore,
+    items::{spawn_detached, DynamicSource, Item, ItemSink, ItemSource, ItemStream},
+};
+use std::{
+    collections::VecDeque,
+    env,
+    ffi::OsStr,
+    fs, mem,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How many files the walk finds before it streams them, unless it takes longer than
+/// `BATCH_TIME`.
+const BATCH_SIZE: usize = 1024;
+const BATCH_TIME: Duration = Duration::from_millis(50);
+
+/// Whether the input is a path, which starts with `/`, `~/` or `./`.
+pub fn is_path(input: &str) -> bool {
+    input.starts_with('/') || input.starts_with("~/") || input.starts_with("./")
+}
+
+/// The path with a leading `~/` in the home directory.
+pub fn expand(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(path), Some(home)) => Path::new(&home).join(path),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Open the file or URL with its default application, with `xdg-open`.
+pub fn open(target: impl AsRef<OsStr>) -> Result<(), DmitriError> {
+    let target = target.as_ref();
+    spawn_detached(process::Command::new("xdg-open").arg(target)).map_err(|source| {
+        DmitriError::Spawn {
+            command: format!("xdg-open {}", target.to_string_lossy()),
+            source,
+        }
+    })
+}
+
+/// Completes inputs that are paths with the entries of the directory up to their last `/`, like
+/// a shell. The entries are written as the input starts, and directories end with `/` so that
+/// their entries are listed next. Hidden entries are only listed once a `.` is typed after the
+/// `/`.
+pub struct PathCompletion;
+
+impl DynamicSource for PathCompletion {
+    fn items(&self, input: &str) -> Option<Vec<Item>> {
+        if !is_path(input) {
+            return None;
+        }
+        let (dir, name) = input.split_at(input.rfind('/')? + 1);
+        let hidden = name.starts_with('.');
+        let mut items = vec![];
+        for entry in fs::read_dir(expand(dir)).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') && !hidden {
+                continue;
+            }
+            // symlinks to directories are completed like directories
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            items.push(Item::new(format!("{}{}{}", dir, name, slash)));
+        }
+        items.sort_by(|a, b| a.display().cmp(b.display()));
+        Some(items)
+    }
+}
+
+/// The files below `root`, by their paths relative to it, which are output as their full paths.
+/// They are found by a walk in the background, shallow ones first, and streamed to the picker. It
+/// leaves out hidden files and those that a `.gitignore` ignores, doesn't follow symlinks to
+/// directories, and stops at `max_depth` directories deep or after `max_count` files.
+pub struct FileSource {
+    pub root: PathBuf,
+    pub max_depth: usize,
+    pub max_count: usize,
+}
+
+impl ItemSource for FileSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        Ok(vec![])
+    }
+
+    fn stream(&mut self) -> Option<ItemStream> {
+        let stream = ItemStream::default();
+        let walk = Walk {
+            root: self.root.clone(),
+            max_depth: self.max_depth,
+            max_count: self.max_count,
+            stream: stream.clone(),
+        };
+        thread::spawn(move || {
+            walk.run();
+            walk.stream.finish();
+        });
+        Some(stream)
+    }
+}
+
+struct Walk {
+    root: PathBuf,
+    max_depth: usize,
+    max_count: usize,
+    stream: ItemStream,
+}
+
+impl Walk {
+    fn run(&self) {
+        let mut found = 0;
+        let mut batch = vec![];
+        let mut streamed = Instant::now();
+        // directories with their depth and the `.gitignore` files above them, by their directory
+        let mut dirs = VecDeque::from([(self.root.clone(), 0, vec![])]);
+        while let Some((dir, depth, mut ignores)) = dirs.pop_front() {
+            if let Some(gitignore) = Gitignore::load(&dir) {
+                ignores.push(Arc::new((dir.clone(), gitignore)));
+            }
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                if name.to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let file_type = match entry.file_type() {
+                    Ok(file_type) => file_type,
+                    Err(_) => continue,
+                };
+                let path = entry.path();
+                if is_ignored(&ignores, &path, file_type.is_dir()) {
+                    continue;
+                }
+                if file_type.is_dir() {
+                    if depth + 1 < self.max_depth {
+                        dirs.push_back((path, depth + 1, ignores.clone()));
+                    }
+                    continue;
+                }
+                let relative = path.strip_prefix(&self.root).unwrap_or(&path);
+                batch.push(Item::with_value(
+                    relative.to_string_lossy().to_string(),
+                    path.to_string_lossy().to_string(),
+                ));
+                found += 1;
+                if found >= self.max_count {
+                    self.stream.extend(batch);
+                    return;
+                }
+                if batch.len() >= BATCH_SIZE || streamed.elapsed() >= BATCH_TIME {
+                    self.stream.extend(mem::take(&mut batch));
+                    streamed = Instant::now();
+                }
+            }
+        }
+        self.stream.extend(batch);
+    }
+}
+
+/// Whether the `.gitignore` files above the path ignore it, the deepest one deciding first.
+fn is_ignored(ignores: &[Arc<(PathBuf, Gitignore)>], path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|ignore| {
+            let (dir, gitignore) = ignore.as_ref();
+            let relative = path.strip_prefix(dir).ok()?.to_string_lossy();
+            gitignore.matched(&relative, is_dir)
+        })
+        .unwrap_or(false)
+}
+
+/// Opens the selected file with `xdg-open`.
+pub struct FileSink;
+
+impl ItemSink for FileSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        open(Path::new(&selection))
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{error::DmitriError, gitignore::glob};
+use regex::Regex;
+
+/// Which commands of `$PATH` and applications are listed: those that match any of the `include`
+/// patterns, if there are any, and none of the `exclude` ones. Commands are matched by their
+/// name, and applications by their name and their desktop file ID without `.desktop`, like
+/// `wine-Programs-Notepad`.
+#[derive(Clone, Default)]
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+/// A glob like `wine*`, or a regular expression between slashes like `/\.bak$/`.
+#[derive(Clone)]
+enum Pattern {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Filter, DmitriError> {
+        Ok(Filter {
+            include: include
+                .iter()
+                .map(|pattern| Pattern::parse(pattern))
+                .collect::<Result<_, _>>()?,
+            exclude: exclude
+                .iter()
+                .map(|pattern| Pattern::parse(pattern))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// The filter of `--filter` patterns, which exclude what they match when they start with `!`
+    /// and include it otherwise. Empty ones are left out.
+    pub fn from_args(patterns: &[String]) -> Result<Filter, DmitriError> {
+        let (exclude, include): (Vec<String>, Vec<String>) = patterns
+            .iter()
+            .filter(|pattern| !pattern.is_empty())
+            .cloned()
+            .partition(|pattern| pattern.starts_with('!'));
+        let exclude: Vec<String> = exclude
+            .into_iter()
+            .map(|pattern| pattern[1..].to_string())
+            .collect();
+        Filter::new(&include, &exclude)
+    }
+
+    /// Whether an item that is known by these names is listed.
+    pub fn keeps<'a>(&self, names: impl IntoIterator<Item = &'a str> + Clone) -> bool {
+        let matches =
+            |pattern: &Pattern| names.clone().into_iter().any(|name| pattern.matches(name));
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Pattern, DmitriError> {
+        match pattern
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            Some(regex) => Regex::new(regex)
+                .map(Pattern::Regex)
+                .map_err(|err| DmitriError::Config(format!("Invalid filter {}: {}", pattern, err))),
+            None => Ok(Pattern::Glob(pattern.to_string())),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            Pattern::Glob(pattern) => glob(pattern.as_bytes(), name.as_bytes()),
+            Pattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
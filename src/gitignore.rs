@@ -0,0 +1,126 @@
+use std::{fs, path::Path};
+
+/// The patterns of a `.gitignore` file, which apply to the paths below its directory. The last
+/// pattern that matches a path decides, and `!` patterns include what others excluded.
+pub struct Gitignore {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    pattern: String,
+    negated: bool,
+    /// Only matches directories, when the pattern ends with `/`.
+    dir_only: bool,
+    /// Matches the whole path from the directory of the file, when the pattern has a `/` before
+    /// its end, instead of the name in any directory.
+    anchored: bool,
+}
+
+impl Gitignore {
+    /// The `.gitignore` file in the directory, if there is one.
+    pub fn load(dir: &Path) -> Option<Gitignore> {
+        let contents = fs::read_to_string(dir.join(".gitignore")).ok()?;
+        Some(Gitignore::parse(&contents))
+    }
+
+    pub fn parse(contents: &str) -> Gitignore {
+        let rules = contents
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let (dir_only, pattern) = match pattern.strip_suffix('/') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, pattern),
+                };
+                let anchored = pattern.contains('/');
+                Some(Rule {
+                    pattern: pattern.trim_start_matches('/').to_string(),
+                    negated,
+                    dir_only,
+                    anchored,
+                })
+            })
+            .collect();
+        Gitignore { rules }
+    }
+
+    /// Whether the path, relative to the directory of the file, is ignored, or `None` if no
+    /// pattern matches it.
+    pub fn matched(&self, relative: &str, is_dir: bool) -> Option<bool> {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only)
+                    && glob(
+                        rule.pattern.as_bytes(),
+                        if rule.anchored { relative } else { name }.as_bytes(),
+                    )
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Whether the text matches the glob pattern, where `*` and `?` don't match `/`, `**` matches
+/// any number of directories, and `[...]` is a class of characters.
+pub(crate) fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+            rest.is_empty()
+                || (0..=text.len())
+                    .filter(|&i| i == 0 || text[i - 1] == b'/')
+                    .any(|i| glob(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => {
+            let end = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=end).any(|i| glob(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [c, text @ ..] if *c != b'/' && glob(rest, text)),
+        [b'[', class @ ..] => {
+            // a `]` right after the `[` is part of the class
+            match class.iter().skip(1).position(|&c| c == b']') {
+                Some(end) => {
+                    let (class, rest) = (&class[..end + 1], &class[end + 2..]);
+                    matches!(text, [c, text @ ..]
+                        if *c != b'/' && in_class(class, *c) && glob(rest, text))
+                }
+                None => matches!(text, [b'[', text @ ..] if glob(class, text)),
+            }
+        }
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => {
+            matches!(text, [t, text @ ..] if t == c && glob(rest, text))
+        }
+    }
+}
+
+/// Whether the character is in a class like `abc`, `a-z` or `!0-9`.
+fn in_class(class: &[u8], c: u8) -> bool {
+    let (negated, mut class) = match class {
+        [b'!' | b'^', class @ ..] => (true, class),
+        class => (false, class),
+    };
+    let mut found = false;
+    while let [first, rest @ ..] = class {
+        match rest {
+            [b'-', last, rest @ ..] => {
+                found |= (*first..=*last).contains(&c);
+                class = rest;
+            }
+            _ => {
+                found |= *first == c;
+                class = rest;
+            }
+        }
+    }
+    found != negated
+}
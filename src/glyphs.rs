@@ -0,0 +1,291 @@
+use crate::text::Color;
+use rusttype::{point, Font, GlyphId, Point, Rect, Scale, ScaledGlyph};
+use std::{cmp::Ordering, io::Cursor};
+use ttf_parser::{Face, RawFace, Tag};
+
+/// A glyph's coverage of each pixel, with its top left corner relative to the pixel that the
+/// glyph's origin is in.
+pub struct Coverage {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub values: Vec<f32>,
+}
+
+impl Coverage {
+    /// Rasterize the glyph with its origin at `offset` within a pixel. `None` for glyphs without
+    /// an outline, like spaces.
+    pub fn rasterize(glyph: ScaledGlyph<'_>, offset: Point<f32>) -> Option<Coverage> {
+        let glyph = glyph.positioned(offset);
+        let bounds = glyph.pixel_bounding_box()?;
+        let width = bounds.width() as u32;
+        let mut values = vec![0.0; (width * bounds.height() as u32) as usize];
+        glyph.draw(|x, y, v| values[(y * width + x) as usize] = v);
+        Some(Coverage {
+            x: bounds.min.x,
+            y: bounds.min.y,
+            width,
+            values,
+        })
+    }
+}
+
+/// The color glyphs of a font, as bitmaps in its CBDT or sbix tables or as layers in its COLR
+/// table, which rusttype can't draw.
+pub struct ColorGlyphs {
+    data: Vec<u8>,
+    index: u32,
+}
+
+/// A glyph's pixels, not premultiplied, with its top left corner relative to the glyph's origin
+/// on the baseline.
+pub struct ColorImage {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl ColorGlyphs {
+    /// The color glyphs of the font, if it has any. The font data is kept to read them from.
+    pub fn parse(data: &[u8], index: u32) -> Option<ColorGlyphs> {
+        let face = RawFace::from_slice(data, index).ok()?;
+        let has = |tag: &[u8; 4]| face.table(Tag::from_bytes(tag)).is_some();
+        if !(has(b"CBDT") && has(b"CBLC") || has(b"sbix") || has(b"COLR") && has(b"CPAL")) {
+            return None;
+        }
+        Some(ColorGlyphs {
+            data: data.to_vec(),
+            index,
+        })
+    }
+
+    /// Draw the glyph of `font`, which must be the font of these color glyphs. COLR layers that
+    /// take the text color are drawn in `foreground`. `None` if the glyph has no colors.
+    pub fn rasterize(
+        &self,
+        font: &Font<'_>,
+        glyph: GlyphId,
+        scale: Scale,
+        foreground: Color,
+    ) -> Option<ColorImage> {
+        layers(&self.data, self.index, font, glyph, scale, foreground)
+            .or_else(|| bitmap(&self.data, self.index, font, glyph, scale))
+    }
+}
+
+/// A COLR (version 0) glyph: outlines of other glyphs in palette colors, bottom to top.
+fn layers(
+    data: &[u8],
+    index: u32,
+    font: &Font<'_>,
+    glyph: GlyphId,
+    scale: Scale,
+    foreground: Color,
+) -> Option<ColorImage> {
+    let face = RawFace::from_slice(data, index).ok()?;
+    let colr = face.table(Tag::from_bytes(b"COLR"))?;
+    let cpal = face.table(Tag::from_bytes(b"CPAL"))?;
+
+    // the base glyph records are sorted by glyph id
+    let base_count = read_u16(colr, 2)? as usize;
+    let base_offset = read_u32(colr, 4)? as usize;
+    let layer_offset = read_u32(colr, 8)? as usize;
+    let (mut low, mut high) = (0, base_count);
+    let (first_layer, layer_count) = loop {
+        if low >= high {
+            return None;
+        }
+        let middle = (low + high) / 2;
+        let record = base_offset + middle * 6;
+        match read_u16(colr, record)?.cmp(&glyph.0) {
+            Ordering::Less => low = middle + 1,
+            Ordering::Greater => high = middle,
+            Ordering::Equal => {
+                break (read_u16(colr, record + 2)?, read_u16(colr, record + 4)?);
+            }
+        }
+    };
+
+    // the first palette, its colors are BGRA
+    let colors_offset = read_u32(cpal, 8)? as usize;
+    let first_color = read_u16(cpal, 12)? as usize;
+    let mut layers = vec![];
+    for layer in first_layer..first_layer + layer_count {
+        let record = layer_offset + layer as usize * 4;
+        let color = match read_u16(colr, record + 2)? {
+            0xffff => foreground,
+            palette_index => {
+                let color = colors_offset + (first_color + palette_index as usize) * 4;
+                let channel = |i: usize| Some(*cpal.get(color + i)? as u16 * 257);
+                (
+                    channel(2)? as f32,
+                    channel(1)? as f32,
+                    channel(0)? as f32,
+                    channel(3)? as f32,
+                )
+            }
+        };
+        let glyph = font
+            .glyph(GlyphId(read_u16(colr, record)?))
+            .scaled(scale)
+            .positioned(point(0.0, 0.0));
+        layers.push((glyph, color));
+    }
+
+    let bounds = layers
+        .iter()
+        .filter_map(|(glyph, _)| glyph.pixel_bounding_box())
+        .reduce(|a, b| Rect {
+            min: point(a.min.x.min(b.min.x), a.min.y.min(b.min.y)),
+            max: point(a.max.x.max(b.max.x), a.max.y.max(b.max.y)),
+        })?;
+    let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+    let mut pixels = vec![(0.0, 0.0, 0.0, 0.0); (width * height) as usize];
+    for (glyph, color) in layers {
+        let glyph_bounds = match glyph.pixel_bounding_box() {
+            Some(glyph_bounds) => glyph_bounds,
+            None => continue,
+        };
+        let (dx, dy) = (
+            glyph_bounds.min.x - bounds.min.x,
+            glyph_bounds.min.y - bounds.min.y,
+        );
+        glyph.draw(|x, y, v| {
+            let i = (y as i32 + dy) as usize * width as usize + (x as i32 + dx) as usize;
+            pixels[i] = over(pixels[i], color, v);
+        });
+    }
+    Some(ColorImage {
+        x: bounds.min.x,
+        y: bounds.min.y,
+        width,
+        pixels,
+    })
+}
+
+/// A PNG from the CBDT or sbix strike closest to the size, scaled to it.
+fn bitmap(
+    data: &[u8],
+    index: u32,
+    font: &Font<'_>,
+    glyph: GlyphId,
+    scale: Scale,
+) -> Option<ColorImage> {
+    let face = Face::from_slice(data, index).ok()?;
+    // rusttype scales the height from descender to ascender, but strikes are sized by the em
+    let pixels_per_em = font.scale_for_pixel_height(scale.y) * face.units_per_em() as f32;
+    let raster =
+        face.glyph_raster_image(ttf_parser::GlyphId(glyph.0), pixels_per_em.round() as u16)?;
+    let (source_width, source_height, source) = decode_png(raster.data)?;
+
+    let factor = pixels_per_em / raster.pixels_per_em as f32;
+    let width = ((source_width as f32 * factor).round() as u32).max(1);
+    let height = ((source_height as f32 * factor).round() as u32).max(1);
+    let pixels = resize((source_width, source_height, &source), width, height);
+
+    // the offsets are to the bottom left corner, upwards from the baseline
+    Some(ColorImage {
+        x: (raster.x as f32 * factor).round() as i32,
+        y: -((raster.y as f32 * factor).round() as i32) - height as i32,
+        width,
+        pixels,
+    })
+}
+
+/// Scale RGBA pixels of the given width and height to `width` by `height` pixels, averaging the
+/// premultiplied source pixels that each pixel covers.
+pub fn resize(
+    (source_width, source_height, source): (u32, u32, &[u8]),
+    width: u32,
+    height: u32,
+) -> Vec<Color> {
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        let (y0, y1) = span(y, height, source_height);
+        for x in 0..width {
+            let (x0, x1) = span(x, width, source_width);
+            let mut sum = [0.0; 4];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let pixel = &source[((sy * source_width + sx) * 4) as usize..][..4];
+                    let alpha = pixel[3] as f32 / 255.0;
+                    sum[0] += pixel[0] as f32 * alpha;
+                    sum[1] += pixel[1] as f32 * alpha;
+                    sum[2] += pixel[2] as f32 * alpha;
+                    sum[3] += alpha;
+                }
+            }
+            let count = ((y1 - y0) * (x1 - x0)) as f32;
+            pixels.push(match sum[3] {
+                alpha if alpha > 0.0 => (
+                    sum[0] / alpha * 257.0,
+                    sum[1] / alpha * 257.0,
+                    sum[2] / alpha * 257.0,
+                    alpha / count * 65535.0,
+                ),
+                _ => (0.0, 0.0, 0.0, 0.0),
+            });
+        }
+    }
+    pixels
+}
+
+/// The range of source pixels that the pixel `i` of `size` covers, at least one.
+fn span(i: u32, size: u32, source_size: u32) -> (u32, u32) {
+    let start = i * source_size / size;
+    let end = ((i + 1) * source_size / size).clamp(start + 1, source_size);
+    (start.min(source_size - 1), end)
+}
+
+/// Width, height and RGBA pixels.
+pub fn decode_png(data: &[u8]) -> Option<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let mut buffer = vec![0; reader.output_buffer_size()?];
+    let info = reader.next_frame(&mut buffer).ok()?;
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buffer,
+        png::ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buffer.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        // expanded by normalize_to_color8
+        png::ColorType::Indexed => return None,
+    };
+    Some((info.width, info.height, rgba))
+}
+
+/// `color` with `coverage` drawn over `below`, neither premultiplied.
+fn over(below: Color, color: Color, coverage: f32) -> Color {
+    let alpha = color.3 / 65535.0 * coverage;
+    let below_alpha = below.3 / 65535.0 * (1.0 - alpha);
+    let total = alpha + below_alpha;
+    if total <= 0.0 {
+        return below;
+    }
+    (
+        (color.0 * alpha + below.0 * below_alpha) / total,
+        (color.1 * alpha + below.1 * below_alpha) / total,
+        (color.2 * alpha + below.2 * below_alpha) / total,
+        total * 65535.0,
+    )
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
@@ -0,0 +1,142 @@
+use crate::{error::DmitriError, items::Item};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Launches older than this count half as much.
+const HALF_LIFE_SECS: f32 = 14. * 24. * 60. * 60.;
+/// Only this many entries with the highest score are kept on disk.
+const MAX_ENTRIES: usize = 1000;
+
+struct Entry {
+    count: u32,
+    last_used: u64,
+}
+
+/// Previously launched selections, persisted in `$XDG_CACHE_HOME/dmitri/`.
+pub struct History {
+    path: PathBuf,
+    entries: HashMap<String, Entry>,
+}
+
+impl History {
+    /// Load the history file with the given name. A missing or malformed file yields an empty
+    /// history, it's just a cache.
+    pub fn load(name: &str) -> History {
+        let path = cache_dir().join(name);
+        let mut entries = HashMap::new();
+        if let Ok(contents) = fs::read_to_string(&path) {
+            for line in contents.lines() {
+                let mut fields = line.splitn(3, '\t');
+                if let (Some(count), Some(last_used), Some(selection)) =
+                    (fields.next(), fields.next(), fields.next())
+                {
+                    if let (Ok(count), Ok(last_used)) = (count.parse(), last_used.parse()) {
+                        entries.insert(selection.to_string(), Entry { count, last_used });
+                    }
+                }
+            }
+        }
+        History { path, entries }
+    }
+
+    /// Use count decayed by the time since the last launch. The history of a daemon lives on, so
+    /// the time is taken anew.
+    pub fn frecency(&self, selection: &str) -> f32 {
+        self.frecency_at(selection, now())
+    }
+
+    fn frecency_at(&self, selection: &str, now: u64) -> f32 {
+        match self.entries.get(selection) {
+            Some(entry) => {
+                let age = now.saturating_sub(entry.last_used) as f32;
+                entry.count as f32 * 0.5f32.powf(age / HALF_LIFE_SECS)
+            }
+            None => 0.,
+        }
+    }
+
+    /// The `n` items with the highest frecency of their values.
+    pub fn top(&self, items: &[Item], n: usize) -> Vec<Item> {
+        let now = now();
+        let mut top: Vec<(&Item, f32)> = items
+            .iter()
+            .filter(|item| self.entries.contains_key(item.value()))
+            .map(|item| (item, self.frecency_at(item.value(), now)))
+            .collect();
+        top.sort_by(|a, b| tie_break(*a, *b));
+        top.into_iter()
+            .take(n)
+            .map(|(item, _)| item.clone())
+            .collect()
+    }
+
+    pub fn record(&mut self, selection: &str) {
+        let entry = self.entries.entry(selection.to_string()).or_insert(Entry {
+            count: 0,
+            last_used: 0,
+        });
+        entry.count += 1;
+        entry.last_used = now();
+    }
+
+    pub fn save(&self) -> Result<(), DmitriError> {
+        let now = now();
+        let mut entries: Vec<(&String, f32)> = self
+            .entries
+            .keys()
+            .map(|selection| (selection, self.frecency_at(selection, now)))
+            .collect();
+        entries.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut contents = String::new();
+        for (selection, _) in entries.into_iter().take(MAX_ENTRIES) {
+            let entry = &self.entries[selection];
+            contents.push_str(&format!(
+                "{}\t{}\t{}\n",
+                entry.count, entry.last_used, selection
+            ));
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, contents)?;
+        Ok(())
+    }
+}
+
+/// `$XDG_CACHE_HOME/dmitri`, or `~/.cache/dmitri`.
+pub fn cache_dir() -> PathBuf {
+    let cache_home = match env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&env::var("HOME").unwrap_or_default()).join(".cache"),
+    };
+    cache_home.join("dmitri")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The order of items that match equally well: the more used first, then the shorter, then
+/// alphabetically.
+pub(crate) fn tie_break(
+    (item, frecency): (&Item, f32),
+    (other, other_frecency): (&Item, f32),
+) -> Ordering {
+    other_frecency.total_cmp(&frecency).then_with(|| {
+        let (display, other) = (item.display(), other.display());
+        display
+            .chars()
+            .count()
+            .cmp(&other.chars().count())
+            .then_with(|| display.cmp(other))
+    })
+}
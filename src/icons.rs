@@ -0,0 +1,245 @@
+use crate::{
+    desktop::data_dirs,
+    glyphs::{self, ColorImage},
+    text::Color,
+};
+use resvg::{tiny_skia, usvg};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// The fallback theme that applications install their icons into.
+const HICOLOR: &str = "hicolor";
+
+/// Load an icon by its path, or by its name from the icon theme, the themes it inherits from,
+/// `hicolor` and the pixmaps directories, like the freedesktop icon theme specification. It is
+/// scaled to fit in a square of `size` pixels, and centered in it by its offsets.
+pub fn load(name: &str, theme: Option<&str>, size: u32) -> Option<ColorImage> {
+    let path = if Path::new(name).is_absolute() {
+        PathBuf::from(name)
+    } else {
+        lookup(name, theme, size)?
+    };
+    let data = fs::read(&path).ok()?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("svg" | "svgz") => render_svg(&data, size),
+        _ => {
+            let (width, height, pixels) = glyphs::decode_png(&data)?;
+            let (fit_width, fit_height) = fit(width as f32, height as f32, size);
+            let pixels = glyphs::resize((width, height, &pixels), fit_width, fit_height);
+            Some(centered(fit_width, fit_height, pixels, size))
+        }
+    }
+}
+
+/// The directories that icon themes are in, in order of precedence.
+fn base_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Ok(home) = env::var("HOME") {
+        dirs.push(Path::new(&home).join(".icons"));
+    }
+    dirs.extend(data_dirs().into_iter().map(|dir| dir.join("icons")));
+    dirs
+}
+
+/// The file of the icon with the size closest to `size`, searching each theme completely before
+/// the next one.
+fn lookup(name: &str, theme: Option<&str>, size: u32) -> Option<PathBuf> {
+    let base_dirs = base_dirs();
+    let mut themes = vec![];
+    if let Some(theme) = theme {
+        add_theme(theme, &base_dirs, &mut themes);
+    }
+    add_theme(HICOLOR, &base_dirs, &mut themes);
+
+    for (theme, index) in &themes {
+        let mut best: Option<(u32, PathBuf)> = None;
+        for base_dir in &base_dirs {
+            for directory in &index.directories {
+                for extension in ["png", "svg"] {
+                    let path = base_dir
+                        .join(theme)
+                        .join(&directory.path)
+                        .join(format!("{}.{}", name, extension));
+                    let distance = directory.distance(size);
+                    if best.as_ref().is_some_and(|(best, _)| *best <= distance) {
+                        continue;
+                    }
+                    if path.is_file() {
+                        best = Some((distance, path));
+                    }
+                }
+            }
+        }
+        if let Some((_, path)) = best {
+            return Some(path);
+        }
+    }
+
+    data_dirs()
+        .into_iter()
+        .flat_map(|dir| {
+            ["png", "svg"]
+                .map(|extension| dir.join("pixmaps").join(format!("{}.{}", name, extension)))
+        })
+        .find(|path| path.is_file())
+}
+
+/// Add the theme and then the themes it inherits from, depth first, each only once.
+fn add_theme(name: &str, base_dirs: &[PathBuf], themes: &mut Vec<(String, Index)>) {
+    if themes.iter().any(|(theme, _)| theme == name) {
+        return;
+    }
+    let index = match base_dirs
+        .iter()
+        .find_map(|dir| fs::read_to_string(dir.join(name).join("index.theme")).ok())
+    {
+        Some(contents) => Index::parse(&contents),
+        None => return,
+    };
+    let inherits = index.inherits.clone();
+    themes.push((name.to_string(), index));
+    for parent in inherits {
+        add_theme(&parent, base_dirs, themes);
+    }
+}
+
+/// The `index.theme` of an icon theme.
+#[derive(Default)]
+struct Index {
+    inherits: Vec<String>,
+    directories: Vec<Directory>,
+}
+
+/// A directory of icons of a theme, and the sizes that its icons are for.
+struct Directory {
+    path: String,
+    size: u32,
+    kind: String,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+}
+
+impl Index {
+    fn parse(contents: &str) -> Index {
+        let mut index = Index::default();
+        let mut group = String::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                group = name.to_string();
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            let list = || value.split(',').map(str::trim).filter(|v| !v.is_empty());
+            if group == "Icon Theme" {
+                match key {
+                    "Inherits" => index.inherits = list().map(String::from).collect(),
+                    "Directories" => {
+                        index.directories = list()
+                            .map(|path| Directory {
+                                path: path.to_string(),
+                                size: 0,
+                                kind: String::from("Threshold"),
+                                min_size: 0,
+                                max_size: 0,
+                                threshold: 2,
+                            })
+                            .collect()
+                    }
+                    _ => (),
+                }
+                continue;
+            }
+            let directory = match index.directories.iter_mut().find(|d| d.path == group) {
+                Some(directory) => directory,
+                None => continue,
+            };
+            match key {
+                "Size" => directory.size = value.parse().unwrap_or(0),
+                "Type" => directory.kind = value.to_string(),
+                "MinSize" => directory.min_size = value.parse().unwrap_or(0),
+                "MaxSize" => directory.max_size = value.parse().unwrap_or(0),
+                "Threshold" => directory.threshold = value.parse().unwrap_or(2),
+                _ => (),
+            }
+        }
+        // sizes that aren't given are the nominal size
+        for directory in &mut index.directories {
+            if directory.min_size == 0 {
+                directory.min_size = directory.size;
+            }
+            if directory.max_size == 0 {
+                directory.max_size = directory.size;
+            }
+        }
+        index
+    }
+}
+
+impl Directory {
+    /// How far the icons of the directory are from the size, 0 if they are made for it.
+    fn distance(&self, size: u32) -> u32 {
+        let (min, max) = match self.kind.as_str() {
+            "Fixed" => (self.size, self.size),
+            "Scalable" => (self.min_size, self.max_size),
+            _ => (
+                self.size.saturating_sub(self.threshold),
+                self.size + self.threshold,
+            ),
+        };
+        min.saturating_sub(size) + size.saturating_sub(max)
+    }
+}
+
+/// Draw an SVG image to fit in the size.
+fn render_svg(data: &[u8], size: u32) -> Option<ColorImage> {
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default()).ok()?;
+    let (width, height) = (tree.size().width(), tree.size().height());
+    let (fit_width, fit_height) = fit(width, height, size);
+    let scale = fit_width as f32 / width;
+    let mut pixmap = tiny_skia::Pixmap::new(fit_width, fit_height)?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .map(|pixel| {
+            let color = pixel.demultiply();
+            (
+                color.red() as f32 * 257.0,
+                color.green() as f32 * 257.0,
+                color.blue() as f32 * 257.0,
+                color.alpha() as f32 * 257.0,
+            )
+        })
+        .collect();
+    Some(centered(fit_width, fit_height, pixels, size))
+}
+
+/// The size of an image of `width` by `height` scaled to fit in a square of `size`.
+fn fit(width: f32, height: f32, size: u32) -> (u32, u32) {
+    let scale = size as f32 / width.max(height);
+    (
+        ((width * scale).round() as u32).clamp(1, size),
+        ((height * scale).round() as u32).clamp(1, size),
+    )
+}
+
+/// The image with offsets that center it in a square of `size`.
+fn centered(width: u32, height: u32, pixels: Vec<Color>, size: u32) -> ColorImage {
+    ColorImage {
+        x: ((size - width) / 2) as i32,
+        y: ((size - height) / 2) as i32,
+        width,
+        pixels,
+    }
+}
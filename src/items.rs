@@ -0,0 +1,1349 @@
+use crate::{error::DmitriError, files, filter::Filter, history::cache_dir, links, man, supervise};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, BufRead, Write},
+    iter,
+    os::unix::{prelude::MetadataExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc, Mutex, OnceLock, PoisonError, RwLock, RwLockReadGuard,
+    },
+    thread,
+};
+
+/// An item to pick, shown and matched by its text, which may output another value. Items are
+/// read from JSON objects like `{"label": "Firefox", "value": "firefox"}` with `--json`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize)]
+pub struct Item {
+    #[serde(rename = "label")]
+    display: String,
+    value: Option<String>,
+    /// An icon name or path.
+    icon: Option<String>,
+    description: Option<String>,
+    /// Other text that the item is found by, but which isn't shown.
+    #[serde(default)]
+    keywords: Vec<String>,
+    badge: Option<Badge>,
+    /// Facts about the item, like where a command is installed, shown at the end of its line
+    /// while it is selected with `--details`.
+    detail: Option<String>,
+}
+
+/// A short text drawn on a box of its color at the end of the line of a listed item, like the
+/// state of a unit. Read from JSON like `{"text": "failed", "color": "#cc3333"}`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize)]
+pub struct Badge {
+    pub text: String,
+    /// A color like `#rrggbb`.
+    pub color: String,
+}
+
+/// How much a match of a keyword counts compared to one of the shown text, so that items whose
+/// shown text matches come first.
+const KEYWORD_WEIGHT: f32 = 0.8;
+
+impl Item {
+    /// An item that outputs the text that is shown.
+    pub fn new(display: String) -> Item {
+        Item {
+            display,
+            value: None,
+            icon: None,
+            description: None,
+            keywords: vec![],
+            badge: None,
+            detail: None,
+        }
+    }
+
+    /// An item that shows `display`, but outputs `value`.
+    pub fn with_value(display: String, value: String) -> Item {
+        Item {
+            value: Some(value),
+            ..Item::new(display)
+        }
+    }
+
+    /// The item with an icon name or path, drawn before it with `--icons`.
+    pub fn with_icon(self, icon: Option<String>) -> Item {
+        Item { icon, ..self }
+    }
+
+    /// The item with a description, shown dimmer next to it.
+    pub fn with_description(self, description: Option<String>) -> Item {
+        Item {
+            description,
+            ..self
+        }
+    }
+
+    /// The item with other text that it is found by, like the keywords of an application.
+    pub fn with_keywords(self, keywords: Vec<String>) -> Item {
+        Item { keywords, ..self }
+    }
+
+    /// The item with a badge, drawn at the end of its line in the list.
+    pub fn with_badge(self, badge: Option<Badge>) -> Item {
+        Item { badge, ..self }
+    }
+
+    /// The item with a detail, drawn at the end of its line while it is selected.
+    pub fn with_detail(self, detail: Option<String>) -> Item {
+        Item { detail, ..self }
+    }
+
+    /// What is shown and matched against the input.
+    pub fn display(&self) -> &str {
+        &self.display
+    }
+
+    /// What is output when the item is confirmed, and recorded in the history.
+    pub fn value(&self) -> &str {
+        self.value.as_deref().unwrap_or(&self.display)
+    }
+
+    pub fn icon(&self) -> Option<&str> {
+        self.icon.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn keywords(&self) -> &[String] {
+        &self.keywords
+    }
+
+    pub fn badge(&self) -> Option<&Badge> {
+        self.badge.as_ref()
+    }
+
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+
+    /// The texts that the item is matched by, each with the weight of its score: the shown text
+    /// and then the keywords.
+    pub fn haystacks(&self) -> impl Iterator<Item = (&str, f32)> {
+        iter::once((self.display(), 1.)).chain(
+            self.keywords
+                .iter()
+                .map(|keyword| (keyword.as_str(), KEYWORD_WEIGHT)),
+        )
+    }
+}
+
+impl From<String> for Item {
+    fn from(display: String) -> Item {
+        Item::new(display)
+    }
+}
+
+/// Provides the list of items that the input is matched against.
+pub trait ItemSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError>;
+
+    /// Items that keep arriving after `items`, for sources that find them in the background.
+    fn stream(&mut self) -> Option<ItemStream> {
+        None
+    }
+
+    /// The directories that the items are found in, which `--daemon` watches so that it can
+    /// `update` the items when files in them change, instead of reading them all again.
+    fn watched(&self) -> Vec<PathBuf> {
+        vec![]
+    }
+
+    /// Update the items, or the streamed ones, for the files in the watched directories that
+    /// were added, changed or removed. Whether they could be updated; if not, they are read
+    /// again.
+    fn update(&mut self, _items: &mut Vec<Item>, _changed: &[PathBuf]) -> bool {
+        false
+    }
+}
+
+/// Items that keep arriving while the picker runs, like the files that a walk in the background
+/// finds. The picker searches them again as more arrive.
+#[derive(Clone, Default)]
+pub struct ItemStream {
+    shared: Arc<Streamed>,
+}
+
+#[derive(Default)]
+struct Streamed {
+    items: RwLock<Vec<Item>>,
+    finished: AtomicBool,
+}
+
+impl ItemStream {
+    /// Add items, which the picker searches the next time it looks.
+    pub fn extend(&self, items: Vec<Item>) {
+        let mut streamed = self
+            .shared
+            .items
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        streamed.extend(items);
+    }
+
+    /// Tell the picker that no more items will arrive.
+    pub fn finish(&self) {
+        self.shared.finished.store(true, Ordering::Release);
+    }
+
+    /// Change the items that arrived, once the stream is finished and while the picker doesn't
+    /// search them.
+    pub fn update<T>(&self, f: impl FnOnce(&mut Vec<Item>) -> T) -> T {
+        let mut streamed = self
+            .shared
+            .items
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        f(&mut streamed)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.shared.finished.load(Ordering::Acquire)
+    }
+
+    /// The items that arrived so far. No more can arrive while they are read.
+    pub fn items(&self) -> RwLockReadGuard<'_, Vec<Item>> {
+        self.shared
+            .items
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What the picker searches: a list of items, or the items of a stream instead, and items for
+/// some inputs instead of either.
+#[derive(Clone, Copy, Default)]
+pub struct Items<'a> {
+    pub list: &'a [Item],
+    pub stream: Option<&'a ItemStream>,
+    pub dynamic: Option<&'a dyn DynamicSource>,
+}
+
+impl<'a> From<&'a [Item]> for Items<'a> {
+    fn from(list: &'a [Item]) -> Items<'a> {
+        Items {
+            list,
+            ..Items::default()
+        }
+    }
+}
+
+/// Receives the confirmed selection.
+pub trait ItemSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError>;
+
+    /// Receives the selection that was confirmed to run in a terminal emulator. Sinks that don't
+    /// run commands take it like any other.
+    fn output_in_terminal(&mut self, selection: String) -> Result<(), DmitriError> {
+        self.output(selection)
+    }
+
+    /// Receives the selection that was confirmed to run as root. Sinks that don't run commands
+    /// take it like any other.
+    fn output_as_root(&mut self, selection: String) -> Result<(), DmitriError> {
+        self.output(selection)
+    }
+
+    /// Receives the selection that was confirmed with the other action of the mode, like
+    /// restarting a unit instead of starting or stopping it. Sinks without one take it like any
+    /// other.
+    fn output_alternate(&mut self, selection: String) -> Result<(), DmitriError> {
+        self.output(selection)
+    }
+
+    /// Receives the result when an arithmetic input was confirmed, which is copied to the
+    /// clipboard unless the sink prints.
+    fn calculation(&mut self, result: String) -> Result<(), DmitriError> {
+        copy_to_clipboard(&result)
+    }
+
+    /// Receives the URL when a web search, a link or an email address was confirmed, which is
+    /// opened unless the sink prints.
+    fn open_url(&mut self, url: String) -> Result<(), DmitriError> {
+        links::open(&url)
+    }
+
+    /// A question to answer with yes before the selection is output, for actions that can't be
+    /// undone.
+    fn confirmation(&self, _selection: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Provides items that depend on the input, which are searched instead of the items of the mode
+/// while it does, like the entries of a directory that is typed.
+pub trait DynamicSource: Sync {
+    /// The items for the input, or `None` for the items of the mode.
+    fn items(&self, input: &str) -> Option<Vec<Item>>;
+}
+
+/// The answers to the question of `--confirm`.
+pub struct ConfirmSource;
+
+impl ItemSource for ConfirmSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        Ok(vec![
+            Item::from(String::from("yes")),
+            Item::from(String::from("no")),
+        ])
+    }
+}
+
+/// Every executable file found in `$PATH`, with the description of its man page if
+/// `descriptions` is set. The aliases come first, with their commands as descriptions and an
+/// `alias` badge, and hide the executables of the same name. Executables that the filter doesn't
+/// keep are left out. The directories are read in the
+/// background and their executables streamed to the picker, so that a directory on slow storage
+/// doesn't keep it from showing.
+pub struct PathSource {
+    pub descriptions: bool,
+    pub aliases: HashMap<String, String>,
+    pub filter: Filter,
+}
+
+impl ItemSource for PathSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        Ok(vec![])
+    }
+
+    fn stream(&mut self) -> Option<ItemStream> {
+        let stream = ItemStream::default();
+        let mut aliases: Vec<(&String, &String)> = self.aliases.iter().collect();
+        aliases.sort();
+        stream.extend(
+            aliases
+                .into_iter()
+                .map(|(name, command)| {
+                    Item::new(name.clone())
+                        .with_description(Some(command.clone()))
+                        .with_badge(Some(Badge {
+                            text: String::from("alias"),
+                            color: String::from("#5f87af"),
+                        }))
+                })
+                .collect(),
+        );
+        let scan = PathScan {
+            stream: stream.clone(),
+            hidden: self.aliases.keys().cloned().collect(),
+            filter: self.filter.clone(),
+            found: Mutex::new(HashMap::new()),
+            descriptions: self.descriptions,
+        };
+        thread::spawn(move || scan.run());
+        Some(stream)
+    }
+
+    fn watched(&self) -> Vec<PathBuf> {
+        path_dirs().into_iter().map(PathBuf::from).collect()
+    }
+
+    /// A changed file is listed when it is an executable in any `$PATH` directory, as another
+    /// may still have one of the same name after it was removed from one, and its detail is
+    /// that of the first one.
+    fn update(&mut self, items: &mut Vec<Item>, changed: &[PathBuf]) -> bool {
+        let dirs = path_dirs();
+        let mut descriptions: Option<HashMap<String, String>> = None;
+        for file in changed {
+            let name = match file.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if self.aliases.contains_key(&name) || !self.filter.keeps([name.as_str()]) {
+                continue;
+            }
+            let found: Vec<(&str, Executable)> = dirs
+                .iter()
+                .filter_map(|dir| {
+                    let metadata = fs::metadata(Path::new(dir).join(&name)).ok()?;
+                    executable(&metadata).then(|| (dir.as_str(), Executable::new(&name, &metadata)))
+                })
+                .collect();
+            let index = items.iter().position(|item| item.display() == name);
+            match (detail(&found), index) {
+                (Some(detail), Some(index)) => {
+                    let item = items[index].clone();
+                    items[index] = item.with_detail(Some(detail));
+                }
+                (Some(detail), None) => {
+                    let description = self
+                        .descriptions
+                        .then(|| {
+                            descriptions
+                                .get_or_insert_with(|| whatis(false))
+                                .remove(&name)
+                        })
+                        .flatten();
+                    items.push(
+                        Item::new(name)
+                            .with_description(description)
+                            .with_detail(Some(detail)),
+                    );
+                }
+                (None, Some(index)) => {
+                    items.remove(index);
+                }
+                (None, None) => (),
+            }
+        }
+        true
+    }
+}
+
+/// Newline separated items read from stdin, like dmenu. With a delimiter, each line is split at
+/// the first one into the text that is shown and the value that is output, like `Firefox\tfirefox`.
+pub struct StdinSource {
+    pub delimiter: Option<String>,
+}
+
+impl ItemSource for StdinSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        let mut items: Vec<Item> = vec![];
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let split = self
+                .delimiter
+                .as_deref()
+                .and_then(|delimiter| line.split_once(delimiter));
+            items.push(match split {
+                Some((display, value)) => Item::with_value(display.to_string(), value.to_string()),
+                None => Item::new(line),
+            });
+        }
+        Ok(items)
+    }
+}
+
+/// Items read from stdin as one JSON object per line, for frontends that know more about them.
+pub struct JsonSource;
+
+impl ItemSource for JsonSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        let mut items: Vec<Item> = vec![];
+        for (i, line) in io::stdin().lock().lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let item = serde_json::from_str(&line).map_err(|err| {
+                let message = format!("Invalid item on line {}: {}", i + 1, err);
+                io::Error::new(io::ErrorKind::InvalidData, message)
+            })?;
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+/// No items at all, when only the typed input matters.
+pub struct EmptySource;
+
+impl ItemSource for EmptySource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        Ok(vec![])
+    }
+}
+
+/// Executes the selection as a shell command, so that it can have arguments, quotes, pipes and
+/// so on, like dmenu_run. With `always_in_terminal`, every command runs in the terminal. Paths of
+/// files that aren't executable are opened with their application instead, and paths of
+/// directories open a shell in them in the terminal. The alternate action opens the man page of
+/// the command. A first word that is an alias is replaced by its command, so that arguments can
+/// follow it. Commands that start with the prefix of `root` run as root.
+pub struct SpawnSink {
+    pub terminal: Terminal,
+    pub always_in_terminal: bool,
+    pub aliases: HashMap<String, String>,
+    pub root: Root,
+}
+
+impl ItemSink for SpawnSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        if let Some(command) = self.root.strip(&selection) {
+            return self.spawn(command.to_string(), self.always_in_terminal, true);
+        }
+        if files::is_path(&selection) {
+            let path = files::expand(&selection);
+            let metadata = fs::metadata(&path).ok();
+            if metadata.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+                return self.open_directory(&path);
+            }
+            if metadata.is_some_and(|metadata| metadata.is_file() && metadata.mode() & 0o111 == 0) {
+                return files::open(&path);
+            }
+        }
+        self.spawn(selection, self.always_in_terminal, false)
+    }
+
+    fn output_in_terminal(&mut self, selection: String) -> Result<(), DmitriError> {
+        match self.root.strip(&selection) {
+            Some(command) => self.spawn(command.to_string(), true, true),
+            None => self.spawn(selection, true, false),
+        }
+    }
+
+    fn output_as_root(&mut self, selection: String) -> Result<(), DmitriError> {
+        let command = self.root.strip(&selection).unwrap_or(&selection);
+        self.spawn(command.to_string(), self.always_in_terminal, true)
+    }
+
+    fn output_alternate(&mut self, selection: String) -> Result<(), DmitriError> {
+        let command = self.root.strip(&selection).unwrap_or(&selection);
+        match self.expand(command.to_string()).split_whitespace().next() {
+            Some(name) => man::open(&self.terminal, name),
+            None => Ok(()),
+        }
+    }
+}
+
+impl SpawnSink {
+    /// Run the command with `$SHELL -c`, in the terminal and as root if asked to.
+    fn spawn(&self, command: String, in_terminal: bool, as_root: bool) -> Result<(), DmitriError> {
+        let command = self.expand(command);
+        let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        let mut argv = vec![shell, String::from("-c"), command.clone()];
+        if as_root {
+            argv = self.root.command(&argv);
+        }
+        if in_terminal {
+            argv = self.terminal.command(&argv);
+        }
+        let mut spawned = process::Command::new(&argv[0]);
+        spawned.args(&argv[1..]);
+        spawn_detached(&mut spawned).map_err(|source| DmitriError::Spawn {
+            command: if in_terminal || as_root {
+                argv.join(" ")
+            } else {
+                command
+            },
+            source,
+        })
+    }
+
+    /// The command with its first word replaced by the alias of that name, if there is one.
+    fn expand(&self, command: String) -> String {
+        let trimmed = command.trim_start();
+        let (name, rest) = trimmed
+            .split_once(char::is_whitespace)
+            .unwrap_or((trimmed, ""));
+        match self.aliases.get(name) {
+            Some(alias) if rest.is_empty() => alias.clone(),
+            Some(alias) => format!("{} {}", alias, rest),
+            None => command,
+        }
+    }
+
+    /// Open an interactive shell in the terminal, in the directory.
+    fn open_directory(&self, path: &Path) -> Result<(), DmitriError> {
+        let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        let argv = self.terminal.command(&[shell]);
+        let mut command = process::Command::new(&argv[0]);
+        command.args(&argv[1..]).current_dir(path);
+        spawn_detached(&mut command).map_err(|source| DmitriError::Spawn {
+            command: argv.join(" "),
+            source,
+        })
+    }
+}
+
+/// How the environment of the programs that are launched differs from the one of dmitri: the
+/// variables to remove and to set, whether to keep only those of the session, and the working
+/// directory of those that don't have their own. With `scope`, each program runs in a systemd
+/// scope of its own in `app.slice`, so that it isn't killed with the scope of dmitri, like when
+/// the compositor restarts. Without a user instance of systemd, it runs without one. With
+/// `notify_failures`, programs are supervised by dmitri for a few seconds, to notify when they
+/// fail, see `supervise`. With `wait`, dmitri waits for them to exit instead of detaching them,
+/// see `exit_status`.
+#[derive(Default)]
+pub struct Environment {
+    pub set: HashMap<String, String>,
+    pub unset: Vec<String>,
+    pub clean: bool,
+    pub working_dir: Option<PathBuf>,
+    pub scope: bool,
+    pub notify_failures: bool,
+    pub wait: bool,
+}
+
+/// The variables that a clean environment keeps, where a trailing `*` matches any suffix.
+const SESSION_VARIABLES: [&str; 16] = [
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "PATH",
+    "LANG",
+    "LANGUAGE",
+    "LC_*",
+    "TZ",
+    "DISPLAY",
+    "XAUTHORITY",
+    "WAYLAND_DISPLAY",
+    "XDG_*",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "SSH_AUTH_SOCK",
+    "TERMINAL",
+];
+
+/// The environment of every program spawned with `spawn_detached`, once it is set.
+static ENVIRONMENT: OnceLock<Environment> = OnceLock::new();
+
+impl Environment {
+    /// Make it the environment of the programs that are spawned from now on. It can only be set
+    /// once.
+    pub fn apply(self) {
+        let _ = ENVIRONMENT.set(self);
+    }
+
+    fn prepare(&self, command: &mut process::Command) {
+        // the variables that dmitri sets for the command itself, like DESKTOP_STARTUP_ID, win
+        let own: Vec<_> = command
+            .get_envs()
+            .map(|(name, value)| (name.to_owned(), value.map(|value| value.to_owned())))
+            .collect();
+        if self.clean {
+            command.env_clear();
+            command.envs(env::vars_os().filter(|(name, _)| {
+                let name = name.to_string_lossy();
+                SESSION_VARIABLES
+                    .iter()
+                    .any(|kept| match kept.strip_suffix('*') {
+                        Some(prefix) => name.starts_with(prefix),
+                        None => name == *kept,
+                    })
+            }));
+        }
+        for name in &self.unset {
+            command.env_remove(name);
+        }
+        command.envs(&self.set);
+        for (name, value) in own {
+            match value {
+                Some(value) => command.env(name, value),
+                None => command.env_remove(name),
+            };
+        }
+        if let Some(dir) = &self.working_dir {
+            if command.get_current_dir().is_none() {
+                command.current_dir(dir);
+            }
+        }
+        if self.notify_failures {
+            if let Ok(exe) = env::current_exe() {
+                let prefix = [exe.into_os_string(), OsString::from(supervise::FLAG)];
+                wrap(command, &prefix, self.clean);
+            }
+        }
+        if self.scope && user_manager() {
+            // systemd-run execs the program in the new scope, with its environment
+            let prefix = [
+                "systemd-run",
+                "--user",
+                "--scope",
+                "--slice=app.slice",
+                "--collect",
+                "--quiet",
+                "--",
+            ];
+            wrap(command, &prefix.map(OsString::from), self.clean);
+        }
+    }
+}
+
+/// Replace the command by one that runs it after the words of `prefix`, with the same
+/// environment and working directory.
+fn wrap(command: &mut process::Command, prefix: &[OsString], clean: bool) {
+    let mut wrapped = process::Command::new(&prefix[0]);
+    wrapped
+        .args(&prefix[1..])
+        .arg(command.get_program())
+        .args(command.get_args());
+    if clean {
+        wrapped.env_clear();
+    }
+    for (name, value) in command.get_envs() {
+        match value {
+            Some(value) => wrapped.env(name, value),
+            None => wrapped.env_remove(name),
+        };
+    }
+    if let Some(dir) = command.get_current_dir() {
+        wrapped.current_dir(dir);
+    }
+    *command = wrapped;
+}
+
+/// Whether `systemd-run` is installed and the user's instance of systemd runs.
+fn user_manager() -> bool {
+    let runtime_dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => return false,
+    };
+    installed("systemd-run") && runtime_dir.join("systemd/private").exists()
+}
+
+/// The exit status of the last program that was waited for, like a shell's `$?`.
+static EXIT_STATUS: AtomicI32 = AtomicI32::new(0);
+
+/// The exit status of the last launched program with `Environment::wait`, 128 and the signal
+/// for one that was killed, or else 0.
+pub fn exit_status() -> i32 {
+    EXIT_STATUS.load(Ordering::Relaxed)
+}
+
+/// Spawn the command as an orphan in a new session with stdio pointing to /dev/null, so that it
+/// survives the launcher and its terminal, and init reaps it instead of leaving a zombie. It gets
+/// the environment of `Environment::apply`, and with its `wait` it runs with the stdout and
+/// stderr of dmitri, which waits for it to exit.
+pub fn spawn_detached(command: &mut process::Command) -> io::Result<()> {
+    if let Some(environment) = ENVIRONMENT.get() {
+        environment.prepare(command);
+        if environment.wait {
+            let status = command.stdin(process::Stdio::null()).status()?;
+            EXIT_STATUS.store(supervise::code(status), Ordering::Relaxed);
+            return Ok(());
+        }
+    }
+    command
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null());
+    // Safety: only async-signal-safe functions are called between fork and exec.
+    unsafe {
+        command.pre_exec(|| {
+            // fork again and let the intermediate child exit, so the command's parent is init
+            match libc::fork() {
+                -1 => return Err(io::Error::last_os_error()),
+                0 => (),
+                _ => libc::_exit(0),
+            }
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    // the intermediate child exits right away, exec errors of the command are still reported
+    command.spawn()?.wait()?;
+    Ok(())
+}
+
+/// Command lines of terminal emulators that are looked for in `$PATH`, in this order, when
+/// neither a template nor `$TERMINAL` is given.
+const TERMINALS: [&str; 11] = [
+    "x-terminal-emulator -e {cmd}",
+    "alacritty -e {cmd}",
+    "kitty {cmd}",
+    "foot {cmd}",
+    "wezterm start -- {cmd}",
+    "gnome-terminal -- {cmd}",
+    "konsole -e {cmd}",
+    "xfce4-terminal -x {cmd}",
+    "urxvt -e {cmd}",
+    "st -e {cmd}",
+    "xterm -e {cmd}",
+];
+
+/// The terminal emulator that commands are run in: a command line template like
+/// `alacritty -e {cmd}`, where `{cmd}` stands for the words of the command, or else
+/// `$TERMINAL -e`, or else the first of the known terminal emulators found in `$PATH`.
+#[derive(Clone, Default)]
+pub struct Terminal {
+    pub template: Option<String>,
+}
+
+impl Terminal {
+    /// The command line that runs `command` in the terminal. Without `{cmd}` in the template, the
+    /// command is appended to it.
+    pub fn command(&self, command: &[String]) -> Vec<String> {
+        let template = match &self.template {
+            Some(template) if !template.trim().is_empty() => template.clone(),
+            _ => detect_terminal(),
+        };
+        let mut argv = vec![];
+        let mut replaced = false;
+        for word in template.split_whitespace() {
+            if word == "{cmd}" {
+                argv.extend_from_slice(command);
+                replaced = true;
+            } else {
+                argv.push(word.to_string());
+            }
+        }
+        if !replaced {
+            argv.extend_from_slice(command);
+        }
+        argv
+    }
+}
+
+fn detect_terminal() -> String {
+    if let Ok(terminal) = env::var("TERMINAL") {
+        if !terminal.is_empty() {
+            return format!("{} -e {{cmd}}", terminal);
+        }
+    }
+    TERMINALS
+        .into_iter()
+        .find(|template| installed(template.split(' ').next().unwrap_or_default()))
+        .unwrap_or(TERMINALS[TERMINALS.len() - 1])
+        .to_string()
+}
+
+/// How commands run as root: those that start with `prefix`, like `# htop`, and those confirmed
+/// to. The command line `template` runs them, where `{cmd}` stands for the words of the command,
+/// or else `pkexec`, or `sudo -A` with an askpass program if pkexec isn't installed. An empty
+/// prefix only runs commands as root when confirmed to.
+#[derive(Clone, Default)]
+pub struct Root {
+    pub prefix: String,
+    pub template: Option<String>,
+}
+
+/// The variables that programs run as root need to show their windows, which pkexec clears.
+const DISPLAY_VARIABLES: [&str; 4] = [
+    "DISPLAY",
+    "XAUTHORITY",
+    "WAYLAND_DISPLAY",
+    "XDG_RUNTIME_DIR",
+];
+
+impl Root {
+    /// The command after the prefix, if the selection starts with it.
+    fn strip<'a>(&self, selection: &'a str) -> Option<&'a str> {
+        if self.prefix.is_empty() {
+            return None;
+        }
+        let command = selection.trim_start().strip_prefix(self.prefix.as_str())?;
+        Some(command.trim_start()).filter(|command| !command.is_empty())
+    }
+
+    /// The command line that runs `command` as root, with the display variables of dmitri.
+    pub fn command(&self, command: &[String]) -> Vec<String> {
+        let template = match &self.template {
+            Some(template) if !template.trim().is_empty() => template.clone(),
+            _ if installed("pkexec") => String::from("pkexec"),
+            _ => String::from("sudo -A"),
+        };
+        let mut wrapped = vec![String::from("env")];
+        for name in DISPLAY_VARIABLES {
+            if let Ok(value) = env::var(name) {
+                wrapped.push(format!("{}={}", name, value));
+            }
+        }
+        wrapped.extend_from_slice(command);
+        let mut argv = vec![];
+        let mut replaced = false;
+        for word in template.split_whitespace() {
+            if word == "{cmd}" {
+                argv.extend_from_slice(&wrapped);
+                replaced = true;
+            } else {
+                argv.push(word.to_string());
+            }
+        }
+        if !replaced {
+            argv.extend(wrapped);
+        }
+        argv
+    }
+}
+
+/// Whether the program is found in `$PATH`.
+fn installed(program: &str) -> bool {
+    env::var("PATH")
+        .unwrap_or_default()
+        .split(':')
+        .any(|dir| Path::new(dir).join(program).is_file())
+}
+
+/// Prints the selection to stdout.
+pub struct PrintSink;
+
+impl ItemSink for PrintSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        println!("{}", selection);
+        Ok(())
+    }
+
+    fn calculation(&mut self, result: String) -> Result<(), DmitriError> {
+        self.output(result)
+    }
+
+    fn open_url(&mut self, url: String) -> Result<(), DmitriError> {
+        self.output(url)
+    }
+}
+
+/// Put the text in the clipboard with `wl-copy` on Wayland or `xclip` on X11, which keep running
+/// in the background to serve it after dmitri exits.
+pub fn copy_to_clipboard(text: &str) -> Result<(), DmitriError> {
+    let (program, args): (&str, &[&str]) = if env::var_os("WAYLAND_DISPLAY").is_some() {
+        ("wl-copy", &[])
+    } else {
+        ("xclip", &["-selection", "clipboard"])
+    };
+    let mut child = process::Command::new(program)
+        .args(args)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn()
+        .map_err(|source| DmitriError::Spawn {
+            command: program.to_string(),
+            source,
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Name of the file in the cache directory that remembers the executables of each `$PATH`
+/// directory.
+const PATH_CACHE: &str = "path";
+
+/// Name of the file in the cache directory that remembers the descriptions of the man pages of
+/// commands, as `whatis` is too slow to run on each start.
+const WHATIS_CACHE: &str = "whatis";
+
+/// The executables of one directory, valid as long as the directory's mtime doesn't change.
+struct CachedDir {
+    mtime: (i64, i64),
+    executables: Vec<Executable>,
+}
+
+/// An executable file, with its size and mtime when its directory was read.
+#[derive(Clone)]
+struct Executable {
+    name: String,
+    size: u64,
+    mtime: i64,
+}
+
+impl Executable {
+    fn new(name: &str, metadata: &fs::Metadata) -> Executable {
+        Executable {
+            name: name.to_string(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+        }
+    }
+}
+
+/// The directory that a command resolves to, as the first one in `$PATH` that has it, its size
+/// and when it was modified there, and the other directories that have one of the same name.
+fn detail(found: &[(&str, Executable)]) -> Option<String> {
+    let ((dir, executable), others) = found.split_first()?;
+    let mut detail = format!(
+        "{}  {}  {}",
+        dir,
+        human_size(executable.size),
+        local_time(executable.mtime)
+    );
+    if !others.is_empty() {
+        let others: Vec<&str> = others.iter().map(|(dir, _)| *dir).collect();
+        detail.push_str(&format!("  shadows {}", others.join(" ")));
+    }
+    Some(detail)
+}
+
+/// The size like `ls -h`, as `840`, `12K` or `1.5M`.
+fn human_size(size: u64) -> String {
+    let mut size = size as f64;
+    for unit in ["", "K", "M", "G"] {
+        if size < 1024. {
+            return match unit {
+                "" => format!("{}", size),
+                _ if size < 10. => format!("{:.1}{}", size, unit),
+                _ => format!("{:.0}{}", size, unit),
+            };
+        }
+        size /= 1024.;
+    }
+    format!("{:.0}T", size)
+}
+
+/// The time in the local time zone, like `2024-05-01 13:45`.
+fn local_time(secs: i64) -> String {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let time = secs as libc::time_t;
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return String::new();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+/// How many directories of `$PATH` are read at the same time at most.
+const MAX_SCAN_THREADS: usize = 8;
+
+/// Reads the directories of `$PATH` on a pool of threads and streams the executables of each
+/// directory once it was read. A name that was already streamed only changes the detail of its
+/// item, which is that of the directory that comes first in `$PATH`, as the shell's is.
+struct PathScan {
+    stream: ItemStream,
+    /// The names of the aliases, which hide executables.
+    hidden: HashSet<String>,
+    filter: Filter,
+    found: Mutex<HashMap<String, Found>>,
+    descriptions: bool,
+}
+
+/// Where an executable that was streamed is.
+struct Found {
+    /// The position of its item in the stream.
+    index: usize,
+    /// The directories that have it, by their position in `$PATH`, with its metadata in each.
+    dirs: Vec<(usize, Executable)>,
+}
+
+impl PathScan {
+    /// Directories are only read again when their mtime changed since the last run, which
+    /// catches added, removed and renamed files but not a `chmod +x` of an existing file. When
+    /// any did, the descriptions are cached again after the stream finished, so that new
+    /// commands have theirs from the next run on.
+    fn run(&self) {
+        let cache_path = cache_dir().join(PATH_CACHE);
+        let cache = Mutex::new(load_path_cache(&cache_path));
+        let stale = AtomicBool::new(false);
+        let descriptions = if self.descriptions {
+            whatis(false)
+        } else {
+            HashMap::new()
+        };
+        let dirs = path_dirs();
+        let scan = || {
+            dirs.par_iter().enumerate().for_each(|(position, path)| {
+                if let Some(executables) = executables(path, &cache, &stale) {
+                    self.send(&dirs, position, executables, &descriptions);
+                }
+            })
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(dirs.len().clamp(1, MAX_SCAN_THREADS))
+            .thread_name(|index| format!("dmitri-path-{}", index))
+            .build();
+        match pool {
+            Ok(pool) => pool.install(scan),
+            Err(_) => scan(),
+        }
+        self.stream.finish();
+
+        if stale.load(Ordering::Relaxed) {
+            let cache = cache.into_inner().unwrap_or_else(PoisonError::into_inner);
+            if let Err(err) = save_path_cache(&cache_path, &cache) {
+                eprintln!("Could not write {}: {}", cache_path.display(), err);
+            }
+            if self.descriptions {
+                whatis(true);
+            }
+        }
+    }
+
+    /// Stream the executables of the directory at `position` in `dirs`.
+    fn send(
+        &self,
+        dirs: &[String],
+        position: usize,
+        executables: Vec<Executable>,
+        descriptions: &HashMap<String, String>,
+    ) {
+        let mut found = self.found.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut new = vec![];
+        let mut shadowing = vec![];
+        for executable in executables {
+            if self.hidden.contains(&executable.name)
+                || !self.filter.keeps([executable.name.as_str()])
+            {
+                continue;
+            }
+            match found.get_mut(&executable.name) {
+                Some(other) => {
+                    shadowing.push(executable.name.clone());
+                    other.dirs.push((position, executable));
+                    other.dirs.sort_by_key(|(position, _)| *position);
+                }
+                None => new.push(executable),
+            }
+        }
+        let detail_of = |found: &Found| {
+            let found: Vec<(&str, Executable)> = found
+                .dirs
+                .iter()
+                .map(|(position, executable)| (dirs[*position].as_str(), executable.clone()))
+                .collect();
+            detail(&found)
+        };
+        if !shadowing.is_empty() {
+            self.stream.update(|items| {
+                for name in &shadowing {
+                    let found = &found[name];
+                    let item = items[found.index].clone();
+                    items[found.index] = item.with_detail(detail_of(found));
+                }
+            });
+        }
+        new.sort_by(|a, b| a.name.cmp(&b.name));
+        // the items are added while `found` is locked, so that their positions stay right
+        let start = self.stream.items().len();
+        let mut items = vec![];
+        for (offset, executable) in new.into_iter().enumerate() {
+            let name = executable.name.clone();
+            let other = Found {
+                index: start + offset,
+                dirs: vec![(position, executable)],
+            };
+            items.push(
+                Item::new(name.clone())
+                    .with_description(descriptions.get(&name).cloned())
+                    .with_detail(detail_of(&other)),
+            );
+            found.insert(name, other);
+        }
+        self.stream.extend(items);
+    }
+}
+
+/// The executables of a `$PATH` directory, from the cache unless its mtime changed, and whether
+/// the cache changed is set in `stale`. Directories that can't be read are left out with a
+/// warning, except for missing ones, which are common in `$PATH`.
+fn executables(
+    path: &str,
+    cache: &Mutex<HashMap<String, CachedDir>>,
+    stale: &AtomicBool,
+) -> Option<Vec<Executable>> {
+    let lock = || cache.lock().unwrap_or_else(PoisonError::into_inner);
+    let scanned = fs::metadata(path).and_then(|metadata| {
+        let mtime = (metadata.mtime(), metadata.mtime_nsec());
+        let cached = lock()
+            .get(path)
+            .filter(|dir| dir.mtime == mtime)
+            .map(|dir| dir.executables.clone());
+        if let Some(executables) = cached {
+            return Ok(executables);
+        }
+        let executables = scan_dir(path)?;
+        lock().insert(
+            path.to_string(),
+            CachedDir {
+                mtime,
+                executables: executables.clone(),
+            },
+        );
+        stale.store(true, Ordering::Relaxed);
+        Ok(executables)
+    });
+    match scanned {
+        Ok(executables) => Some(executables),
+        Err(err) => {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("Could not read {}: {}", path, err);
+            }
+            if lock().remove(path).is_some() {
+                stale.store(true, Ordering::Relaxed);
+            }
+            None
+        }
+    }
+}
+
+/// Where executables are looked for when `$PATH` isn't set, like the default of shells.
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// The directories of `$PATH` in their order, without empty and repeated ones. Directories that
+/// are the same through symlinks, like `/bin` and `/usr/bin` on systems with a merged `/usr`,
+/// are repeated ones.
+fn path_dirs() -> Vec<String> {
+    let path_var = env::var("PATH").unwrap_or_else(|err| {
+        eprintln!("Could not read $PATH: {}, using {}", err, DEFAULT_PATH);
+        DEFAULT_PATH.to_string()
+    });
+    let mut seen = HashSet::new();
+    path_var
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .filter(|dir| seen.insert(fs::canonicalize(dir).unwrap_or_else(|_| PathBuf::from(dir))))
+        .map(String::from)
+        .collect()
+}
+
+/// The one line descriptions of the man pages of commands, by command. They are read from the
+/// cache, unless the executables changed since they were cached, as when packages were installed.
+fn whatis(changed: bool) -> HashMap<String, String> {
+    let cache_path = cache_dir().join(WHATIS_CACHE);
+    let cached = (!changed)
+        .then(|| fs::read_to_string(&cache_path).ok())
+        .flatten();
+    let contents = match cached {
+        Some(contents) => contents,
+        None => {
+            // lines like `ls (1)               - list directory contents`, nothing without man-db
+            let output = process::Command::new("whatis")
+                .args(["-s", "1:6:8", "-w", "*"])
+                .stderr(process::Stdio::null())
+                .output()
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+                .unwrap_or_default();
+            let mut contents = String::new();
+            for line in output.lines() {
+                if let Some((page, description)) = line.split_once(" - ") {
+                    let name = page.split(" (").next().unwrap_or(page).trim();
+                    contents.push_str(&format!("{}\t{}\n", name, description.trim()));
+                }
+            }
+            let written = cache_path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&cache_path, &contents));
+            if let Err(err) = written {
+                eprintln!("Could not write {}: {}", cache_path.display(), err);
+            }
+            contents
+        }
+    };
+    let mut descriptions = HashMap::new();
+    for line in contents.lines() {
+        if let Some((name, description)) = line.split_once('\t') {
+            descriptions
+                .entry(name.to_string())
+                .or_insert_with(|| description.to_string());
+        }
+    }
+    descriptions
+}
+
+/// The executable files of the directory, also through symlinks. Dangling symlinks are skipped,
+/// and entries that can't be read are skipped with a warning.
+fn scan_dir(path: &str) -> io::Result<Vec<Executable>> {
+    let mut executables: Vec<Executable> = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                eprintln!("Could not read {}: {}", path, err);
+                continue;
+            }
+        };
+
+        let os_filename = entry.file_name();
+        let filename = os_filename.to_string_lossy().to_string();
+        let pathbuf = entry.path();
+        let metadata = match fs::metadata(&pathbuf) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                eprintln!("Could not read {}: {}", pathbuf.display(), err);
+                continue;
+            }
+        };
+        if executable(&metadata) {
+            executables.push(Executable::new(&filename, &metadata));
+        }
+    }
+    Ok(executables)
+}
+
+/// Whether the file is a regular file that anyone may execute.
+fn executable(metadata: &fs::Metadata) -> bool {
+    metadata.is_file() && metadata.mode() & 0o111 != 0
+}
+
+/// The cache file has a header line per directory, starting with a tab and holding the mtime and
+/// the directory, followed by one line per executable in it with its size, mtime and name. A
+/// missing or malformed file yields an empty cache, and a directory with malformed lines, like
+/// those of older versions without the size and mtime, is left out so that it is read again.
+fn load_path_cache(cache_path: &Path) -> HashMap<String, CachedDir> {
+    let mut cache = HashMap::new();
+    let contents = match fs::read_to_string(cache_path) {
+        Ok(contents) => contents,
+        Err(_) => return cache,
+    };
+    let mut malformed = HashSet::new();
+    let mut current: Option<&str> = None;
+    for line in contents.lines() {
+        if let Some(header) = line.strip_prefix('\t') {
+            let mut fields = header.splitn(3, '\t');
+            current = match (fields.next(), fields.next(), fields.next()) {
+                (Some(secs), Some(nsecs), Some(dir)) => match (secs.parse(), nsecs.parse()) {
+                    (Ok(secs), Ok(nsecs)) => {
+                        cache.entry(dir.to_string()).or_insert(CachedDir {
+                            mtime: (secs, nsecs),
+                            executables: vec![],
+                        });
+                        Some(dir)
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+        } else if let Some(dir) = current {
+            let mut fields = line.splitn(3, '\t');
+            let executable = match (fields.next(), fields.next(), fields.next()) {
+                (Some(size), Some(mtime), Some(name)) => match (size.parse(), mtime.parse()) {
+                    (Ok(size), Ok(mtime)) => Some(Executable {
+                        name: name.to_string(),
+                        size,
+                        mtime,
+                    }),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match (executable, cache.get_mut(dir)) {
+                (Some(executable), Some(cached)) => cached.executables.push(executable),
+                _ => {
+                    malformed.insert(dir);
+                }
+            }
+        }
+    }
+    for dir in malformed {
+        cache.remove(dir);
+    }
+    cache
+}
+
+fn save_path_cache(
+    cache_path: &Path,
+    cache: &HashMap<String, CachedDir>,
+) -> Result<(), DmitriError> {
+    let mut contents = String::new();
+    for (path, dir) in cache {
+        contents.push_str(&format!("\t{}\t{}\t{}\n", dir.mtime.0, dir.mtime.1, path));
+        for executable in &dir.executables {
+            contents.push_str(&format!(
+                "{}\t{}\t{}\n",
+                executable.size, executable.mtime, executable.name
+            ));
+        }
+    }
+    if let Some(dir) = cache_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(cache_path, contents)?;
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use crate::error::DmitriError;
+use breadx::{
+    display::DisplayFunctionsExt,
+    prelude::*,
+    protocol::xproto::{AtomEnum, GetPropertyRequest, Window},
+};
+use std::env;
+use xkbcommon::xkb::{self, compose, keysyms};
+
+/// Translates key events with the server's keyboard layout, including shift levels, AltGr,
+/// layout switching, dead keys and Compose key sequences.
+pub struct Keyboard {
+    state: xkb::State,
+    /// The sequences of the locale's compose table typed so far, if there is a table.
+    compose: Option<compose::State>,
+}
+
+impl Keyboard {
+    /// Compile a keymap from the rules, model, layout, variant and options that the server
+    /// advertises in the `_XKB_RULES_NAMES` root window property.
+    pub fn new<Dpy: Display>(dpy: &mut Dpy, root: Window) -> Result<Keyboard, DmitriError> {
+        let atom = dpy.intern_atom(false, "_XKB_RULES_NAMES")?;
+        let atom = dpy.wait_for_reply(atom)?.atom;
+        let cookie = dpy.send_reply_request(GetPropertyRequest {
+            delete: false,
+            window: root,
+            property: atom,
+            type_: AtomEnum::STRING.into(),
+            long_offset: 0,
+            long_length: 1024,
+        })?;
+        let reply = dpy.wait_for_reply(cookie)?;
+
+        // missing names fall back to the xkbcommon defaults
+        let mut names = reply
+            .value
+            .split(|b| *b == 0)
+            .map(|name| String::from_utf8_lossy(name).to_string());
+        let rules = names.next().unwrap_or_default();
+        let model = names.next().unwrap_or_default();
+        let layout = names.next().unwrap_or_default();
+        let variant = names.next().unwrap_or_default();
+        let options = names.next().filter(|options| !options.is_empty());
+
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_names(
+            &context,
+            &rules,
+            &model,
+            &layout,
+            &variant,
+            options,
+            xkb::COMPILE_NO_FLAGS,
+        )
+        .ok_or_else(|| DmitriError::Connection("Could not compile keymap".into()))?;
+        let compose =
+            compose::Table::new_from_locale(&context, locale().as_ref(), compose::COMPILE_NO_FLAGS)
+                .ok()
+                .map(|table| compose::State::new(&table, compose::STATE_NO_FLAGS));
+        Ok(Keyboard {
+            state: xkb::State::new(&keymap),
+            compose,
+        })
+    }
+
+    /// Set the modifiers and layout group from the `state` field of a key event. The core
+    /// modifier bits map one-to-one to the real xkb modifiers, and the group is in bits 13-14.
+    pub fn update(&mut self, event_state: u16) {
+        let mods = (event_state & 0xff) as u32;
+        let group = ((event_state >> 13) & 0b11) as u32;
+        self.state.update_mask(mods, 0, 0, 0, 0, group);
+    }
+
+    /// The keysym of a pressed key and the text that it types, empty for keys that don't type
+    /// any. A key that completes a dead key or Compose sequence types the composed text, while
+    /// the keys before it, and one that cancels the sequence, have no keysym and type nothing,
+    /// so that they don't trigger bindings.
+    pub fn press(&mut self, keycode: u8) -> (u32, String) {
+        let keycode = xkb::Keycode::new(keycode as u32);
+        let keysym = self.state.key_get_one_sym(keycode);
+        let typed = || (keysym.raw(), self.state.key_get_utf8(keycode));
+        let (keysym, text) = match &mut self.compose {
+            Some(compose) => match (compose.feed(keysym), compose.status()) {
+                // modifiers don't take part in sequences
+                (compose::FeedResult::Ignored, _) | (_, compose::Status::Nothing) => typed(),
+                (_, compose::Status::Composed) => {
+                    let composed = (
+                        compose
+                            .keysym()
+                            .map_or(keysyms::KEY_NoSymbol, xkb::Keysym::raw),
+                        compose.utf8().unwrap_or_default(),
+                    );
+                    compose.reset();
+                    composed
+                }
+                (_, compose::Status::Composing | compose::Status::Cancelled) => {
+                    (keysyms::KEY_NoSymbol, String::new())
+                }
+            },
+            None => typed(),
+        };
+        if text.chars().any(char::is_control) {
+            return (keysym, String::new());
+        }
+        (keysym, text)
+    }
+
+    /// Forget a sequence that was started and not finished, as when the window is hidden.
+    pub fn reset(&mut self) {
+        if let Some(compose) = &mut self.compose {
+            compose.reset();
+        }
+    }
+}
+
+/// The locale that selects the compose table, from the same variables as `setlocale`.
+fn locale() -> String {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .unwrap_or_else(|| "C".to_string())
+}
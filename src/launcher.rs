@@ -0,0 +1,892 @@
+#[cfg(feature = "wayland")]
+use crate::wayland::WaylandBackend;
+use crate::{
+    backend::{Backend, Event, Placement},
+    bangs,
+    bindings::Action,
+    calc,
+    editor::Editor,
+    error::DmitriError,
+    history::{tie_break, History},
+    items::{Item, ItemStream, Items},
+    links,
+    matcher::Matcher,
+    selection::Selection,
+    text::{FontRenderer, RunOptions},
+    x11::X11Backend,
+};
+use rayon::prelude::*;
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    env,
+    io::{Read, Write},
+    mem,
+    ops::Range,
+    os::{fd::AsFd, unix::net::UnixStream},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long the caret is shown, and then hidden.
+const BLINK: Duration = Duration::from_millis(500);
+
+/// How often streamed items are searched while more arrive.
+const STREAM_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What was confirmed in the window.
+pub enum Output {
+    /// An item or the typed input, empty if the window was closed.
+    Selection(String),
+    /// The result of an arithmetic input.
+    Calculation(String),
+    /// The URL of a web search with a bang, like `!ddg rust`, of a typed link, or a `mailto:` one
+    /// for a typed email address.
+    Url(String),
+    /// The values of the items marked with `--multi`, in the order they were marked.
+    Multiple(Vec<String>),
+    /// An item or the typed input, to run in a terminal emulator.
+    Terminal(String),
+    /// An item or the typed input, for the other action of the mode.
+    Alternate(String),
+    /// An item or the typed input, to run as root.
+    Root(String),
+    /// The next mode was asked for, with `switch_modes`. The window stays open, and the next run
+    /// starts with the same input.
+    NextMode,
+    PrevMode,
+}
+
+/// The picker: a window with an input line that is matched against a list of items.
+pub struct Launcher {
+    backend: Box<dyn Backend>,
+    renderer: FontRenderer<'static>,
+    options: RunOptions,
+    matcher: Box<dyn Matcher>,
+    /// The input that the next run starts with.
+    input: String,
+    /// Whether the last run returned with the window kept open.
+    open: bool,
+}
+
+impl Launcher {
+    /// Open the window on the named backend, `x11` or `wayland`, by default the one of the
+    /// running session.
+    pub fn new(
+        backend: Option<&str>,
+        placement: &Placement,
+        options: RunOptions,
+        matcher: Box<dyn Matcher>,
+    ) -> Result<Launcher, DmitriError> {
+        let rows = options.lines + 1 + options.message.is_some() as u16;
+        let height = (options.fontsize + options.margin * 2) * rows;
+        let mut backend = create_backend(backend, height, placement, options.xrender)?;
+        let (image, pixel_layout) = backend.create_image()?;
+        let mut renderer = FontRenderer::new(image, pixel_layout, &options)?;
+        if backend.draws_commands() {
+            renderer.record_commands();
+        }
+        Ok(Launcher {
+            backend,
+            renderer,
+            options,
+            matcher,
+            input: String::new(),
+            open: false,
+        })
+    }
+
+    pub fn hide(&mut self) -> Result<(), DmitriError> {
+        self.backend.hide()
+    }
+
+    pub fn show(&mut self) -> Result<(), DmitriError> {
+        self.backend.show()
+    }
+
+    /// Whether the last run returned a selection but kept the window open, for the next run to
+    /// continue with the same input.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Start the next run with this input, and search for it right away.
+    pub fn set_input(&mut self, input: &str) {
+        // the input is a single line
+        self.input = input.trim_end_matches('\n').replace('\n', " ");
+    }
+
+    /// Show this prompt before the input from the next run on.
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        self.renderer.set_prompt(prompt);
+    }
+
+    /// Let the user pick one of the items, ranked by the history, until Enter or Escape. The
+    /// window is hidden afterwards, unless the selection was confirmed to keep it open. Matching
+    /// runs on a worker thread, so that typing stays responsive with huge lists, and its results
+    /// are shown as they arrive.
+    pub fn run(
+        &mut self,
+        items: Items<'_>,
+        history: Option<&History>,
+    ) -> Result<Output, DmitriError> {
+        let backend = self.backend.as_mut();
+        let font_render = &mut self.renderer;
+        let options = &self.options;
+        let matcher = self.matcher.as_ref();
+        let next_input = &mut self.input;
+        let open = &mut self.open;
+        let mut editor = Editor::default();
+        editor.set(&mem::take(next_input));
+        let resumed = mem::take(open);
+
+        let (inputs, worker_inputs) = mpsc::channel::<String>();
+        let (worker_results, results) = mpsc::channel::<Search>();
+        // the worker writes to the socket to wake up the event loop when results are ready
+        let (mut wake, worker_wake) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                search_worker(
+                    worker_inputs,
+                    Results {
+                        sender: worker_results,
+                        wake: worker_wake,
+                    },
+                    items,
+                    matcher,
+                    options,
+                    history,
+                )
+            });
+            // dropped when the picker returns, which stops the worker
+            let inputs = inputs;
+
+            let mut matches: Vec<Item> = vec![];
+            let mut highlights: Vec<Vec<usize>> = vec![];
+            let mut selection = Selection::default();
+            // the items marked with `--multi`
+            let mut chosen: Vec<Item> = vec![];
+            // the result of the input itself, shown as the first match
+            let mut synthetic = Synthetic::of(editor.text(), options);
+            // the worker only stops early by panicking, which the scope passes on
+            inputs.send(editor.text().to_string()).ok();
+
+            // the window was just shown, so all of it is presented at first, while a window that
+            // was kept open still shows the input
+            font_render.show_caret();
+            font_render.set_total(0);
+            if !resumed {
+                font_render.invalidate();
+                let damage = render(font_render, options, &editor, &[], &[], &[], None);
+                backend.present(font_render.frame(), &damage)?;
+            }
+
+            // closed when nothing was done for this long, with --timeout
+            let mut deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+            let mut blink = Instant::now() + BLINK;
+            loop {
+                let text = editor.text().to_string();
+                let wakeup = deadline.map_or(blink, |deadline| deadline.min(blink));
+                let event = backend.next_event(wake.as_fd(), Some(wakeup))?;
+                if let Event::Key(_)
+                | Event::Motion(..)
+                | Event::Click(..)
+                | Event::Scroll(_)
+                | Event::Paste(_) = event
+                {
+                    deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+                    // the caret stays while typing
+                    font_render.show_caret();
+                    blink = Instant::now() + BLINK;
+                }
+                match event {
+                    Event::Close => {
+                        backend.hide()?;
+                        return Ok(Output::Selection(String::new()));
+                    }
+                    Event::Timeout
+                        if deadline.is_some_and(|deadline| deadline <= Instant::now()) =>
+                    {
+                        backend.hide()?;
+                        return Ok(Output::Selection(String::new()));
+                    }
+                    Event::Timeout => {
+                        font_render.blink();
+                        blink = Instant::now() + BLINK;
+                    }
+                    Event::Redraw => font_render.invalidate(),
+                    Event::Wake => {
+                        while matches!(wake.read(&mut [0; 64]), Ok(n) if n > 0) {}
+                        // results of inputs that were edited since are outdated
+                        if let Some(search) = results.try_iter().last() {
+                            if search.input == editor.text() {
+                                // more items that arrived for the same input keep the selection
+                                if !search.again {
+                                    selection.clear();
+                                }
+                                matches = search.matches;
+                                highlights = search.highlights;
+                                if let Some(synthetic) = &synthetic {
+                                    matches.insert(0, synthetic.item());
+                                    highlights.insert(0, vec![]);
+                                }
+                                font_render.set_total(search.total + synthetic.is_some() as usize);
+                                // the only choice is confirmed by Enter
+                                if options.only_match && matches.len() == 1 {
+                                    selection.select(0);
+                                }
+                                // or right away with --auto-accept, once something is typed
+                                if options.auto_accept
+                                    && !search.partial
+                                    && synthetic.is_none()
+                                    && matches.len() == 1
+                                    && !editor.text().is_empty()
+                                {
+                                    backend.hide()?;
+                                    let value = matches[0].value().to_string();
+                                    return Ok(Output::Selection(value));
+                                }
+                            }
+                        }
+                    }
+                    Event::Key(key) => match options.keys.action(&key) {
+                        Some(Action::Cancel) => {
+                            backend.hide()?;
+                            return Ok(Output::Selection(String::new()));
+                        }
+                        // with --multi, Ctrl+Enter marks the selected item instead
+                        Some(Action::ConfirmKeepOpen) if options.multi => {
+                            let item = selection
+                                .index()
+                                .filter(|&i| i > 0 || synthetic.is_none())
+                                .and_then(|i| matches.get(i));
+                            if let Some(item) = item {
+                                match chosen.iter().position(|chosen| chosen == item) {
+                                    Some(i) => {
+                                        chosen.remove(i);
+                                    }
+                                    None => chosen.push(item.clone()),
+                                }
+                                selection.next(matches.len());
+                            }
+                        }
+                        Some(Action::Confirm) if !chosen.is_empty() => {
+                            backend.hide()?;
+                            let values = chosen.iter().map(|item| item.value().to_string());
+                            return Ok(Output::Multiple(values.collect()));
+                        }
+                        // only matches can be confirmed with --only-match
+                        Some(
+                            Action::Confirm
+                            | Action::ConfirmKeepOpen
+                            | Action::ConfirmInTerminal
+                            | Action::ConfirmAlternate
+                            | Action::ConfirmAsRoot
+                            | Action::AltConfirm,
+                        ) if options.only_match && selection.index().is_none() => {}
+                        Some(Action::Confirm) => {
+                            backend.hide()?;
+                            return Ok(output(
+                                &editor,
+                                &matches,
+                                selection.index(),
+                                synthetic.clone(),
+                                options.print,
+                            ));
+                        }
+                        Some(Action::ConfirmKeepOpen) => {
+                            *next_input = editor.text().to_string();
+                            *open = true;
+                            return Ok(output(
+                                &editor,
+                                &matches,
+                                selection.index(),
+                                synthetic.clone(),
+                                options.print,
+                            ));
+                        }
+                        Some(Action::ConfirmInTerminal) => {
+                            backend.hide()?;
+                            return Ok(
+                                match output(
+                                    &editor,
+                                    &matches,
+                                    selection.index(),
+                                    synthetic.clone(),
+                                    options.print,
+                                ) {
+                                    Output::Selection(selection) => Output::Terminal(selection),
+                                    output => output,
+                                },
+                            );
+                        }
+                        Some(Action::ConfirmAlternate) => {
+                            backend.hide()?;
+                            return Ok(
+                                match output(
+                                    &editor,
+                                    &matches,
+                                    selection.index(),
+                                    synthetic.clone(),
+                                    options.print,
+                                ) {
+                                    Output::Selection(selection) => Output::Alternate(selection),
+                                    output => output,
+                                },
+                            );
+                        }
+                        Some(Action::ConfirmAsRoot) => {
+                            backend.hide()?;
+                            return Ok(
+                                match output(
+                                    &editor,
+                                    &matches,
+                                    selection.index(),
+                                    synthetic.clone(),
+                                    options.print,
+                                ) {
+                                    Output::Selection(selection) => Output::Root(selection),
+                                    output => output,
+                                },
+                            );
+                        }
+                        Some(action @ (Action::NextMode | Action::PrevMode))
+                            if options.switch_modes =>
+                        {
+                            *next_input = editor.text().to_string();
+                            *open = true;
+                            return Ok(if action == Action::NextMode {
+                                Output::NextMode
+                            } else {
+                                Output::PrevMode
+                            });
+                        }
+                        Some(Action::AltConfirm) if options.only_match => {
+                            backend.hide()?;
+                            return Ok(output(
+                                &editor,
+                                &matches,
+                                selection.index(),
+                                synthetic.clone(),
+                                options.print,
+                            ));
+                        }
+                        Some(Action::AltConfirm) => {
+                            backend.hide()?;
+                            return Ok(Output::Selection(editor.text().to_string()));
+                        }
+                        Some(Action::Complete) => {
+                            let completion = if options.tab_complete {
+                                completion(&editor, &matches, selection.index(), &synthetic)
+                            } else {
+                                None
+                            };
+                            match completion {
+                                Some(completion) => editor.set(&completion),
+                                None if matches.len() > 1 => selection.next(matches.len()),
+                                None => {}
+                            }
+                        }
+                        Some(Action::Next) => selection.next(matches.len()),
+                        Some(Action::Prev) => selection.previous(matches.len()),
+                        // without a list, Left and Right go through the matches after the input
+                        // once the caret is past its end
+                        Some(Action::Left) if options.lines == 0 && selection.index().is_some() => {
+                            selection.previous(matches.len())
+                        }
+                        Some(Action::Right)
+                            if options.lines == 0
+                                && editor.caret() == editor.text().len()
+                                && !matches.is_empty() =>
+                        {
+                            selection.next(matches.len())
+                        }
+                        Some(Action::Left) => editor.left(),
+                        Some(Action::Right) => editor.right(),
+                        Some(Action::Home) => editor.home(),
+                        Some(Action::End) => editor.end(),
+                        Some(Action::Backspace) => editor.backspace(),
+                        Some(Action::Delete) => editor.delete(),
+                        Some(Action::DeleteWord) => editor.delete_word(),
+                        Some(Action::KillToStart) => editor.kill_to_start(),
+                        Some(Action::KillToEnd) => editor.kill_to_end(),
+                        Some(Action::Pick(n)) => {
+                            if let Some(i) = font_render.shown(n - 1) {
+                                backend.hide()?;
+                                return Ok(output(
+                                    &editor,
+                                    &matches,
+                                    Some(i),
+                                    synthetic.clone(),
+                                    options.print,
+                                ));
+                            }
+                        }
+                        Some(Action::Paste) => backend.paste(false)?,
+                        Some(Action::PastePrimary) => backend.paste(true)?,
+                        Some(Action::NextMode | Action::PrevMode) => {}
+                        None => editor.insert(&key.text),
+                    },
+                    Event::Motion(x, y) => {
+                        if let Some(i) = font_render.match_at(x, y) {
+                            selection.select(i);
+                        }
+                    }
+                    Event::Click(x, y) => {
+                        if let Some(i) = font_render.match_at(x, y) {
+                            backend.hide()?;
+                            return Ok(output(
+                                &editor,
+                                &matches,
+                                Some(i),
+                                synthetic.clone(),
+                                options.print,
+                            ));
+                        }
+                    }
+                    Event::Scroll(steps) => {
+                        for _ in 0..steps.unsigned_abs() {
+                            if steps > 0 {
+                                selection.next(matches.len());
+                            } else {
+                                selection.previous(matches.len());
+                            }
+                        }
+                    }
+                    Event::Paste(text) => {
+                        // the input is a single line
+                        editor.insert(&text.trim_end_matches('\n').replace('\n', " "));
+                    }
+                }
+                if editor.text() != text {
+                    selection.clear();
+                    synthetic = Synthetic::of(editor.text(), options);
+                    inputs.send(editor.text().to_string()).ok();
+                }
+                let damage = render(
+                    font_render,
+                    options,
+                    &editor,
+                    &matches,
+                    &highlights,
+                    &chosen,
+                    selection.index(),
+                );
+                if !damage.is_empty() {
+                    backend.present(font_render.frame(), &damage)?;
+                }
+            }
+        })
+    }
+}
+
+/// Draw the input, masked for passwords, and the matches, marking the chosen ones.
+fn render(
+    font_render: &mut FontRenderer,
+    options: &RunOptions,
+    editor: &Editor,
+    matches: &[Item],
+    highlights: &[Vec<usize>],
+    chosen: &[Item],
+    selected: Option<usize>,
+) -> Vec<Range<u16>> {
+    let marked: Vec<bool> = matches.iter().map(|m| chosen.contains(m)).collect();
+    match options.echo_char {
+        Some(echo_char) => {
+            let (masked, caret) = mask(editor, echo_char);
+            font_render.render_text(&masked, Some(caret), matches, highlights, &marked, selected)
+        }
+        None => font_render.render_text(
+            editor.text(),
+            Some(editor.caret()),
+            matches,
+            highlights,
+            &marked,
+            selected,
+        ),
+    }
+}
+
+/// A result of the input itself rather than of the items, shown as the first match.
+#[derive(Clone)]
+enum Synthetic {
+    /// The value of an arithmetic input.
+    Calculation(String),
+    /// A web search with a bang, like `!ddg rust`.
+    WebSearch { query: String, url: String },
+    /// A typed or pasted link, to open in the browser.
+    Link(String),
+    /// A typed email address, to write to.
+    Email(String),
+}
+
+impl Synthetic {
+    /// The result of the input, unless it is hidden for a password.
+    fn of(input: &str, options: &RunOptions) -> Option<Synthetic> {
+        if options.echo_char.is_some() {
+            return None;
+        }
+        calc::eval(input)
+            .map(Synthetic::Calculation)
+            .or_else(|| {
+                let (query, url) = bangs::search(input, &options.bangs)?;
+                Some(Synthetic::WebSearch { query, url })
+            })
+            .or_else(|| links::url(input).map(Synthetic::Link))
+            .or_else(|| links::email(input).map(Synthetic::Email))
+    }
+
+    fn item(&self) -> Item {
+        match self {
+            Synthetic::Calculation(result) => Item::new(format!("= {}", result)),
+            Synthetic::WebSearch { query, url } => {
+                Item::new(format!("Search the web for {}", query))
+                    .with_description(Some(url.clone()))
+            }
+            Synthetic::Link(url) => {
+                Item::new(String::from("Open in browser")).with_description(Some(url.clone()))
+            }
+            Synthetic::Email(address) => {
+                Item::new(String::from("Compose email")).with_description(Some(address.clone()))
+            }
+        }
+    }
+}
+
+/// The selected match, which is the synthetic result if it is first, or else the input. Enter on
+/// the input confirms the result shown for it, unless the selection is `print`ed, where the input
+/// is output as typed like dmenu does.
+fn output(
+    editor: &Editor,
+    matches: &[Item],
+    selected: Option<usize>,
+    synthetic: Option<Synthetic>,
+    print: bool,
+) -> Output {
+    let selected = match (selected, &synthetic) {
+        (None, Some(_)) if !print => Some(0),
+        _ => selected,
+    };
+    match (selected, synthetic) {
+        (Some(0), Some(Synthetic::Calculation(result))) => Output::Calculation(result),
+        (Some(0), Some(Synthetic::WebSearch { url, .. } | Synthetic::Link(url))) => {
+            Output::Url(url)
+        }
+        (Some(0), Some(Synthetic::Email(address))) => Output::Url(format!("mailto:{}", address)),
+        (None, _) => Output::Selection(editor.text().to_string()),
+        (Some(i), _) => Output::Selection(
+            matches
+                .get(i)
+                .map(|item| item.value().to_string())
+                .unwrap_or_else(|| editor.text().to_string()),
+        ),
+    }
+}
+
+/// What Tab completes the input to: the selected match, or else the longest common prefix of the
+/// matches if it is longer than the input.
+fn completion(
+    editor: &Editor,
+    matches: &[Item],
+    selected: Option<usize>,
+    synthetic: &Option<Synthetic>,
+) -> Option<String> {
+    // the synthetic result is shown as the first match
+    match (selected, synthetic) {
+        (Some(0), Some(Synthetic::Calculation(result))) => return Some(result.clone()),
+        (Some(0), Some(_)) => return None,
+        (Some(i), _) => return matches.get(i).map(|m| m.display().to_string()),
+        (None, _) => {}
+    }
+    let matches = &matches[synthetic.is_some() as usize..];
+    let (first, rest) = matches.split_first()?;
+    let prefix = rest.iter().fold(first.display(), |prefix, m| {
+        let m = m.display();
+        let end = prefix
+            .char_indices()
+            .zip(m.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(m.len()), |((i, _), _)| i);
+        &prefix[..end]
+    });
+    (prefix.len() > editor.text().len()).then(|| prefix.to_string())
+}
+
+fn create_backend(
+    name: Option<&str>,
+    height: u16,
+    placement: &Placement,
+    xrender: bool,
+) -> Result<Box<dyn Backend>, DmitriError> {
+    let name = match name {
+        Some(name) => name,
+        None if env::var_os("WAYLAND_DISPLAY").is_some() && cfg!(feature = "wayland") => "wayland",
+        None => "x11",
+    };
+    match name {
+        "x11" => Ok(Box::new(X11Backend::new(height, placement, xrender)?)),
+        #[cfg(feature = "wayland")]
+        "wayland" => Ok(Box::new(WaylandBackend::new(height, placement)?)),
+        _ => Err(DmitriError::Config(format!(
+            "Unsupported backend: {}",
+            name
+        ))),
+    }
+}
+
+/// The input with every character replaced by `echo_char`, and the caret moved accordingly.
+fn mask(editor: &Editor, echo_char: char) -> (String, usize) {
+    let len = editor.text().chars().count();
+    let caret = editor.text()[..editor.caret()].chars().count();
+    (
+        echo_char.to_string().repeat(len),
+        caret * echo_char.len_utf8(),
+    )
+}
+
+/// The matches for an input, with the characters to highlight in each.
+struct Search {
+    input: String,
+    matches: Vec<Item>,
+    highlights: Vec<Vec<usize>>,
+    /// The number of all items that match, of which only the best are kept.
+    total: usize,
+    /// Whether the input was searched again because more items arrived.
+    again: bool,
+    /// Whether more items may still arrive.
+    partial: bool,
+}
+
+/// Where the worker sends its results, each followed by a byte on `wake` that wakes up the event
+/// loop.
+struct Results {
+    sender: Sender<Search>,
+    wake: UnixStream,
+}
+
+impl Results {
+    /// Whether the picker still takes results.
+    fn send(&mut self, search: Search) -> bool {
+        self.sender.send(search).is_ok() && self.wake.write_all(&[0]).is_ok()
+    }
+}
+
+/// Search the items for each input, skipping to the latest one when several arrived meanwhile,
+/// until the picker stops sending. While items are streamed, the last input is searched again
+/// every `STREAM_INTERVAL` if more arrived.
+fn search_worker(
+    inputs: Receiver<String>,
+    mut results: Results,
+    items: Items,
+    matcher: &dyn Matcher,
+    options: &RunOptions,
+    history: Option<&History>,
+) {
+    // the last input searched, the indices of the items that matched it, and the number of items
+    let mut previous: Option<(String, Vec<usize>, usize)> = None;
+    let mut input = String::new();
+    // the number of streamed items that were searched
+    let mut searched = 0;
+    loop {
+        let streaming = items
+            .stream
+            .filter(|stream| !stream.is_finished() || stream.items().len() > searched);
+        let received = match streaming {
+            Some(_) => inputs.recv_timeout(STREAM_INTERVAL),
+            None => inputs.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let again = match received {
+            Ok(received) => {
+                input = inputs.try_iter().last().unwrap_or(received);
+                false
+            }
+            Err(RecvTimeoutError::Timeout)
+                if streaming.is_some_and(|stream| stream.items().len() > searched) =>
+            {
+                true
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        let streamed = items.stream.map(ItemStream::items);
+        let partial = items.stream.is_some_and(|stream| !stream.is_finished());
+        searched = streamed.as_ref().map_or(0, |streamed| streamed.len());
+        let list = streamed.as_deref().map_or(items.list, Vec::as_slice);
+        let dynamic_items = items.dynamic.and_then(|dynamic| dynamic.items(&input));
+        let list = dynamic_items.as_deref().unwrap_or(list);
+        // typing more can only drop matches, while other edits need all items again, as do
+        // items that changed since
+        let candidates = previous
+            .as_ref()
+            .filter(|(previous, _, len)| {
+                *len == list.len()
+                    && matcher.narrows(previous)
+                    && !previous.is_empty()
+                    && input.starts_with(previous.as_str())
+            })
+            .map(|(_, matched, _)| matched.as_slice());
+        let (matches, matched) = if input.is_empty() && options.list_all {
+            let matches = list.iter().take(options.max_matches).cloned().collect();
+            (matches, (0..list.len()).collect())
+        } else {
+            search(
+                &input,
+                list,
+                candidates,
+                matcher,
+                options.precise_wheight,
+                options.max_matches,
+                history,
+            )
+        };
+        // without an input, the matches are from the history
+        let total = matched.len().max(matches.len());
+        previous = dynamic_items
+            .is_none()
+            .then(|| (input.clone(), matched, list.len()));
+        let highlights = matches
+            .iter()
+            .map(|m| matcher.positions(&input, m.display()))
+            .collect();
+        let search = Search {
+            input: input.clone(),
+            matches,
+            highlights,
+            total,
+            again,
+            partial,
+        };
+        if !results.send(search) {
+            return;
+        }
+    }
+}
+
+/// The best `max_matches` items for the input, and the indices of all items that match. Only
+/// the `candidates` are scored if given, in parallel, and each thread keeps only its best items
+/// so that huge lists are never sorted as a whole.
+fn search<'a>(
+    input: &str,
+    items: &'a [Item],
+    candidates: Option<&[usize]>,
+    matcher: &dyn Matcher,
+    precise_wheight: f32,
+    max_matches: usize,
+    history: Option<&History>,
+) -> (Vec<Item>, Vec<usize>) {
+    if input.is_empty() {
+        return match history {
+            Some(history) => (history.top(items, max_matches), vec![]),
+            None => (vec![], vec![]),
+        };
+    }
+
+    let score = |(index, item): (usize, &'a Item)| {
+        // the best of the shown text and the keywords
+        let mut score = item
+            .haystacks()
+            .filter_map(|(haystack, weight)| Some(matcher.score(input, haystack)? * weight))
+            .max_by(f32::total_cmp)?;
+        if let Some(start) = item.display().find(input) {
+            score += precise_wheight / (start as f32 + precise_wheight);
+        }
+        let frecency = history.map_or(0., |history| history.frecency(item.value()));
+        score += frecency / (frecency + 1.);
+        Some(Scored {
+            score,
+            frecency,
+            index,
+            item,
+        })
+    };
+    let (best, matched) = match candidates {
+        Some(candidates) => keep_best(
+            candidates
+                .par_iter()
+                .filter_map(|&index| score((index, &items[index]))),
+            max_matches,
+        ),
+        None => keep_best(items.par_iter().enumerate().filter_map(score), max_matches),
+    };
+    let matches = best
+        .into_sorted_vec()
+        .into_iter()
+        .map(|scored| scored.item.clone())
+        .collect();
+    (matches, matched)
+}
+
+/// A matching item, ordered from the best to the worst: by score, and then by `tie_break`, so
+/// that the order doesn't depend on the order that the items arrived in.
+struct Scored<'a> {
+    score: f32,
+    frecency: f32,
+    index: usize,
+    item: &'a Item,
+}
+
+impl Ord for Scored<'_> {
+    fn cmp(&self, other: &Scored) -> Ordering {
+        other
+            .score
+            .total_cmp(&self.score)
+            .then_with(|| tie_break((self.item, self.frecency), (other.item, other.frecency)))
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for Scored<'_> {
+    fn partial_cmp(&self, other: &Scored) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Scored<'_> {
+    fn eq(&self, other: &Scored) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored<'_> {}
+
+/// The best `max` of the scored items in a heap, and the indices of all of them.
+fn keep_best<'a>(
+    scored: impl ParallelIterator<Item = Scored<'a>>,
+    max: usize,
+) -> (BinaryHeap<Scored<'a>>, Vec<usize>) {
+    scored
+        .fold(
+            || (BinaryHeap::new(), vec![]),
+            |(best, mut matched), scored| {
+                matched.push(scored.index);
+                (push_best(best, scored, max), matched)
+            },
+        )
+        .reduce(
+            || (BinaryHeap::new(), vec![]),
+            |(best, mut matched), (other, other_matched)| {
+                matched.extend(other_matched);
+                let best = other
+                    .into_iter()
+                    .fold(best, |best, scored| push_best(best, scored, max));
+                (best, matched)
+            },
+        )
+}
+
+/// Add the item to the heap, dropping the worst if there are more than `max` items. The heap's
+/// top is the worst item.
+fn push_best<'a>(
+    mut best: BinaryHeap<Scored<'a>>,
+    scored: Scored<'a>,
+    max: usize,
+) -> BinaryHeap<Scored<'a>> {
+    best.push(scored);
+    if best.len() > max {
+        best.pop();
+    }
+    best
+}
@@ -0,0 +1,52 @@
+//! The picker of dmitri, for embedding it with other item sources, matchers and sinks.
+
+pub mod appimage;
+pub mod backend;
+pub mod bangs;
+pub mod bindings;
+pub mod bookmarks;
+mod calc;
+pub mod config;
+pub mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
+pub mod desktop;
+mod editor;
+pub mod emoji;
+pub mod error;
+pub mod files;
+pub mod filter;
+mod gitignore;
+mod glyphs;
+pub mod history;
+mod icons;
+pub mod items;
+mod keyboard;
+mod launcher;
+pub mod links;
+pub mod man;
+pub mod matcher;
+pub mod menu;
+pub mod pass;
+pub mod power;
+pub mod recent;
+pub mod script;
+mod selection;
+pub mod ssh;
+mod startup;
+pub mod supervise;
+pub mod systemd;
+pub mod text;
+pub mod tmux;
+pub mod watch;
+#[cfg(feature = "wayland")]
+mod wayland;
+pub mod windows;
+pub mod x11;
+mod xrender;
+
+pub use error::DmitriError;
+pub use items::{Badge, DynamicSource, Item, ItemSink, ItemSource, ItemStream, Items};
+pub use launcher::{Launcher, Output};
+pub use matcher::Matcher;
+pub use text::FontRenderer as Renderer;
@@ -0,0 +1,53 @@
+use crate::{error::DmitriError, files, items::spawn_detached};
+use std::process;
+
+/// The URL that the input is, if it has a scheme like `https://` or starts with `www.`, which
+/// gets `https://`. It is one word, as pasted links are.
+pub fn url(input: &str) -> Option<String> {
+    let input = input.trim();
+    if input.is_empty() || input.contains(char::is_whitespace) {
+        return None;
+    }
+    if input.starts_with("www.") && input.len() > 4 {
+        return Some(format!("https://{}", input));
+    }
+    let (scheme, rest) = input.split_once("://")?;
+    let valid = scheme
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    (valid && !rest.is_empty()).then(|| input.to_string())
+}
+
+/// The email address that the input is, with or without `mailto:`, if it is one word with an
+/// `@` and a domain with a dot after it.
+pub fn email(input: &str) -> Option<String> {
+    let input = input.trim();
+    let address = input.strip_prefix("mailto:").unwrap_or(input);
+    if address.contains(char::is_whitespace) || address.contains("://") {
+        return None;
+    }
+    let (user, domain) = address.split_once('@')?;
+    let valid = !user.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.');
+    valid.then(|| address.to_string())
+}
+
+/// Open the URL with `xdg-open`, or write to the address of a `mailto:` URL with `xdg-email`.
+pub fn open(url: &str) -> Result<(), DmitriError> {
+    if !url.starts_with("mailto:") {
+        return files::open(url);
+    }
+    spawn_detached(process::Command::new("xdg-email").arg(url)).map_err(|source| {
+        DmitriError::Spawn {
+            command: format!("xdg-email {}", url),
+            source,
+        }
+    })
+}
@@ -1,363 +1,933 @@
-use breadx::{
-    display::{DisplayConnection, DisplayFunctionsExt},
-    prelude::*,
-    protocol::{
-        xproto::{self, EventMask, InputFocus, SetInputFocusRequest, UngrabKeyRequest},
-        Event,
+mod cli;
+
+use cli::Args;
+use dmitri::{
+    appimage::{AppImageSink, AppImageSource},
+    backend::{Length, Placement},
+    bangs,
+    bindings::Bindings,
+    bookmarks::Bookmarks,
+    config::{Chords, Config},
+    daemon::{self, Daemon},
+    desktop::Desktop,
+    emoji::{EmojiSink, EmojiSource},
+    files::{self, FileSink, FileSource, PathCompletion},
+    filter::Filter,
+    history::History,
+    items::{
+        self, ConfirmSource, EmptySource, Environment, ItemSink, ItemSource, JsonSource,
+        PathSource, PrintSink, Root, SpawnSink, StdinSource, Terminal,
     },
+    links,
+    man::{ManSink, ManSource},
+    matcher::{self, Case, CaseFolding},
+    menu::MenuSource,
+    pass::{PassSink, PassSource},
+    power::Power,
+    recent::Recent,
+    script::Script,
+    ssh::{SshSink, SshSource},
+    supervise,
+    systemd::{SystemdSink, SystemdSource},
+    text::{self, FontPattern, RunOptions},
+    tmux::{TmuxSink, TmuxSource},
+    watch::{Changes, Watcher},
+    windows::Windows,
+    x11, DmitriError, DynamicSource, Item, ItemStream, Items, Launcher, Output,
 };
-use breadx_keysyms::{keysyms, KeyboardState};
-use getopts::Options;
-use hex_color::HexColor;
-use rust_fuzzy_search::fuzzy_search_best_n;
-use std::{boxed::Box, env, error::Error, fs, os::unix::prelude::MetadataExt, process};
-
-mod text;
-use text::{FontRenderer, RunOptions};
+use std::{boxed::Box, collections::HashMap, path::PathBuf, process, time::Duration};
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut opts = Options::new();
-    opts.optopt("f", "fontname", "set font name", "mono");
-    opts.optopt("s", "fontsize", "set font size", "32");
-    opts.optopt("m", "margin", "set margin", "7");
-    opts.optopt("c", "color", "set color", "#ff8800");
-    opts.optopt(
-        "p",
-        "precise-wheight",
-        "set additional wheight of subtext matching",
-        "5.0",
-    );
-
-    opts.optflag("h", "help", "print this help menu");
+fn main() {
+    match run() {
+        // with --wait, the exit status of the launched program
+        Ok(true) => process::exit(items::exit_status()),
+        // like dmenu, so that scripts can tell
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("dmitri: {}", err);
+            process::exit(err.exit_code());
+        }
+    }
+}
 
-    let args: Vec<String> = std::env::args().collect();
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => m,
-        Err(f) => panic!("Could not parse arguments: {}", f),
+/// Whether something was selected, or the window was closed without a selection.
+fn run() -> Result<bool, DmitriError> {
+    // with --notify-failures, dmitri runs again as the supervisor of each program it launches
+    if std::env::args().nth(1).as_deref() == Some(supervise::FLAG) {
+        process::exit(supervise::run(
+            &std::env::args().skip(2).collect::<Vec<String>>(),
+        ));
+    }
+    let args = match Args::parse()? {
+        Some(args) => args,
+        None => return Ok(true),
+    };
+    if args.present("show") {
+        return daemon::show().map(|()| true);
+    }
+    let daemon = if args.present("daemon") {
+        if args.present("dmenu")
+            || args.present("json")
+            || args.present("password")
+            || args.present("confirm")
+        {
+            return Err(DmitriError::Config(String::from(
+                "--daemon can't read items from stdin or prompt for passwords or confirmations",
+            )));
+        }
+        if args.present("wait") {
+            return Err(DmitriError::Config(String::from(
+                "--daemon can't wait for the programs it launches",
+            )));
+        }
+        Some(Daemon::listen()?)
+    } else {
+        None
+    };
+    let mut config = Config::load(args.value("config").as_deref())?;
+    if let Some(resources) = x11::resource_manager() {
+        config = config.with_xresources(&resources);
+    }
+    let color = args
+        .value("color")
+        .or(config.color)
+        .and_then(|s| text::parse_color(&s))
+        .unwrap_or((255, 127, 0, 255));
+    let normal_bg = args
+        .value("normal-bg")
+        .or(config.normal_bg)
+        .and_then(|s| text::parse_color(&s))
+        .unwrap_or((0, 0, 0, 255));
+    let normal_fg = args
+        .value("normal-fg")
+        .or(config.normal_fg)
+        .and_then(|s| text::parse_color(&s))
+        .unwrap_or((color.0 / 2, color.1 / 2, color.2 / 2, color.3));
+    // in run mode, where any input runs anyway, Shift+Enter runs it in a terminal instead
+    let run_mode = ![
+        "confirm",
+        "password",
+        "json",
+        "dmenu",
+        "window",
+        "emoji",
+        "ssh",
+        "drun",
+        "appimage",
+        "menu",
+        "power",
+        "mode",
+        "modes",
+        "files",
+        "recent",
+        "bookmarks",
+        "pass",
+        "tmux",
+        "systemd",
+        "man",
+    ]
+    .iter()
+    .any(|mode| args.present(mode));
+    let mut keys = config.keys;
+    if run_mode && !keys.contains_key("confirm-terminal") {
+        keys.insert(
+            String::from("confirm-terminal"),
+            Chords::One(String::from("Shift+Return")),
+        );
+    }
+    // and F1 opens the man page of the selected command
+    if run_mode && !keys.contains_key("confirm-alternate") {
+        keys.insert(
+            String::from("confirm-alternate"),
+            Chords::One(String::from("F1")),
+        );
+    }
+    // and Ctrl+Shift+Enter runs it as root
+    if run_mode && !keys.contains_key("confirm-root") {
+        keys.insert(
+            String::from("confirm-root"),
+            Chords::One(String::from("Ctrl+Shift+Return")),
+        );
+    }
+    let mut set_env = config.env;
+    for variable in args.values("env") {
+        let (name, value) = variable.split_once('=').ok_or_else(|| {
+            DmitriError::Config(format!("--env {} is not like NAME=VALUE", variable))
+        })?;
+        set_env.insert(name.to_string(), value.to_string());
+    }
+    Environment {
+        set: set_env,
+        unset: config
+            .unset_env
+            .into_iter()
+            .chain(args.values("unset-env"))
+            .collect(),
+        clean: args.present("clean-env") || config.clean_env.unwrap_or(false),
+        working_dir: args
+            .value("working-dir")
+            .or(config.working_dir)
+            .map(|dir| files::expand(&dir)),
+        scope: args.present("systemd-scope") || config.systemd_scope.unwrap_or(false),
+        notify_failures: args.present("notify-failures") || config.notify_failures.unwrap_or(false),
+        wait: args.present("wait"),
+    }
+    .apply();
+    let terminal = Terminal {
+        template: args.value("terminal-command").or(config.terminal_command),
     };
-    if matches.opt_present("h") {
-        println!("{}", opts.usage("dmitri: a launcher"));
-        return Ok(());
+    let descriptions = !args.present("no-descriptions") && config.descriptions.unwrap_or(true);
+    let font = args
+        .value("fontname")
+        .or(config.fontname)
+        .map(|s| s.parse::<FontPattern>())
+        .transpose()?;
+    let mode_names: Vec<String> = args
+        .value("modes")
+        .iter()
+        .flat_map(|modes| modes.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect();
+    // with --systemd, Shift+Enter restarts the selected unit
+    let systemd = args.present("systemd") || mode_names.iter().any(|name| name == "systemd");
+    if systemd && !keys.contains_key("confirm-alternate") {
+        keys.insert(
+            String::from("confirm-alternate"),
+            Chords::One(String::from("Shift+Return")),
+        );
     }
+    // with several modes, the prompt is the name of the shown one
+    let prompt = match mode_names.as_slice() {
+        [first, _, ..] => Some(first.clone()),
+        _ => args.value("prompt").or(config.prompt),
+    };
     let options = RunOptions {
-        fontname: matches.opt_str("f"),
-        fontsize: matches
-            .opt_str("s")
+        fontsize: args
+            .value("fontsize")
             .and_then(|s| s.parse::<u16>().ok())
+            .or_else(|| font.as_ref()?.size.map(|size| size.round() as u16))
+            .or(config.fontsize)
             .unwrap_or(32),
-        color: text::color_from_u8(
-            matches
-                .opt_str("c")
-                .and_then(|s| s.parse::<HexColor>().ok())
-                .map(|h| (h.r, h.g, h.b))
-                .unwrap_or((255, 127, 0)),
+        font,
+        color: text::color_from_u8(color),
+        normal_bg: text::color_from_u8(normal_bg),
+        normal_fg: text::color_from_u8(normal_fg),
+        selected_bg: text::color_from_u8(
+            args.value("selected-bg")
+                .or(config.selected_bg)
+                .and_then(|s| text::parse_color(&s))
+                .unwrap_or(color),
         ),
-        margin: matches
-            .opt_str("m")
+        selected_fg: text::color_from_u8(
+            args.value("selected-fg")
+                .or(config.selected_fg)
+                .and_then(|s| text::parse_color(&s))
+                .unwrap_or((0, 0, 0, 255)),
+        ),
+        margin: args
+            .value("margin")
             .and_then(|s| s.parse::<u16>().ok())
+            .or(config.margin)
             .unwrap_or(7),
-        precise_wheight: matches
-            .opt_str("p")
+        precise_wheight: args
+            .value("precise-wheight")
             .and_then(|s| s.parse::<f32>().ok())
+            .or(config.precise_wheight)
             .unwrap_or(5.0),
+        max_matches: args
+            .value("max-matches")
+            .and_then(|s| s.parse::<usize>().ok())
+            .or(config.max_matches)
+            .unwrap_or(20)
+            .max(1),
+        tab_complete: args.present("tab-complete") || config.tab_complete.unwrap_or(false),
+        hints: args.present("hints") || config.hints.unwrap_or(false),
+        counter: args.present("counter") || config.counter.unwrap_or(false),
+        only_match: args.present("only-match")
+            || args.present("confirm")
+            || args.present("menu")
+            || args.present("power")
+            || args.present("pass")
+            || args.present("tmux")
+            || args.present("systemd"),
+        list_all: args.present("confirm")
+            || args.present("menu")
+            || args.present("power")
+            || args.present("mode")
+            || args.present("files")
+            || args.present("recent")
+            || args.present("bookmarks")
+            || args.present("pass")
+            || args.present("tmux")
+            || args.present("systemd")
+            || args.present("man"),
+        switch_modes: mode_names.len() > 1,
+        auto_accept: args.present("auto-accept"),
+        timeout: args
+            .value("timeout")
+            .map(|s| {
+                s.parse::<f32>()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f32(secs).ok())
+                    .ok_or_else(|| DmitriError::Config(format!("Invalid timeout: {}", s)))
+            })
+            .transpose()?,
+        multi: args.present("multi"),
+        icons: args.present("icons") || config.icons.unwrap_or(false),
+        icon_theme: args.value("icon-theme").or(config.icon_theme),
+        descriptions,
+        details: args.present("details") || config.details.unwrap_or(false),
+        // halfway between the text and the background by default
+        description_fg: text::color_from_u8(
+            args.value("description-fg")
+                .or(config.description_fg)
+                .and_then(|s| text::parse_color(&s))
+                .unwrap_or((
+                    ((normal_fg.0 as u16 + normal_bg.0 as u16) / 2) as u8,
+                    ((normal_fg.1 as u16 + normal_bg.1 as u16) / 2) as u8,
+                    ((normal_fg.2 as u16 + normal_bg.2 as u16) / 2) as u8,
+                    normal_fg.3,
+                )),
+        ),
+        lines: args
+            .value("lines")
+            .and_then(|s| s.parse::<u16>().ok())
+            .or(config.lines)
+            .unwrap_or(0),
+        prompt: prompt.clone(),
+        message: args.value("confirm"),
+        echo_char: if args.present("password") {
+            Some(
+                args.value("echo-char")
+                    .or(config.echo_char)
+                    .and_then(|s| s.chars().next())
+                    .unwrap_or('*'),
+            )
+        } else {
+            None
+        },
+        print: args.present("dmenu")
+            || args.present("json")
+            || args.present("mode")
+            || args.present("print")
+            || args.present("multi"),
+        bangs: bangs::with_defaults(config.bangs),
+        xrender: args.present("xrender") || config.xrender.unwrap_or(false),
+        keys: Bindings::new(&keys)?,
     };
 
-    let mut conn = DisplayConnection::connect(None)?;
-
-    let root = conn.default_screen().root;
-    //
-    // let cookie = conn.send_request(GetInputFocusRequest {
-    // ..Default::default()
-    // })?;
-    // let reply = conn.resolve_request(cookie)?;
-    // let focus_window = reply.focus;
-    //
-    // let screens = conn.screens().to_owned();
-    // 'out: for screen in screens {
-    // let tree = screen.root.query_tree_immediate(&mut conn)?;
-    // for child in tree.children.iter() {
-    // if *child == focus_window {
-    // println!("it is child");
-    // root = screen.root;
-    // break 'out;
-    // }
-    // }
-    // }
-
-    let root_geometry = conn.get_geometry_immediate(root)?;
+    let mut script = args
+        .value("mode")
+        .map(|s| s.parse::<Script>())
+        .transpose()?;
+    // --filter replaces the patterns of the config
+    let filters = args.values("filter");
+    let filter = if filters.is_empty() {
+        Filter::new(&config.include, &config.exclude)?
+    } else {
+        Filter::from_args(&filters)?
+    };
+    let settings = ModeSettings {
+        terminal,
+        always_in_terminal: args.present("terminal"),
+        descriptions,
+        appimage_dirs: config.appimage_dirs,
+        power: config.power,
+        files_root: files::expand(&args.value("files").unwrap_or_else(|| String::from("."))),
+        files_max_depth: config.files_max_depth.unwrap_or(16),
+        files_max_count: config.files_max_count.unwrap_or(200_000),
+        browser: config.browser,
+        pass_type: args.present("pass-type") || config.pass_type.unwrap_or(false),
+        aliases: config.aliases,
+        filter,
+        root: Root {
+            prefix: config.root_prefix.unwrap_or_else(|| String::from("#")),
+            template: config.root_command,
+        },
+    };
+    let mut modes = if !mode_names.is_empty() {
+        mode_names
+            .iter()
+            .map(|name| builtin_mode(name, &settings))
+            .collect::<Result<Vec<Mode>, DmitriError>>()?
+    } else if args.present("confirm") {
+        vec![Mode::new(
+            "confirm",
+            Box::new(ConfirmSource),
+            Box::new(PrintSink),
+            None,
+        )]
+    } else if args.present("password") {
+        vec![Mode::new(
+            "password",
+            Box::new(EmptySource),
+            Box::new(PrintSink),
+            None,
+        )]
+    } else if args.present("json") {
+        vec![Mode::new(
+            "json",
+            Box::new(JsonSource),
+            Box::new(PrintSink),
+            None,
+        )]
+    } else if args.present("dmenu") {
+        let source = StdinSource {
+            delimiter: args.value("delimiter"),
+        };
+        vec![Mode::new(
+            "dmenu",
+            Box::new(source),
+            Box::new(PrintSink),
+            None,
+        )]
+    } else if let Some(path) = args.value("menu") {
+        let sink = SpawnSink {
+            terminal: settings.terminal.clone(),
+            always_in_terminal: false,
+            aliases: settings.aliases.clone(),
+            root: settings.root.clone(),
+        };
+        vec![Mode::new(
+            "menu",
+            Box::new(MenuSource { path }),
+            Box::new(sink),
+            None,
+        )]
+    } else if script.is_some() {
+        // the script lists the items of each step, see `run_script`
+        vec![Mode::new(
+            "script",
+            Box::new(EmptySource),
+            Box::new(PrintSink),
+            None,
+        )]
+    } else {
+        let name = [
+            "window",
+            "emoji",
+            "ssh",
+            "drun",
+            "power",
+            "appimage",
+            "files",
+            "recent",
+            "bookmarks",
+            "pass",
+            "tmux",
+            "systemd",
+            "man",
+        ]
+        .into_iter()
+        .find(|name| args.present(name))
+        .unwrap_or("run");
+        vec![builtin_mode(name, &settings)?]
+    };
+    if args.present("print") || args.present("multi") {
+        for mode in &mut modes {
+            mode.sink = Box::new(PrintSink);
+        }
+    }
+    // the daemon keeps the items up to date as the directories they are found in change
+    if daemon.is_some() {
+        for mode in &mut modes {
+            mode.watch();
+        }
+    }
+    // the other modes are loaded when they are first shown
+    modes[0].load()?;
 
-    let height = options.fontsize + (options.margin * 2) as u16;
+    let case = if args.present("i") {
+        Case::Insensitive
+    } else {
+        Case::from_name(
+            args.value("case")
+                .or(config.case)
+                .as_deref()
+                .unwrap_or("smart"),
+        )?
+    };
+    let matcher = CaseFolding {
+        matcher: matcher::from_name(
+            args.value("matcher")
+                .or(config.matcher)
+                .as_deref()
+                .unwrap_or("fuzzy"),
+        )?,
+        case,
+    };
 
-    let wid = conn.generate_xid()?;
-    conn.create_window_checked(
-        0, // depth
-        wid,
-        root,                // parent
-        0,                   // x
-        0,                   // y
-        root_geometry.width, // width
-        height,              // height
-        0,                   // border width
-        xproto::WindowClass::COPY_FROM_PARENT,
-        0, // visual
-        xproto::CreateWindowAux::new()
-            .background_pixel(conn.default_screen().black_pixel)
-            .override_redirect(1)
-            .event_mask(
-                EventMask::EXPOSURE
-                    | EventMask::KEY_PRESS
-                    | EventMask::KEY_RELEASE
-                    | EventMask::VISIBILITY_CHANGE
-                    | EventMask::FOCUS_CHANGE,
-            ),
+    let placement = Placement {
+        monitor: args
+            .value("monitor")
+            .and_then(|s| s.parse::<usize>().ok())
+            .or(config.monitor),
+        bottom: args.present("bottom") || config.bottom.unwrap_or(false),
+        x: args
+            .value("x")
+            .or(config.x)
+            .map(|s| s.parse::<Length>())
+            .transpose()?,
+        y: args
+            .value("y")
+            .or(config.y)
+            .map(|s| s.parse::<Length>())
+            .transpose()?,
+        width: args
+            .value("width")
+            .or(config.width)
+            .map(|s| s.parse::<Length>())
+            .transpose()?,
+    };
+    let mut launcher = Launcher::new(
+        args.value("backend").or(config.backend).as_deref(),
+        &placement,
+        options,
+        Box::new(matcher),
     )?;
 
-    conn.map_window(wid)?;
-    // window.set_title(&mut conn, "Hello World!")?;
-
-    conn.send_void_request(
-        SetInputFocusRequest {
-            focus: wid,
-            revert_to: InputFocus::PARENT,
-            ..Default::default()
+    let input = args.value("input");
+    if let Some(input) = &input {
+        launcher.set_input(input);
+    }
+    if args.present("confirm") {
+        let output = launcher.run(modes[0].items(), None)?;
+        // like a selection, so that `dmitri --confirm "..." && poweroff` only runs on yes
+        return Ok(matches!(output, Output::Selection(answer) if answer == "yes"));
+    }
+    // the mode that is shown, of `--modes`
+    let mut current = 0;
+    if let (Some(script), None) = (&mut script, &daemon) {
+        return run_script(&mut launcher, script, prompt.as_deref());
+    }
+    let daemon = match daemon {
+        Some(daemon) => daemon,
+        None => loop {
+            let output = run_modes(&mut launcher, &mut modes, &mut current, prompt.as_deref())?;
+            let mode = &mut modes[current];
+            if !launcher.is_open() {
+                return finish(output, mode.sink.as_mut(), mode.history.as_mut());
+            }
+            // the window stays open for more, so a failure only concerns this selection
+            if let Err(err) = finish(output, mode.sink.as_mut(), mode.history.as_mut()) {
+                eprintln!("dmitri: {}", err);
+            }
         },
-        true,
-    )?;
+    };
 
-    match run(&mut conn, wid, root, options) {
-        Err(err) => {
-            eprintln!("Error: {}", err);
-            Err(err)
+    launcher.hide()?;
+    loop {
+        if !launcher.is_open() {
+            daemon.wait_for_show()?;
+            launcher.show()?;
+            if let Some(input) = &input {
+                launcher.set_input(input);
+            }
         }
-        Ok(output) => {
-            if !output.is_empty() {
-                return spawn(output);
+        if let Some(script) = &mut script {
+            if let Err(err) = run_script(&mut launcher, script, prompt.as_deref()) {
+                eprintln!("dmitri: {}", err);
             }
-            Ok(())
+            continue;
+        }
+        let output = run_modes(&mut launcher, &mut modes, &mut current, prompt.as_deref())?;
+        let mode = &mut modes[current];
+        if let Err(err) = finish(output, mode.sink.as_mut(), mode.history.as_mut()) {
+            eprintln!("dmitri: {}", err);
         }
     }
 }
 
-fn run<Dpy: Display>(
-    connection: &mut Dpy,
-    wid: u32,
-    root: u32,
-    options: RunOptions,
-) -> Result<String, Box<dyn Error>> {
-    let gc = connection.generate_xid()?;
-    connection.create_gc_checked(
-        gc,
-        wid,
-        xproto::CreateGCAux::new()
-            .foreground(connection.default_screen().black_pixel)
-            .graphics_exposures(0)
-            .line_width(10),
-    )?;
+/// Where the items of a mode come from, what happens to the selection, and the history that ranks
+/// the items.
+struct Mode {
+    name: String,
+    source: Box<dyn ItemSource>,
+    sink: Box<dyn ItemSink>,
+    history: Option<History>,
+    /// Read from the source when the mode is first shown.
+    items: Option<Vec<Item>>,
+    /// Searched instead of the items for the inputs it has items for.
+    dynamic: Option<Box<dyn DynamicSource>>,
+    /// Searched instead of the items once the mode is shown, as they arrive.
+    stream: Option<ItemStream>,
+    /// With `--daemon`, what changed in the directories that the items are found in since they
+    /// were read.
+    watcher: Option<Watcher>,
+}
 
-    let geometry = connection.get_geometry_immediate(wid)?;
-    let mut font_render = FontRenderer::new(
-        connection,
-        geometry.depth,
-        geometry.width as _,
-        geometry.height as _,
-        &options,
-    )?;
-    let mut input = String::new();
+impl Mode {
+    fn new(
+        name: &str,
+        source: Box<dyn ItemSource>,
+        sink: Box<dyn ItemSink>,
+        history: Option<History>,
+    ) -> Mode {
+        Mode {
+            name: name.to_string(),
+            source,
+            sink,
+            history,
+            items: None,
+            dynamic: None,
+            stream: None,
+            watcher: None,
+        }
+    }
 
-    let mut matches: Vec<String> = vec![];
-    let mut matches_i: Option<usize> = None;
+    /// Watch the directories that the items are found in, to update the items when files in
+    /// them change.
+    fn watch(&mut self) {
+        let dirs = self.source.watched();
+        if dirs.is_empty() {
+            return;
+        }
+        match Watcher::start(&dirs) {
+            Ok(watcher) => self.watcher = Some(watcher),
+            Err(err) => eprintln!("Could not watch the directories of {}: {}", self.name, err),
+        }
+    }
 
-    font_render.render_text(connection, wid, gc, "█", &matches, matches_i)?;
+    fn load(&mut self) -> Result<(), DmitriError> {
+        self.update();
+        if self.items.is_none() {
+            // what changed until now is in the items that are read
+            if let Some(watcher) = &self.watcher {
+                watcher.take();
+            }
+            self.items = Some(self.source.items()?);
+            self.stream = self.source.stream();
+        }
+        Ok(())
+    }
 
-    // set up an exit strategy
-    let wm_protocols = connection.intern_atom(false, "WM_PROTOCOLS")?;
-    let wm_delete_window = connection.intern_atom(false, "WM_DELETE_WINDOW")?;
-    connection.flush()?;
-    let wm_protocols = connection.wait_for_reply(wm_protocols)?.atom;
-    let wm_delete_window = connection.wait_for_reply(wm_delete_window)?.atom;
+    /// Apply what changed since the items were read, or have them read again when the source
+    /// can't update them or anything may have changed. Streamed items are updated once they all
+    /// arrived.
+    fn update(&mut self) {
+        let (watcher, items) = match (&self.watcher, &mut self.items) {
+            (Some(watcher), Some(items)) => (watcher, items),
+            _ => return,
+        };
+        if self
+            .stream
+            .as_ref()
+            .is_some_and(|stream| !stream.is_finished())
+        {
+            return;
+        }
+        let updated = match watcher.take() {
+            None => return,
+            Some(Changes::Files(changed)) => match &self.stream {
+                Some(stream) => stream.update(|items| self.source.update(items, &changed)),
+                None => self.source.update(items, &changed),
+            },
+            Some(Changes::All) => false,
+        };
+        if !updated {
+            self.items = None;
+            self.stream = None;
+            // for directories that were added or removed
+            self.watch();
+        }
+    }
 
-    connection.change_property(
-        xproto::PropMode::REPLACE,
-        wid,
-        wm_protocols,
-        xproto::AtomEnum::ATOM.into(),
-        32,
-        1,
-        &wm_delete_window,
-    )?;
+    fn items(&self) -> Items<'_> {
+        Items {
+            list: self.items.as_deref().unwrap_or_default(),
+            stream: self.stream.as_ref(),
+            dynamic: self.dynamic.as_deref(),
+        }
+    }
+}
 
-    let mut keystate = KeyboardState::new(connection)?;
-    let mut is_shift = false;
+/// What the built-in modes are set up with.
+struct ModeSettings {
+    terminal: Terminal,
+    always_in_terminal: bool,
+    descriptions: bool,
+    appimage_dirs: Vec<String>,
+    power: HashMap<String, String>,
+    files_root: PathBuf,
+    files_max_depth: usize,
+    files_max_count: usize,
+    browser: Option<String>,
+    pass_type: bool,
+    aliases: HashMap<String, String>,
+    filter: Filter,
+    root: Root,
+}
 
-    let executables = build_path()?;
+/// A built-in mode by its name in `--modes`.
+fn builtin_mode(name: &str, settings: &ModeSettings) -> Result<Mode, DmitriError> {
+    let terminal = settings.terminal.clone();
+    Ok(match name {
+        // inputs like paths complete files instead of executables
+        "run" => Mode {
+            dynamic: Some(Box::new(PathCompletion)),
+            ..Mode::new(
+                name,
+                Box::new(PathSource {
+                    descriptions: settings.descriptions,
+                    aliases: settings.aliases.clone(),
+                    filter: settings.filter.clone(),
+                }),
+                Box::new(SpawnSink {
+                    terminal,
+                    always_in_terminal: settings.always_in_terminal,
+                    aliases: settings.aliases.clone(),
+                    root: settings.root.clone(),
+                }),
+                Some(History::load("history")),
+            )
+        },
+        "drun" => {
+            let desktop = Desktop::load(terminal, settings.filter.clone());
+            Mode::new(
+                name,
+                Box::new(desktop.clone()),
+                Box::new(desktop),
+                Some(History::load("history-drun")),
+            )
+        }
+        "window" => {
+            let windows = Windows::load()?;
+            Mode::new(name, Box::new(windows.clone()), Box::new(windows), None)
+        }
+        "ssh" => Mode::new(
+            name,
+            Box::new(SshSource),
+            Box::new(SshSink { terminal }),
+            Some(History::load("history-ssh")),
+        ),
+        "emoji" => Mode::new(
+            name,
+            Box::new(EmojiSource),
+            Box::new(EmojiSink),
+            Some(History::load("history-emoji")),
+        ),
+        "appimage" => Mode::new(
+            name,
+            Box::new(AppImageSource {
+                dirs: settings.appimage_dirs.clone(),
+            }),
+            Box::new(AppImageSink),
+            Some(History::load("history-appimage")),
+        ),
+        "power" => {
+            let power = Power::new(settings.power.clone())?;
+            Mode::new(name, Box::new(power.clone()), Box::new(power), None)
+        }
+        "files" => Mode::new(
+            name,
+            Box::new(FileSource {
+                root: settings.files_root.clone(),
+                max_depth: settings.files_max_depth,
+                max_count: settings.files_max_count,
+            }),
+            Box::new(FileSink),
+            Some(History::load("history-files")),
+        ),
+        "recent" => {
+            let recent = Recent::load()?;
+            Mode::new(name, Box::new(recent.clone()), Box::new(recent), None)
+        }
+        "bookmarks" => {
+            let bookmarks = Bookmarks::load(settings.browser.clone());
+            Mode::new(
+                name,
+                Box::new(bookmarks.clone()),
+                Box::new(bookmarks),
+                Some(History::load("history-bookmarks")),
+            )
+        }
+        "pass" => Mode::new(
+            name,
+            Box::new(PassSource),
+            Box::new(PassSink {
+                type_password: settings.pass_type,
+            }),
+            Some(History::load("history-pass")),
+        ),
+        "tmux" => Mode::new(
+            name,
+            Box::new(TmuxSource),
+            Box::new(TmuxSink { terminal }),
+            Some(History::load("history-tmux")),
+        ),
+        "systemd" => Mode::new(
+            name,
+            Box::new(SystemdSource),
+            Box::new(SystemdSink),
+            Some(History::load("history-systemd")),
+        ),
+        "man" => Mode::new(
+            name,
+            Box::new(ManSource {
+                descriptions: settings.descriptions,
+            }),
+            Box::new(ManSink { terminal }),
+            Some(History::load("history-man")),
+        ),
+        _ => return Err(DmitriError::Config(format!("Unknown mode: {}", name))),
+    })
+}
 
+/// Let the user pick one of the items of the current mode, switching to the next or previous
+/// mode as asked, and confirm the selection if its sink asks to. The prompt shows the name of the
+/// mode when there are several.
+fn run_modes(
+    launcher: &mut Launcher,
+    modes: &mut [Mode],
+    current: &mut usize,
+    prompt: Option<&str>,
+) -> Result<Output, DmitriError> {
+    let count = modes.len();
     loop {
-        let ev = match connection.wait_for_event() {
-            Ok(ev) => ev,
-            Err(e) => {
-                eprintln!("Program closed with error: {:?}", e);
-                process::exit(1);
+        let mode = &mut modes[*current];
+        mode.load()?;
+        let output = launcher.run(mode.items(), mode.history.as_ref())?;
+        *current = match output {
+            Output::NextMode => (*current + 1) % count,
+            Output::PrevMode => (*current + count - 1) % count,
+            output => {
+                let prompt = if count > 1 {
+                    Some(mode.name.as_str())
+                } else {
+                    prompt
+                };
+                return confirmed(launcher, output, mode.sink.as_ref(), prompt);
             }
         };
-
-        match ev {
-            Event::ClientMessage(cme) => {
-                if cme.data.as_data32()[0] == wm_delete_window {
-                    process::exit(0);
-                }
-            }
-            Event::Expose(_) => {
-                font_render.render_text(connection, wid, gc, &input, &matches, matches_i)?;
-            }
-            Event::FocusOut(_e) => {
-                connection.send_void_request(
-                    SetInputFocusRequest {
-                        focus: wid,
-                        revert_to: InputFocus::PARENT,
-                        ..Default::default()
-                    },
-                    true,
-                )?;
-            }
-            Event::KeyPress(kp) => {
-                let sym = keystate.symbol(connection, kp.detail, 0)?;
-                match sym {
-                    keysyms::KEY_Escape => {
-                        connection.send_void_request(
-                            UngrabKeyRequest {
-                                grab_window: root,
-                                ..Default::default()
-                            },
-                            true,
-                        )?;
-                        connection.unmap_window(wid)?;
-                        // window.free(conn)?;
-                        return Ok(String::new());
-                    }
-                    keysyms::KEY_Return => {
-                        let output: String = match matches_i {
-                            None => input,
-                            Some(i) => matches.get(i).map(String::to_owned).unwrap_or(input),
-                        };
-                        return Ok(output);
-                    }
-                    keysyms::KEY_Tab => {
-                        if matches.len() > 1 {
-                            match matches_i {
-                                None => {
-                                    if !is_shift {
-                                        matches_i = Some(0);
-                                    } else {
-                                        matches_i = Some(matches.len() - 1);
-                                    }
-                                }
-                                Some(i) => {
-                                    if !is_shift {
-                                        match matches.get(i + 1) {
-                                            Some(_) => matches_i = Some(i + 1),
-                                            None => matches_i = None,
-                                        }
-                                    } else if i > 0 && matches.get(i - 1).is_some() {
-                                        matches_i = Some(i - 1);
-                                    } else {
-                                        matches_i = None;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    keysyms::KEY_BackSpace => {
-                        if !input.is_empty() {
-                            input = input[0..input.len() - 1].to_string();
-                            matches_i = None;
-                            matches = search(&input, &executables, options.precise_wheight);
-                        }
-                    }
-                    keysyms::KEY_Shift_L | keysyms::KEY_Shift_R => {
-                        is_shift = true;
-                    }
-                    k => {
-                        if let Some(mut keycode_char) = char::from_u32(k) {
-                            keycode_char = keycode_char
-                                .to_lowercase()
-                                .next()
-                                .ok_or("lowercase keycode char")?;
-                            input.push(keycode_char);
-                            matches_i = None;
-                            matches = search(&input, &executables, options.precise_wheight);
-                        }
-                    }
-                }
-                font_render.render_text(connection, wid, gc, &input, &matches, matches_i)?;
-            }
-            Event::KeyRelease(kr) => {
-                let sym = keystate.symbol(connection, kr.detail, 0)?;
-                match sym {
-                    keysyms::KEY_Shift_L | keysyms::KEY_Shift_R => {
-                        is_shift = false;
-                    }
-                    _ => (),
-                }
-            }
-            _ => (),
-        }
+        launcher.set_prompt(Some(modes[*current].name.clone()));
     }
 }
 
-fn build_path() -> Result<Vec<String>, Box<dyn Error>> {
-    let mut executables: Vec<String> = vec![];
-
-    let path_var = env::var("PATH")?;
-    let paths = path_var.split(':');
-    for path in paths {
-        if let Ok(dir) = fs::read_dir(path) {
-            for entry in dir {
-                let entry = entry?;
-
-                let os_filename = entry.file_name();
-                let filename = os_filename.to_string_lossy().to_string();
-                if executables.contains(&filename) {
-                    continue;
-                }
-                let pathbuf = entry.path();
-                let metadata = fs::metadata(&pathbuf)?;
-                if !metadata.is_file() {
-                    continue;
-                }
-                if metadata.mode() & 0o111 != 0 {
-                    executables.push(filename);
-                }
+/// Go through the steps of a script mode, until the script lists no more items or nothing is
+/// selected. The prompt of each step is the one the script sets, or else `prompt` or the name of
+/// the mode. Whether something was selected.
+fn run_script(
+    launcher: &mut Launcher,
+    script: &mut Script,
+    prompt: Option<&str>,
+) -> Result<bool, DmitriError> {
+    let mut step = script.start()?;
+    let mut selected = false;
+    loop {
+        launcher.set_prompt(Some(
+            step.prompt
+                .take()
+                .or_else(|| prompt.map(String::from))
+                .unwrap_or_else(|| script.name.clone()),
+        ));
+        let selections = match launcher.run(step.items.as_slice().into(), None)? {
+            Output::Selection(selection)
+            | Output::Terminal(selection)
+            | Output::Alternate(selection)
+            | Output::Root(selection)
+                if selection.is_empty() =>
+            {
+                return Ok(selected)
             }
+            Output::Selection(selection)
+            | Output::Terminal(selection)
+            | Output::Alternate(selection)
+            | Output::Root(selection)
+            | Output::Calculation(selection) => vec![selection],
+            Output::Multiple(selections) => selections,
+            Output::Url(url) => return links::open(&url).map(|()| true),
+            Output::NextMode | Output::PrevMode => continue,
+        };
+        selected = true;
+        // with --multi, the script gets each of the marked items, and the last one goes on
+        let matched: Vec<bool> = selections
+            .iter()
+            .map(|selection| step.items.iter().any(|item| item.value() == selection))
+            .collect();
+        for (selection, matched) in selections.iter().zip(matched) {
+            step = script.select(selection, matched)?;
+        }
+        if step.items.is_empty() {
+            return Ok(true);
         }
+        launcher.show()?;
     }
-    executables.sort();
-    Ok(executables)
 }
 
-fn search(input: &String, executables: &[String], precise_wheight: f32) -> Vec<String> {
-    if input.is_empty() {
-        return vec![];
-    }
-
-    let list = executables
-        .iter()
-        .map(String::as_ref)
-        .collect::<Vec<&str>>();
+/// Ask the question of the sink about the selection, if it has one, in place of the prompt, and
+/// drop the selection unless the answer is yes.
+fn confirmed(
+    launcher: &mut Launcher,
+    output: Output,
+    sink: &dyn ItemSink,
+    prompt: Option<&str>,
+) -> Result<Output, DmitriError> {
+    let question = match &output {
+        Output::Selection(selection)
+        | Output::Terminal(selection)
+        | Output::Alternate(selection)
+        | Output::Root(selection) => sink.confirmation(selection),
+        _ => None,
+    };
+    let question = match question {
+        Some(question) => question,
+        None => return Ok(output),
+    };
+    launcher.set_prompt(Some(question));
+    launcher.show()?;
+    let answers = ConfirmSource.items()?;
+    let answer = launcher.run(answers.as_slice().into(), None);
+    launcher.set_prompt(prompt.map(String::from));
+    Ok(match answer? {
+        Output::Selection(answer) if answer == "yes" => output,
+        _ => Output::Selection(String::new()),
+    })
+}
 
-    let mut res: Vec<(&str, f32)> = fuzzy_search_best_n(input, &list, 20);
-    for (entry, i) in &mut res {
-        if let Some(start) = entry.find(input) {
-            *i += (precise_wheight / (start as f32 + precise_wheight)) as f32;
+/// Record the selection in the history and hand it to the sink. Whether there was a selection.
+fn finish(
+    output: Output,
+    sink: &mut dyn ItemSink,
+    history: Option<&mut History>,
+) -> Result<bool, DmitriError> {
+    // the method of the sink that each selection goes to
+    type Method = fn(&mut dyn ItemSink, String) -> Result<(), DmitriError>;
+    let (selections, output): (_, Method) = match output {
+        Output::Selection(output)
+        | Output::Terminal(output)
+        | Output::Alternate(output)
+        | Output::Root(output)
+            if output.is_empty() =>
+        {
+            return Ok(false)
+        }
+        Output::Selection(output) => (vec![output], |sink, selection| sink.output(selection)),
+        Output::Terminal(output) => (vec![output], |sink, selection| {
+            sink.output_in_terminal(selection)
+        }),
+        Output::Alternate(output) => (vec![output], |sink, selection| {
+            sink.output_alternate(selection)
+        }),
+        Output::Root(output) => (vec![output], |sink, selection| {
+            sink.output_as_root(selection)
+        }),
+        Output::Multiple(items) => (items, |sink, selection| sink.output(selection)),
+        Output::NextMode | Output::PrevMode => return Ok(false),
+        Output::Calculation(result) => return sink.calculation(result).map(|()| true),
+        Output::Url(url) => return sink.open_url(url).map(|()| true),
+    };
+    if let Some(history) = history {
+        for selection in &selections {
+            history.record(selection);
+        }
+        if let Err(err) = history.save() {
+            eprintln!("Could not save history: {}", err);
         }
     }
-    res.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
-
-    return res.iter().map(|(s, _)| String::from(*s)).collect();
-}
-
-fn spawn(output: String) -> Result<(), Box<dyn Error>> {
-    if let Err(err) = process::Command::new(output).spawn() {
-        eprintln!("Command error: {}", err);
+    for selection in selections {
+        output(sink, selection)?;
     }
-    Ok(())
+    Ok(true)
 }
@@ -0,0 +1,162 @@
+use crate::{
+    error::DmitriError,
+    history::cache_dir,
+    items::{spawn_detached, Item, ItemSink, ItemSource, Terminal},
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, fs,
+    os::unix::fs::MetadataExt,
+    path::PathBuf,
+    process,
+};
+
+/// Name of the file in the cache directory with the descriptions of all man pages. Its first
+/// line is the newest mtime of the section directories when it was written, so that it is
+/// written again once pages were installed or removed.
+const MAN_CACHE: &str = "man";
+
+/// Extensions of compressed man pages, which come after the section.
+const COMPRESSIONS: [&str; 6] = ["gz", "bz2", "xz", "zst", "lzma", "Z"];
+
+/// The man pages of the directories of `manpath`, as `name(section)`, with the descriptions
+/// that `whatis` gives if `descriptions` is set. Pages in other languages are left out.
+pub struct ManSource {
+    pub descriptions: bool,
+}
+
+impl ItemSource for ManSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        // the section directories are man1, man3p and so on, with the pages like ls.1.gz
+        let mut pages = BTreeSet::new();
+        let mut newest = 0;
+        for dir in man_dirs() {
+            let sections = match fs::read_dir(&dir) {
+                Ok(sections) => sections,
+                Err(_) => continue,
+            };
+            for section in sections.flatten() {
+                if !section.file_name().to_string_lossy().starts_with("man") {
+                    continue;
+                }
+                if let Ok(metadata) = section.metadata() {
+                    newest = newest.max(metadata.mtime());
+                }
+                for page in fs::read_dir(section.path()).into_iter().flatten().flatten() {
+                    let file_name = page.file_name().to_string_lossy().to_string();
+                    let file_name = match file_name.rsplit_once('.') {
+                        Some((rest, extension)) if COMPRESSIONS.contains(&extension) => rest,
+                        _ => &file_name,
+                    };
+                    if let Some((name, section)) = file_name.rsplit_once('.') {
+                        pages.insert((name.to_string(), section.to_string()));
+                    }
+                }
+            }
+        }
+        let mut descriptions = if self.descriptions {
+            whatis(newest)
+        } else {
+            HashMap::new()
+        };
+        Ok(pages
+            .into_iter()
+            .map(|(name, section)| {
+                let page = format!("{}({})", name, section);
+                let description = descriptions.remove(&page);
+                Item::new(page).with_description(description)
+            })
+            .collect())
+    }
+}
+
+/// The directories of man pages: those of `manpath`, or else of `$MANPATH` or the usual ones.
+fn man_dirs() -> Vec<PathBuf> {
+    let manpath = process::Command::new("manpath")
+        .stderr(process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .or_else(|| env::var("MANPATH").ok())
+        .filter(|manpath| !manpath.is_empty())
+        .unwrap_or_else(|| String::from("/usr/local/share/man:/usr/share/man"));
+    manpath
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// The one line descriptions of the man pages of all sections, by `name(section)`. They are read
+/// from the cache, unless the section directories changed since `newest`.
+fn whatis(newest: i64) -> HashMap<String, String> {
+    let cache_path = cache_dir().join(MAN_CACHE);
+    let cached = fs::read_to_string(&cache_path).ok().and_then(|contents| {
+        let (mtime, descriptions) = contents.split_once('\n')?;
+        (mtime.parse() == Ok(newest)).then(|| descriptions.to_string())
+    });
+    let contents = match cached {
+        Some(contents) => contents,
+        None => {
+            // lines like `ls (1)               - list directory contents`, nothing without man-db
+            let output = process::Command::new("whatis")
+                .args(["-w", "*"])
+                .stderr(process::Stdio::null())
+                .output()
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+                .unwrap_or_default();
+            let mut contents = String::new();
+            for line in output.lines() {
+                if let Some((page, description)) = line.split_once(" - ") {
+                    let page = page.trim().replacen(" (", "(", 1);
+                    contents.push_str(&format!("{}\t{}\n", page, description.trim()));
+                }
+            }
+            let written = cache_path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&cache_path, format!("{}\n{}", newest, contents)));
+            if let Err(err) = written {
+                eprintln!("Could not write {}: {}", cache_path.display(), err);
+            }
+            contents
+        }
+    };
+    contents
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(page, description)| (page.to_string(), description.to_string()))
+        .collect()
+}
+
+/// Opens the selected man page in a terminal. Pages are selected as `name(section)`, and a typed
+/// name opens the first page of that name.
+pub struct ManSink {
+    pub terminal: Terminal,
+}
+
+impl ItemSink for ManSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        open(&self.terminal, &selection)
+    }
+}
+
+/// Open the man page, like `ls(1)` or `ls`, in a terminal.
+pub fn open(terminal: &Terminal, page: &str) -> Result<(), DmitriError> {
+    let mut man = vec![String::from("man")];
+    match page
+        .strip_suffix(')')
+        .and_then(|page| page.rsplit_once('('))
+    {
+        Some((name, section)) => man.extend([section.to_string(), name.to_string()]),
+        None => man.push(page.to_string()),
+    }
+    let argv = terminal.command(&man);
+    let mut command = process::Command::new(&argv[0]);
+    command.args(&argv[1..]);
+    spawn_detached(&mut command).map_err(|source| DmitriError::Spawn {
+        command: argv.join(" "),
+        source,
+    })
+}
@@ -0,0 +1,483 @@
+use crate::error::DmitriError;
+use rust_fuzzy_search::fuzzy_compare;
+use std::{borrow::Cow, boxed::Box, iter};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Decides whether an item matches the input and how well. Matching runs on another thread than
+/// the window.
+pub trait Matcher: Sync {
+    /// Score `item` against `input`, roughly between 0 and 1 where higher is better, or `None` if
+    /// it doesn't match at all.
+    fn score(&self, input: &str, item: &str) -> Option<f32>;
+
+    /// Indices of the characters of `item` that match the input, to highlight them.
+    fn positions(&self, input: &str, item: &str) -> Vec<usize>;
+
+    /// Whether an item that doesn't match `input` can't match any longer input that starts with
+    /// it, so that those are only matched against the items that matched before.
+    fn narrows(&self, _input: &str) -> bool {
+        false
+    }
+}
+
+/// The matcher by its name. All but `prefix` match each word of the input on its own, see
+/// `Query`.
+pub fn from_name(name: &str) -> Result<Box<dyn Matcher>, DmitriError> {
+    match name {
+        "fuzzy" => Ok(Box::new(Query(Box::new(Fuzzy)))),
+        "fzf" => Ok(Box::new(Query(Box::new(Subsequence)))),
+        "prefix" => Ok(Box::new(Prefix)),
+        "substring" => Ok(Box::new(Query(Box::new(Substring)))),
+        _ => Err(DmitriError::Config(format!("Unknown matcher: {}", name))),
+    }
+}
+
+/// When to ignore the case of the input and the items.
+#[derive(Clone, Copy)]
+pub enum Case {
+    /// Ignore case unless the input contains uppercase characters.
+    Smart,
+    Insensitive,
+    Sensitive,
+}
+
+impl Case {
+    pub fn from_name(name: &str) -> Result<Case, DmitriError> {
+        match name {
+            "smart" => Ok(Case::Smart),
+            "insensitive" => Ok(Case::Insensitive),
+            "sensitive" => Ok(Case::Sensitive),
+            _ => Err(DmitriError::Config(format!("Unknown case mode: {}", name))),
+        }
+    }
+}
+
+/// Lowercases the input and the items for another matcher, depending on the case mode, and
+/// strips them of diacritics, so that `uberwriter` finds `Überwriter`.
+pub struct CaseFolding {
+    pub matcher: Box<dyn Matcher>,
+    pub case: Case,
+}
+
+impl CaseFolding {
+    fn fold(&self, input: &str) -> bool {
+        match self.case {
+            Case::Smart => !input.chars().any(char::is_uppercase),
+            Case::Insensitive => true,
+            Case::Sensitive => false,
+        }
+    }
+}
+
+impl Matcher for CaseFolding {
+    fn score(&self, input: &str, item: &str) -> Option<f32> {
+        let fold = self.fold(input);
+        self.matcher
+            .score(&normalize(input, fold), &normalize(item, fold))
+    }
+
+    /// The positions in the normalized item, of the characters of the item that they come from.
+    fn positions(&self, input: &str, item: &str) -> Vec<usize> {
+        let fold = self.fold(input);
+        let (normalized, sources) = normalize_mapped(item, fold);
+        let mut positions: Vec<usize> = self
+            .matcher
+            .positions(&normalize(input, fold), &normalized)
+            .into_iter()
+            .filter_map(|position| sources.get(position).copied())
+            .collect();
+        positions.dedup();
+        positions
+    }
+
+    /// Folding stops when an uppercase character is typed, but what matches case-sensitively
+    /// also matches without case.
+    fn narrows(&self, input: &str) -> bool {
+        self.matcher.narrows(input)
+    }
+}
+
+/// The text in compatibility decomposition without combining marks, like `Uberwriter` for
+/// `Überwriter` and `fi` for `ﬁ`, and lowercased if `lowercase`.
+fn normalize(text: &str, lowercase: bool) -> Cow<'_, str> {
+    if !text.is_ascii() {
+        return Cow::Owned(normalize_mapped(text, lowercase).0);
+    }
+    if lowercase && text.bytes().any(|byte| byte.is_ascii_uppercase()) {
+        Cow::Owned(text.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// The normalized text, and the index of the character of the text that each of its characters
+/// comes from.
+fn normalize_mapped(text: &str, lowercase: bool) -> (String, Vec<usize>) {
+    let mut normalized = String::new();
+    let mut sources = vec![];
+    for (i, c) in text.chars().enumerate() {
+        for c in iter::once(c).nfkd().filter(|c| !is_combining_mark(*c)) {
+            if lowercase {
+                for c in c.to_lowercase() {
+                    normalized.push(c);
+                    sources.push(i);
+                }
+            } else {
+                normalized.push(c);
+                sources.push(i);
+            }
+        }
+    }
+    (normalized, sources)
+}
+
+/// Splits the input at whitespace into terms that each must match, in any order, like fzf, so
+/// that `fire priv` finds `firefox-private`. Terms are matched by the other matcher, unless they
+/// have an operator like fzf's: `'term` must be in the item as it is, `^term` must start it,
+/// `term$` must end it, both `^term$` must be all of it, and `!term` must not be in it. The
+/// score is the mean of the scores of the terms that don't exclude, and an input of one term
+/// without an operator is matched as it is.
+pub struct Query(pub Box<dyn Matcher>);
+
+/// A term of the input, and how it must match.
+#[derive(Clone, Copy)]
+enum Term<'a> {
+    Matched(&'a str),
+    Exact(&'a str),
+    Prefix(&'a str),
+    Suffix(&'a str),
+    Equal(&'a str),
+    Excluded(&'a str),
+}
+
+/// The terms of the input. Operators without any text, like a lone `!`, are left out.
+fn terms(input: &str) -> impl Iterator<Item = Term<'_>> {
+    input.split_whitespace().filter_map(|word| {
+        let term = if let Some(text) = word.strip_prefix('!') {
+            Term::Excluded(text)
+        } else if let Some(text) = word.strip_prefix('\'') {
+            Term::Exact(text)
+        } else if let Some(text) = word.strip_prefix('^') {
+            match text.strip_suffix('$') {
+                Some(text) => Term::Equal(text),
+                None => Term::Prefix(text),
+            }
+        } else if let Some(text) = word.strip_suffix('$') {
+            Term::Suffix(text)
+        } else {
+            Term::Matched(word)
+        };
+        match term {
+            Term::Matched(text)
+            | Term::Exact(text)
+            | Term::Prefix(text)
+            | Term::Suffix(text)
+            | Term::Equal(text)
+            | Term::Excluded(text) => (!text.is_empty()).then_some(term),
+        }
+    })
+}
+
+impl Query {
+    /// The text of the input if it is one term for the other matcher, without the whitespace
+    /// around it.
+    fn plain(input: &str) -> Option<&str> {
+        let mut terms = terms(input);
+        match (terms.next(), terms.next()) {
+            (Some(Term::Matched(text)), None) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn term_score(&self, term: Term, item: &str) -> Option<f32> {
+        let exact = |text: &str| text.len() as f32 / item.len() as f32;
+        match term {
+            Term::Matched(text) => self.0.score(text, item),
+            Term::Exact(text) => item.contains(text).then(|| exact(text)),
+            Term::Prefix(text) => item.starts_with(text).then(|| exact(text)),
+            Term::Suffix(text) => item.ends_with(text).then(|| exact(text)),
+            Term::Equal(text) => (item == text).then_some(1.),
+            Term::Excluded(text) => (!item.contains(text)).then_some(0.),
+        }
+    }
+
+    fn term_positions(&self, term: Term, item: &str) -> Vec<usize> {
+        let chars = |start: usize, text: &str| {
+            let start = item[..start].chars().count();
+            (start..start + text.chars().count()).collect()
+        };
+        match term {
+            Term::Matched(text) => self.0.positions(text, item),
+            Term::Exact(text) => item.find(text).map_or(vec![], |start| chars(start, text)),
+            Term::Prefix(text) | Term::Equal(text) => chars(0, text),
+            Term::Suffix(text) => chars(item.len() - text.len(), text),
+            Term::Excluded(_) => vec![],
+        }
+    }
+}
+
+impl Matcher for Query {
+    fn score(&self, input: &str, item: &str) -> Option<f32> {
+        if let Some(text) = Query::plain(input) {
+            return self.0.score(text, item);
+        }
+        let mut total = 0.;
+        let mut scored = 0;
+        for term in terms(input) {
+            total += self.term_score(term, item)?;
+            if !matches!(term, Term::Excluded(_)) {
+                scored += 1;
+            }
+        }
+        Some(total / scored.max(1) as f32)
+    }
+
+    /// The characters that the terms that matched match.
+    fn positions(&self, input: &str, item: &str) -> Vec<usize> {
+        if let Some(text) = Query::plain(input) {
+            return self.0.positions(text, item);
+        }
+        let mut positions: Vec<usize> = terms(input)
+            .filter(|term| self.term_score(*term, item).is_some())
+            .flat_map(|term| self.term_positions(term, item))
+            .collect();
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+    }
+
+    /// A longer input has the same or more terms, which are the same or longer, but a longer
+    /// term that excludes, or one that no longer ends with `$`, can match more. After
+    /// whitespace, the next term is yet to be typed.
+    fn narrows(&self, input: &str) -> bool {
+        if input.ends_with(char::is_whitespace) {
+            return false;
+        }
+        let last = input.split_whitespace().last();
+        self.0.narrows(input)
+            && !last.is_some_and(|last| last.starts_with('!') || last.ends_with('$'))
+    }
+}
+
+/// Trigram similarity from rust_fuzzy_search.
+///
+/// It doesn't narrow: a longer input has trigrams that a shorter one lacks, which other items
+/// may share, like `zbc` that matches `abc` by `bc ` but not `ab`. So every item is scored
+/// again on each key.
+pub struct Fuzzy;
+
+impl Matcher for Fuzzy {
+    fn score(&self, input: &str, item: &str) -> Option<f32> {
+        let score = fuzzy_compare(input, item);
+        if score > 0. {
+            Some(score)
+        } else {
+            None
+        }
+    }
+
+    /// The characters of every trigram of the item that also appears in the input.
+    fn positions(&self, input: &str, item: &str) -> Vec<usize> {
+        let input = trigrams(input);
+        let item_len = item.chars().count();
+        let mut positions = vec![];
+        for (i, trigram) in trigrams(item).iter().enumerate() {
+            if input.contains(trigram) {
+                // the item is padded with two spaces at the start and one at the end
+                positions.extend((i.saturating_sub(2)..i + 1).filter(|j| *j < item_len));
+            }
+        }
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+    }
+}
+
+/// Every window of three characters, padded like rust_fuzzy_search does.
+fn trigrams(s: &str) -> Vec<[char; 3]> {
+    let padded: Vec<char> = "  ".chars().chain(s.chars()).chain(" ".chars()).collect();
+    padded.windows(3).map(|w| [w[0], w[1], w[2]]).collect()
+}
+
+/// Every input character must appear in order, like fzf. Consecutive characters and characters at
+/// the start of a word score higher, gaps score lower.
+pub struct Subsequence;
+
+impl Matcher for Subsequence {
+    fn score(&self, input: &str, item: &str) -> Option<f32> {
+        subsequence(input, item).map(|(score, _)| score)
+    }
+
+    fn positions(&self, input: &str, item: &str) -> Vec<usize> {
+        subsequence(input, item)
+            .map(|(_, positions)| positions)
+            .unwrap_or_default()
+    }
+
+    fn narrows(&self, _input: &str) -> bool {
+        true
+    }
+}
+
+fn subsequence(input: &str, item: &str) -> Option<(f32, Vec<usize>)> {
+    let item: Vec<char> = item.chars().collect();
+    let mut score = 0.;
+    let mut positions = vec![];
+    let mut next = 0;
+    let mut prev: Option<usize> = None;
+    for c in input.chars() {
+        let i = (next..item.len()).find(|i| item[*i] == c)?;
+        score += 1.;
+        if i == 0 || !item[i - 1].is_alphanumeric() {
+            score += 0.8;
+        }
+        if let Some(prev) = prev {
+            if prev + 1 == i {
+                score += 1.;
+            } else {
+                score -= 0.1 * (i - prev - 1) as f32;
+            }
+        }
+        positions.push(i);
+        prev = Some(i);
+        next = i + 1;
+    }
+    Some((score / (input.chars().count() as f32 * 2.8), positions))
+}
+
+/// The item must start with the input, shorter items score higher.
+pub struct Prefix;
+
+impl Matcher for Prefix {
+    fn score(&self, input: &str, item: &str) -> Option<f32> {
+        if item.starts_with(input) {
+            Some(input.len() as f32 / item.len() as f32)
+        } else {
+            None
+        }
+    }
+
+    fn positions(&self, input: &str, item: &str) -> Vec<usize> {
+        if item.starts_with(input) {
+            (0..input.chars().count()).collect()
+        } else {
+            vec![]
+        }
+    }
+
+    fn narrows(&self, _input: &str) -> bool {
+        true
+    }
+}
+
+/// The item must contain the input, shorter items score higher.
+pub struct Substring;
+
+impl Matcher for Substring {
+    fn score(&self, input: &str, item: &str) -> Option<f32> {
+        if item.contains(input) {
+            Some(input.len() as f32 / item.len() as f32)
+        } else {
+            None
+        }
+    }
+
+    fn positions(&self, input: &str, item: &str) -> Vec<usize> {
+        match item.find(input) {
+            Some(start) => {
+                let start = item[..start].chars().count();
+                (start..start + input.chars().count()).collect()
+            }
+            None => vec![],
+        }
+    }
+
+    fn narrows(&self, _input: &str) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(name: &str, case: Case) -> CaseFolding {
+        CaseFolding {
+            matcher: from_name(name).unwrap(),
+            case,
+        }
+    }
+
+    #[test]
+    fn matches_each_word() {
+        for name in ["fuzzy", "fzf", "substring"] {
+            let matcher = matcher(name, Case::Smart);
+            for input in ["fire", "fire ", " fire", "fire priv", "priv fire"] {
+                assert!(
+                    matcher.score(input, "firefox-private").is_some(),
+                    "{} {:?}",
+                    name,
+                    input
+                );
+            }
+            assert!(
+                matcher.score("fire xyz", "firefox-private").is_none(),
+                "{}",
+                name
+            );
+            assert_eq!(
+                matcher.positions("fire ", "firefox"),
+                [0, 1, 2, 3],
+                "{}",
+                name
+            );
+        }
+        let prefix = matcher("prefix", Case::Smart);
+        assert!(prefix.score("git comm", "git commit").is_some());
+        assert!(prefix.score("comm", "git commit").is_none());
+    }
+
+    #[test]
+    fn operators() {
+        let matcher = matcher("fzf", Case::Smart);
+        let matches = |input: &str, item: &str| matcher.score(input, item).is_some();
+        assert!(matches("'fox", "firefox"));
+        assert!(!matches("'ffx", "firefox"));
+        assert!(matches("^fire", "firefox"));
+        assert!(!matches("^fox", "firefox"));
+        assert!(matches("fox$", "firefox"));
+        assert!(matches("^firefox$", "firefox"));
+        assert!(!matches("^fire$", "firefox"));
+        assert!(matches("fire !priv", "firefox"));
+        assert!(!matches("fire !priv", "firefox-private"));
+        assert_eq!(matcher.positions("^fi ox$", "firefox"), [0, 1, 5, 6]);
+    }
+
+    #[test]
+    fn folds_case_and_diacritics() {
+        let smart = matcher("substring", Case::Smart);
+        assert!(smart.score("fire", "Firefox").is_some());
+        assert!(smart.score("Fire", "firefox").is_none());
+        assert!(smart.score("uberwriter", "Überwriter").is_some());
+        assert_eq!(smart.positions("ecran", "Écran"), [0, 1, 2, 3, 4]);
+        let sensitive = matcher("substring", Case::Sensitive);
+        assert!(sensitive.score("fire", "Firefox").is_none());
+        let insensitive = matcher("substring", Case::Insensitive);
+        assert!(insensitive.score("FIRE", "firefox").is_some());
+    }
+
+    #[test]
+    fn narrows() {
+        let fzf = matcher("fzf", Case::Smart);
+        assert!(fzf.narrows("fire"));
+        assert!(fzf.narrows("fire priv"));
+        // the next word is yet to be typed, and may exclude or end with `$`
+        assert!(!fzf.narrows("fire "));
+        assert!(!fzf.narrows("fire !pri"));
+        assert!(!fzf.narrows("fox$"));
+        // typing uppercase stops folding, which matches less, not more
+        assert!(fzf.narrows("Fire"));
+        assert!(!matcher("fuzzy", Case::Smart).narrows("fire"));
+        assert!(matcher("prefix", Case::Smart).narrows("fire "));
+    }
+}
@@ -0,0 +1,56 @@
+use crate::{
+    error::DmitriError,
+    items::{Item, ItemSource},
+};
+use serde::Deserialize;
+use std::fs;
+
+/// The items of a menu file, which show their labels and output their commands. The file is TOML
+/// with a table for each item, in the order they are listed:
+///
+/// ```toml
+/// [[item]]
+/// label = "Lock"
+/// command = "loginctl lock-session"
+/// icon = "system-lock-screen"
+/// ```
+pub struct MenuSource {
+    pub path: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Menu {
+    #[serde(default)]
+    item: Vec<MenuItem>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MenuItem {
+    label: String,
+    command: String,
+    icon: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    keywords: Vec<String>,
+}
+
+impl ItemSource for MenuSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        let invalid =
+            |err: &dyn std::fmt::Display| DmitriError::Config(format!("{}: {}", self.path, err));
+        let contents = fs::read_to_string(&self.path).map_err(|err| invalid(&err))?;
+        let menu: Menu = toml::from_str(&contents).map_err(|err| invalid(&err))?;
+        Ok(menu
+            .item
+            .into_iter()
+            .map(|item| {
+                Item::with_value(item.label, item.command)
+                    .with_icon(item.icon)
+                    .with_description(item.description)
+                    .with_keywords(item.keywords)
+            })
+            .collect())
+    }
+}
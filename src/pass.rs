@@ -0,0 +1,125 @@
+use crate::{
+    error::DmitriError,
+    items::{spawn_detached, Item, ItemSink, ItemSource},
+};
+use std::{
+    collections::HashSet,
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// The directory of the password store: `$PASSWORD_STORE_DIR`, or `~/.password-store`.
+fn store_dir() -> Option<PathBuf> {
+    match env::var_os("PASSWORD_STORE_DIR") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(Path::new(&env::var_os("HOME")?).join(".password-store")),
+    }
+}
+
+/// The entries of the password store of pass(1), by their names like `email/work`, which are the
+/// paths of their `.gpg` files without the extension. Hidden directories like `.git` are left out.
+pub struct PassSource;
+
+impl ItemSource for PassSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        let mut entries = vec![];
+        if let Some(dir) = store_dir() {
+            collect_entries(&dir, "", &mut entries, &mut HashSet::new());
+        }
+        entries.sort();
+        Ok(entries.into_iter().map(Item::new).collect())
+    }
+}
+
+/// Add the entries under the directory, by their paths after `prefix`. Each directory is read
+/// once, as a link to one of its parents would lead back to it forever.
+fn collect_entries(
+    dir: &Path,
+    prefix: &str,
+    entries: &mut Vec<String>,
+    visited: &mut HashSet<PathBuf>,
+) {
+    if !visited.insert(fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())) {
+        return;
+    }
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(_) => return,
+    };
+    for entry in read_dir.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        // the store may link to directories of other stores
+        let path = entry.path();
+        if path.is_dir() {
+            collect_entries(&path, &format!("{}{}/", prefix, name), entries, visited);
+        } else if let Some(name) = name.strip_suffix(".gpg") {
+            entries.push(format!("{}{}", prefix, name));
+        }
+    }
+}
+
+/// Copies the password of the selected entry to the clipboard with `pass show -c`, which clears
+/// it after a while, or with `type_password`, types it into the focused window instead, like
+/// passmenu. The password is the first line of the entry, and it is typed with `xdotool`, which
+/// fakes the key presses over the XTEST extension, or with `wtype` on Wayland.
+pub struct PassSink {
+    pub type_password: bool,
+}
+
+impl ItemSink for PassSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        if !self.type_password {
+            let mut command = process::Command::new("pass");
+            command.args(["show", "-c", "--", &selection]);
+            return spawn_detached(&mut command).map_err(|source| DmitriError::Spawn {
+                command: format!("pass show -c {}", selection),
+                source,
+            });
+        }
+        let spawn_error = |source| DmitriError::Spawn {
+            command: format!("pass show {}", selection),
+            source,
+        };
+        let output = process::Command::new("pass")
+            .args(["show", "--", &selection])
+            .stdin(process::Stdio::null())
+            .stderr(process::Stdio::inherit())
+            .output()
+            .map_err(spawn_error)?;
+        if !output.status.success() {
+            return Err(spawn_error(io::Error::other(output.status.to_string())));
+        }
+        let contents = String::from_utf8_lossy(&output.stdout);
+        let password = contents.lines().next().unwrap_or_default();
+        type_text(password)
+    }
+}
+
+/// Type the text into the focused window, passing it on stdin so that it doesn't show up in the
+/// arguments of the process.
+fn type_text(text: &str) -> Result<(), DmitriError> {
+    let (program, args): (&str, &[&str]) = if env::var_os("WAYLAND_DISPLAY").is_some() {
+        ("wtype", &["-"])
+    } else {
+        ("xdotool", &["type", "--clearmodifiers", "--file", "-"])
+    };
+    let mut child = process::Command::new(program)
+        .args(args)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::null())
+        .spawn()
+        .map_err(|source| DmitriError::Spawn {
+            command: program.to_string(),
+            source,
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    child.wait()?;
+    Ok(())
+}
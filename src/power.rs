@@ -0,0 +1,130 @@
+use crate::{
+    error::DmitriError,
+    items::{spawn_detached, Item, ItemSink, ItemSource},
+};
+use std::{collections::HashMap, env, process};
+
+/// An action of the power menu, with the command that it runs unless the config replaces it.
+struct Action {
+    name: &'static str,
+    label: &'static str,
+    icon: &'static str,
+    keywords: &'static [&'static str],
+    command: &'static str,
+    /// Whether it ends the session, so that it is confirmed first.
+    destructive: bool,
+}
+
+const ACTIONS: [Action; 6] = [
+    Action {
+        name: "lock",
+        label: "Lock",
+        icon: "system-lock-screen",
+        keywords: &["screen"],
+        command: "loginctl lock-session",
+        destructive: false,
+    },
+    Action {
+        name: "logout",
+        label: "Log out",
+        icon: "system-log-out",
+        keywords: &["exit", "sign out"],
+        command: "loginctl terminate-session \"$XDG_SESSION_ID\"",
+        destructive: true,
+    },
+    Action {
+        name: "suspend",
+        label: "Suspend",
+        icon: "system-suspend",
+        keywords: &["sleep"],
+        command: "systemctl suspend",
+        destructive: false,
+    },
+    Action {
+        name: "hibernate",
+        label: "Hibernate",
+        icon: "system-hibernate",
+        keywords: &[],
+        command: "systemctl hibernate",
+        destructive: false,
+    },
+    Action {
+        name: "reboot",
+        label: "Reboot",
+        icon: "system-reboot",
+        keywords: &["restart"],
+        command: "systemctl reboot",
+        destructive: true,
+    },
+    Action {
+        name: "poweroff",
+        label: "Power off",
+        icon: "system-shutdown",
+        keywords: &["shut down", "halt"],
+        command: "systemctl poweroff",
+        destructive: true,
+    },
+];
+
+/// Locking the screen, logging out, suspending, hibernating, rebooting and powering off, with
+/// loginctl and systemctl, or with the shell commands of the config by action name. Actions with
+/// an empty command are left out.
+#[derive(Clone)]
+pub struct Power {
+    commands: HashMap<String, String>,
+}
+
+impl Power {
+    pub fn new(commands: HashMap<String, String>) -> Result<Power, DmitriError> {
+        if let Some(name) = commands
+            .keys()
+            .find(|name| !ACTIONS.iter().any(|action| action.name == name.as_str()))
+        {
+            return Err(DmitriError::Config(format!(
+                "Unknown power action: {}",
+                name
+            )));
+        }
+        Ok(Power { commands })
+    }
+
+    fn command(&self, action: &Action) -> &str {
+        self.commands
+            .get(action.name)
+            .map_or(action.command, String::as_str)
+    }
+}
+
+impl ItemSource for Power {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        Ok(ACTIONS
+            .iter()
+            .filter(|action| !self.command(action).is_empty())
+            .map(|action| {
+                Item::with_value(action.label.to_string(), action.name.to_string())
+                    .with_icon(Some(action.icon.to_string()))
+                    .with_keywords(action.keywords.iter().map(|k| k.to_string()).collect())
+            })
+            .collect())
+    }
+}
+
+impl ItemSink for Power {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        let action = match ACTIONS.iter().find(|action| action.name == selection) {
+            Some(action) => action,
+            None => return Ok(()),
+        };
+        let command = self.command(action).to_string();
+        let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        spawn_detached(process::Command::new(shell).arg("-c").arg(&command))
+            .map_err(|source| DmitriError::Spawn { command, source })
+    }
+
+    fn confirmation(&self, selection: &str) -> Option<String> {
+        ACTIONS
+            .iter()
+            .find(|action| action.name == selection && action.destructive)
+            .map(|action| format!("{}?", action.label))
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{
+    desktop::{data_dirs, split_exec},
+    error::DmitriError,
+    files,
+    items::{spawn_detached, Item, ItemSink, ItemSource},
+};
+use std::{env, fs, path::Path, process};
+
+/// The documents that applications recently opened, from `recently-used.xbel` in the data
+/// directory, the most recent first. They are shown by their paths, and opened with the
+/// application that opened them last, or else with `xdg-open`. Documents that no longer exist
+/// are left out.
+#[derive(Clone)]
+pub struct Recent {
+    documents: Vec<Document>,
+}
+
+/// A document of the file, with the time it was last modified, as an ISO 8601 date.
+#[derive(Clone)]
+struct Document {
+    path: String,
+    modified: String,
+    /// The name and the command line of the application that used it last.
+    application: Option<(String, String)>,
+}
+
+impl Recent {
+    pub fn load() -> Result<Recent, DmitriError> {
+        let path = match data_dirs().into_iter().next() {
+            Some(dir) => dir.join("recently-used.xbel"),
+            None => return Ok(Recent { documents: vec![] }),
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(Recent { documents: vec![] }),
+        };
+        let mut documents = parse(&contents)
+            .map_err(|err| DmitriError::Config(format!("{}: {}", path.display(), err)))?;
+        documents.retain(|document| Path::new(&document.path).exists());
+        documents.sort_by(|a, b| b.modified.cmp(&a.modified));
+        Ok(Recent { documents })
+    }
+}
+
+impl ItemSource for Recent {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        let home = env::var("HOME").ok();
+        Ok(self
+            .documents
+            .iter()
+            .map(|document| {
+                let shown = match home
+                    .as_deref()
+                    .and_then(|home| document.path.strip_prefix(home))
+                {
+                    Some(relative) if relative.starts_with('/') => format!("~{}", relative),
+                    _ => document.path.clone(),
+                };
+                let name = document.application.as_ref().map(|(name, _)| name.clone());
+                Item::with_value(shown, document.path.clone()).with_description(name)
+            })
+            .collect())
+    }
+}
+
+impl ItemSink for Recent {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        let exec = self
+            .documents
+            .iter()
+            .find(|document| document.path == selection)
+            .and_then(|document| document.application.as_ref())
+            .map(|(_, exec)| exec);
+        let exec = match exec {
+            Some(exec) => exec,
+            None => return files::open(Path::new(&selection)),
+        };
+        let uri = format!("file://{}", encode(&selection));
+        // the documents are files, so %f and %u are the same document
+        let argv: Vec<String> = split_exec(exec)
+            .into_iter()
+            .map(|arg| match arg.as_str() {
+                "%f" | "%F" => selection.clone(),
+                "%u" | "%U" => uri.clone(),
+                _ => arg,
+            })
+            .collect();
+        if argv.is_empty() {
+            return files::open(Path::new(&selection));
+        }
+        let mut command = process::Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        spawn_detached(&mut command).map_err(|source| DmitriError::Spawn {
+            command: argv.join(" "),
+            source,
+        })
+    }
+}
+
+/// The local documents of an XBEL file, with the application that modified each one last.
+fn parse(contents: &str) -> Result<Vec<Document>, roxmltree::Error> {
+    let document = roxmltree::Document::parse(contents)?;
+    let mut documents = vec![];
+    for node in document
+        .root_element()
+        .children()
+        .filter(|node| node.has_tag_name("bookmark"))
+    {
+        let path = match node
+            .attribute("href")
+            .and_then(|href| href.strip_prefix("file://"))
+        {
+            Some(path) => decode(path),
+            None => continue,
+        };
+        let application = node
+            .descendants()
+            .filter(|node| node.tag_name().name() == "application")
+            .max_by_key(|node| node.attribute("modified").unwrap_or_default())
+            .and_then(|node| {
+                let name = node.attribute("name")?;
+                // GLib writes the command line in single quotes
+                let exec = node.attribute("exec")?;
+                let exec = exec
+                    .strip_prefix('\'')
+                    .and_then(|exec| exec.strip_suffix('\''))
+                    .unwrap_or(exec);
+                Some((name.to_string(), exec.to_string()))
+            });
+        documents.push(Document {
+            path,
+            modified: node.attribute("modified").unwrap_or_default().to_string(),
+            application,
+        });
+    }
+    Ok(documents)
+}
+
+/// Decode the `%` escapes of a URI path.
+fn decode(path: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let [first, tail @ ..] = rest {
+        let hex = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (first, hex) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(*first);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Escape a path for a `file://` URI.
+pub(crate) fn encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
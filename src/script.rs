@@ -0,0 +1,123 @@
+use crate::{error::DmitriError, items::Item};
+use std::{collections::HashMap, env, process, str::FromStr};
+
+/// A mode whose items a script prints, one per line, like a rofi script mode. The script is run
+/// without arguments for the first items, and then with each selection for the next ones, until
+/// it prints none. It gets the environment of rofi's protocol, so that rofi scripts work as they
+/// are:
+///
+/// - `ROFI_RETV`: 0 for the first run, 1 when an item was selected and 2 for other input.
+/// - `ROFI_INFO`: the `info` of the selected item.
+/// - `ROFI_DATA`: the `data` that the last run set.
+///
+/// Options follow the text of a line after a nul byte, as names and values separated by `\x1f`:
+/// items can have an `icon`, `meta` text they are also found by, and `info`. Lines of only options
+/// set the `prompt` and the `data` of the step, and other options are ignored.
+pub struct Script {
+    pub name: String,
+    command: String,
+    /// The `info` of the items of the last step, by their text.
+    info: HashMap<String, String>,
+    data: Option<String>,
+}
+
+/// The items of a step of a script, and its prompt.
+pub struct Step {
+    pub items: Vec<Item>,
+    pub prompt: Option<String>,
+}
+
+impl FromStr for Script {
+    type Err = DmitriError;
+
+    /// A mode like `notes:~/bin/notes.sh`, with the name that is shown as its prompt.
+    fn from_str(s: &str) -> Result<Script, DmitriError> {
+        match s.split_once(':') {
+            Some((name, command)) if !name.is_empty() && !command.trim().is_empty() => Ok(Script {
+                name: name.to_string(),
+                command: command.to_string(),
+                info: HashMap::new(),
+                data: None,
+            }),
+            _ => Err(DmitriError::Config(format!(
+                "Invalid mode, expected name:command: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl Script {
+    /// Run the script for the first step.
+    pub fn start(&mut self) -> Result<Step, DmitriError> {
+        self.data = None;
+        self.info.clear();
+        self.run(0, None)
+    }
+
+    /// Run the script with the selection for the next step, which is one of the items of the last
+    /// step if `matched`, or any other input.
+    pub fn select(&mut self, selection: &str, matched: bool) -> Result<Step, DmitriError> {
+        self.run(if matched { 1 } else { 2 }, Some(selection))
+    }
+
+    fn run(&mut self, retv: u8, selection: Option<&str>) -> Result<Step, DmitriError> {
+        let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        let mut command = process::Command::new(shell);
+        // the selection is an argument of the command, as "$1" of `sh -c`
+        command
+            .arg("-c")
+            .arg(format!("{} \"$@\"", self.command))
+            .arg(&self.name)
+            .args(selection)
+            .env("ROFI_RETV", retv.to_string())
+            .env_remove("ROFI_INFO")
+            .env_remove("ROFI_DATA")
+            .stdin(process::Stdio::null())
+            .stderr(process::Stdio::inherit());
+        if let Some(info) = selection.and_then(|selection| self.info.get(selection)) {
+            command.env("ROFI_INFO", info);
+        }
+        if let Some(data) = &self.data {
+            command.env("ROFI_DATA", data);
+        }
+        let output = command.output().map_err(|source| DmitriError::Spawn {
+            command: self.command.clone(),
+            source,
+        })?;
+
+        self.info.clear();
+        let mut step = Step {
+            items: vec![],
+            prompt: None,
+        };
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let (text, options) = line.split_once('\0').unwrap_or((line, ""));
+            let mut fields = options.split('\x1f');
+            let options = std::iter::from_fn(|| Some((fields.next()?, fields.next()?)));
+            if text.is_empty() {
+                for (name, value) in options {
+                    match name {
+                        "prompt" => step.prompt = Some(value.to_string()),
+                        "data" => self.data = Some(value.to_string()),
+                        _ => (),
+                    }
+                }
+                continue;
+            }
+            let mut item = Item::new(text.to_string());
+            for (name, value) in options {
+                match name {
+                    "icon" => item = item.with_icon(Some(value.to_string())),
+                    "meta" => item = item.with_keywords(vec![value.to_string()]),
+                    "info" => {
+                        self.info.insert(text.to_string(), value.to_string());
+                    }
+                    _ => (),
+                }
+            }
+            step.items.push(item);
+        }
+        Ok(step)
+    }
+}
@@ -0,0 +1,68 @@
+/// The selected match, if any. Moving past either end of the matches selects the input again.
+#[derive(Default)]
+pub struct Selection {
+    index: Option<usize>,
+}
+
+impl Selection {
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    /// Select the input, as when the matches changed.
+    pub fn clear(&mut self) {
+        self.index = None;
+    }
+
+    pub fn select(&mut self, i: usize) {
+        self.index = Some(i);
+    }
+
+    /// Select the match after the selected one, or the first one after the input.
+    pub fn next(&mut self, len: usize) {
+        self.index = match self.index {
+            None if len > 0 => Some(0),
+            Some(i) if i + 1 < len => Some(i + 1),
+            _ => None,
+        };
+    }
+
+    /// Select the match before the selected one, or the last one before the input.
+    pub fn previous(&mut self, len: usize) {
+        self.index = match self.index {
+            None => len.checked_sub(1),
+            Some(i) => i.checked_sub(1),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_through_the_input() {
+        let mut selection = Selection::default();
+        assert_eq!(selection.index(), None);
+        selection.next(2);
+        assert_eq!(selection.index(), Some(0));
+        selection.next(2);
+        assert_eq!(selection.index(), Some(1));
+        selection.next(2);
+        assert_eq!(selection.index(), None);
+        selection.previous(2);
+        assert_eq!(selection.index(), Some(1));
+        selection.select(0);
+        selection.previous(2);
+        assert_eq!(selection.index(), None);
+    }
+
+    #[test]
+    fn stays_on_the_input_without_matches() {
+        let mut selection = Selection::default();
+        selection.next(0);
+        assert_eq!(selection.index(), None);
+        selection.previous(0);
+        assert_eq!(selection.index(), None);
+    }
+}
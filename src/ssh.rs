@@ -0,0 +1,112 @@
+use crate::{
+    error::DmitriError,
+    items::{spawn_detached, Item, ItemSink, ItemSource, Terminal},
+};
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Hosts from the `Host` lines of `~/.ssh/config`, then those in `~/.ssh/known_hosts`.
+pub struct SshSource;
+
+impl ItemSource for SshSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        let ssh_dir = match env::var("HOME") {
+            Ok(home) => PathBuf::from(home).join(".ssh"),
+            Err(_) => return Ok(vec![]),
+        };
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut hosts: Vec<Item> = vec![];
+        for host in config_hosts(&ssh_dir.join("config"))
+            .into_iter()
+            .chain(known_hosts(&ssh_dir.join("known_hosts")))
+        {
+            if seen.insert(host.clone()) {
+                hosts.push(Item::new(host));
+            }
+        }
+        Ok(hosts)
+    }
+}
+
+/// Opens an ssh session to the selected host in a terminal.
+pub struct SshSink {
+    pub terminal: Terminal,
+}
+
+impl ItemSink for SshSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        let argv = self.terminal.command(&[String::from("ssh"), selection]);
+        let mut command = process::Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        spawn_detached(&mut command).map_err(|source| DmitriError::Spawn {
+            command: argv.join(" "),
+            source,
+        })
+    }
+}
+
+/// The aliases of `Host` lines, without patterns since they don't name a single host.
+fn config_hosts(path: &Path) -> Vec<String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return vec![],
+    };
+    let mut hosts = vec![];
+    for line in contents.lines() {
+        let mut words = line.split(|c: char| c.is_whitespace() || c == '=');
+        let keyword = match words.find(|w| !w.is_empty()) {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if !keyword.eq_ignore_ascii_case("host") {
+            continue;
+        }
+        hosts.extend(
+            words
+                .filter(|w| !w.is_empty() && !w.contains(['*', '?', '!']))
+                .map(|w| w.trim_matches('"').to_string()),
+        );
+    }
+    hosts
+}
+
+/// The host names of `known_hosts`. Hashed entries can't be listed, and hosts on another port,
+/// written as `[host]:port`, become `ssh://host:port` which ssh accepts as well.
+fn known_hosts(path: &Path) -> Vec<String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return vec![],
+    };
+    let mut hosts = vec![];
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        let mut names = match fields.next() {
+            Some(names) => names,
+            None => continue,
+        };
+        // markers like @cert-authority come before the host names
+        if names.starts_with('@') {
+            names = match fields.next() {
+                Some(names) => names,
+                None => continue,
+            };
+        }
+        if names.starts_with('#') || names.starts_with('|') {
+            continue;
+        }
+        for name in names.split(',') {
+            if name.contains(['*', '?', '!']) {
+                continue;
+            }
+            match name.strip_prefix('[').and_then(|n| n.split_once("]:")) {
+                Some((host, port)) => hosts.push(format!("ssh://{}:{}", host, port)),
+                None => hosts.push(name.to_string()),
+            }
+        }
+    }
+    hosts
+}
@@ -0,0 +1,109 @@
+use crate::error::DmitriError;
+use breadx::{
+    display::{DisplayConnection, DisplayFunctionsExt},
+    prelude::*,
+    protocol::xproto::{ClientMessageEvent, EventMask, SendEventRequest},
+};
+use std::{
+    borrow::Cow,
+    fs, process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What the window manager shows while an application starts, from its desktop entry.
+pub struct Launch<'a> {
+    pub name: &'a str,
+    /// The program that is run.
+    pub bin: &'a str,
+    pub icon: Option<&'a str>,
+    /// The `StartupWMClass=` that the application's window will have.
+    pub wm_class: Option<&'a str>,
+    /// The path of the desktop entry.
+    pub application_id: &'a str,
+}
+
+/// Announce the launch to the window manager with a `new:` message of the freedesktop startup
+/// notification protocol, and return its ID. The application gets it in `DESKTOP_STARTUP_ID`, and
+/// ends the notification when its window appears.
+pub fn begin(launch: &Launch) -> Result<String, DmitriError> {
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+    let id = format!(
+        "dmitri-{}-{}-{}-{}",
+        process::id(),
+        hostname.trim(),
+        launch.bin.rsplit('/').next().unwrap_or(launch.bin),
+        nanos
+    );
+
+    let mut conn = DisplayConnection::connect(None)?;
+    let screen = conn.default_screen_index().to_string();
+    let mut fields = vec![
+        ("ID", id.as_str()),
+        ("NAME", launch.name),
+        ("SCREEN", screen.as_str()),
+        ("BIN", launch.bin),
+        ("APPLICATION_ID", launch.application_id),
+    ];
+    if let Some(icon) = launch.icon {
+        fields.push(("ICON", icon));
+    }
+    if let Some(wm_class) = launch.wm_class {
+        fields.push(("WMCLASS", wm_class));
+    }
+    send(&mut conn, &message("new", &fields))?;
+    Ok(id)
+}
+
+/// End the notification, when the application could not be started.
+pub fn cancel(id: &str) -> Result<(), DmitriError> {
+    let mut conn = DisplayConnection::connect(None)?;
+    send(&mut conn, &message("remove", &[("ID", id)]))
+}
+
+/// A message like `new: ID=... NAME="Text Editor"`, with values quoted where needed.
+fn message(kind: &str, fields: &[(&str, &str)]) -> String {
+    let mut message = format!("{}:", kind);
+    for (key, value) in fields {
+        let value = if value.contains([' ', '"', '\\']) {
+            format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+        } else {
+            value.to_string()
+        };
+        message += &format!(" {}={}", key, value);
+    }
+    message
+}
+
+/// Send the message to the root window, in client messages of 20 bytes each including the
+/// terminating nul. The first has the type `_NET_STARTUP_INFO_BEGIN` and the others
+/// `_NET_STARTUP_INFO`.
+fn send(conn: &mut DisplayConnection, message: &str) -> Result<(), DmitriError> {
+    let root = conn.default_screen().root;
+    let begin = conn.intern_atom(false, "_NET_STARTUP_INFO_BEGIN")?;
+    let more = conn.intern_atom(false, "_NET_STARTUP_INFO")?;
+    let begin = conn.wait_for_reply(begin)?.atom;
+    let more = conn.wait_for_reply(more)?.atom;
+
+    let mut bytes = message.as_bytes().to_vec();
+    bytes.push(0);
+    for (i, chunk) in bytes.chunks(20).enumerate() {
+        let mut data = [0u8; 20];
+        data[..chunk.len()].copy_from_slice(chunk);
+        let type_ = if i == 0 { begin } else { more };
+        let event = ClientMessageEvent::new(8, root, type_, data);
+        conn.send_void_request(
+            SendEventRequest {
+                propagate: false,
+                destination: root,
+                event_mask: u32::from(EventMask::PROPERTY_CHANGE),
+                event: Cow::Owned(event.into()),
+            },
+            true,
+        )?;
+    }
+    conn.flush()?;
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::{
+    io::Read,
+    os::unix::process::ExitStatusExt,
+    process,
+    sync::{mpsc, Arc, Mutex, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The first argument of dmitri when it runs as the supervisor of a launched program, followed
+/// by the program and its arguments.
+pub const FLAG: &str = "--supervise";
+
+/// How long a program is watched after it starts. Programs that fail later aren't reported, as
+/// they ran.
+const WATCH: Duration = Duration::from_secs(3);
+
+/// How much of the end of stderr a notification shows.
+const MAX_OUTPUT: usize = 1024;
+
+/// Run the program with its stderr captured, and show a desktop notification with `notify-send`
+/// if it can't be started or exits with an error within the first seconds. Afterwards stderr is
+/// still read, and dropped, until the program exits, so that writing to it doesn't fail. The
+/// exit status of the program, or 127 if it can't be started, like a shell.
+pub fn run(argv: &[String]) -> i32 {
+    let (program, args) = match argv.split_first() {
+        Some(argv) => argv,
+        None => return 0,
+    };
+    let name = display_name(argv);
+    let mut child = match process::Command::new(program)
+        .args(args)
+        .stderr(process::Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            notify(&format!("Could not run {}", name), &err.to_string());
+            return 127;
+        }
+    };
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let (done, finished) = mpsc::channel();
+    if let Some(mut stderr) = child.stderr.take() {
+        let output = Arc::clone(&output);
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            while let Ok(n @ 1..) = stderr.read(&mut buffer) {
+                let mut output = output.lock().unwrap_or_else(PoisonError::into_inner);
+                output.extend_from_slice(&buffer[..n]);
+                let excess = output.len().saturating_sub(MAX_OUTPUT);
+                output.drain(..excess);
+            }
+            let _ = done.send(());
+        });
+    }
+    let start = Instant::now();
+    while start.elapsed() < WATCH {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => return 0,
+            Ok(Some(status)) => {
+                // the rest of stderr, unless a process that the program started still has it
+                let _ = finished.recv_timeout(Duration::from_millis(200));
+                let output = output.lock().unwrap_or_else(PoisonError::into_inner);
+                let output = String::from_utf8_lossy(&output);
+                let body = match output.trim() {
+                    "" => status.to_string(),
+                    output => output.to_string(),
+                };
+                notify(&format!("{} failed", name), &body);
+                return code(status);
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(_) => break,
+        }
+    }
+    let _ = finished.recv();
+    child.wait().map_or(1, code)
+}
+
+/// The exit code of the status, or 128 and the signal that killed the program.
+pub(crate) fn code(status: process::ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+/// The command that a shell runs with `-c`, or else the program and its arguments.
+fn display_name(argv: &[String]) -> String {
+    match argv {
+        [_, c, command, ..] if c == "-c" => command.clone(),
+        _ => argv.join(" "),
+    }
+}
+
+fn notify(summary: &str, body: &str) {
+    let notified = process::Command::new("notify-send")
+        .args([
+            "--app-name=dmitri",
+            "--urgency=critical",
+            "--",
+            summary,
+            body,
+        ])
+        .stdin(process::Stdio::null())
+        .status();
+    if notified.is_err() {
+        eprintln!("dmitri: {}: {}", summary, body);
+    }
+}
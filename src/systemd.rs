@@ -0,0 +1,128 @@
+use crate::{
+    error::DmitriError,
+    items::{spawn_detached, Badge, Item, ItemSink, ItemSource},
+};
+use std::{collections::HashSet, process};
+
+/// The services of the user's systemd instance and of the system one, with their active states
+/// as badges: the loaded units, and the installed ones that aren't loaded as inactive. Templates
+/// and masked units are left out, as they can't be started. Units are output as `user:name` or
+/// `system:name`.
+pub struct SystemdSource;
+
+impl ItemSource for SystemdSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        let mut items = vec![];
+        for scope in ["user", "system"] {
+            let mut listed = HashSet::new();
+            let units = systemctl(scope, &["list-units", "--all", "--type=service", "--plain"])?;
+            for line in units.lines() {
+                // UNIT LOAD ACTIVE SUB DESCRIPTION, where failed units may start with a bullet
+                let fields: Vec<&str> = line.trim_start_matches('●').split_whitespace().collect();
+                if fields.len() < 4 {
+                    continue;
+                }
+                let (unit, active) = (fields[0], fields[2]);
+                listed.insert(unit.to_string());
+                items.push(unit_item(scope, unit, active, fields[4..].join(" ")));
+            }
+            let files = systemctl(scope, &["list-unit-files", "--type=service"])?;
+            for line in files.lines() {
+                // UNIT STATE PRESET
+                let mut fields = line.split_whitespace();
+                let (unit, state) = match (fields.next(), fields.next()) {
+                    (Some(unit), Some(state)) => (unit, state),
+                    _ => continue,
+                };
+                if unit.contains("@.") || state == "masked" || listed.contains(unit) {
+                    continue;
+                }
+                items.push(unit_item(scope, unit, "inactive", String::new()));
+            }
+        }
+        Ok(items)
+    }
+}
+
+fn unit_item(scope: &str, unit: &str, active: &str, description: String) -> Item {
+    let color = match active {
+        "active" => "#5faf5f",
+        "failed" => "#d75f5f",
+        "activating" | "deactivating" | "reloading" => "#d7af5f",
+        _ => "#808080",
+    };
+    let description = match scope {
+        "user" if description.is_empty() => String::from("user"),
+        "user" => format!("{} (user)", description),
+        _ => description,
+    };
+    Item::with_value(unit.to_string(), format!("{}:{}", scope, unit))
+        .with_description(Some(description).filter(|description| !description.is_empty()))
+        .with_badge(Some(Badge {
+            text: active.to_string(),
+            color: color.to_string(),
+        }))
+}
+
+/// The output of systemctl for the instance of the scope, without headers and legends. It is
+/// empty when the instance can't be reached, like the user's one outside of a session.
+fn systemctl(scope: &str, args: &[&str]) -> Result<String, DmitriError> {
+    let output = process::Command::new("systemctl")
+        .arg(format!("--{}", scope))
+        .args(args)
+        .args(["--no-legend", "--no-pager"])
+        .stdin(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .output()
+        .map_err(|source| DmitriError::Spawn {
+            command: format!("systemctl --{} {}", scope, args.join(" ")),
+            source,
+        })?;
+    if !output.status.success() {
+        return Ok(String::new());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Stops the selected unit if it is active and starts it otherwise, or restarts it with the
+/// alternate action. System units ask polkit for authorization.
+pub struct SystemdSink;
+
+/// The option of systemctl for the scope of the selection, and the unit.
+fn target(selection: &str) -> (String, &str) {
+    match selection.split_once(':') {
+        Some((scope @ ("user" | "system"), unit)) => (format!("--{}", scope), unit),
+        _ => (String::from("--system"), selection),
+    }
+}
+
+impl SystemdSink {
+    fn run(&self, selection: &str, verb: &str) -> Result<(), DmitriError> {
+        let (scope, unit) = target(selection);
+        let mut command = process::Command::new("systemctl");
+        command.args([&scope, verb, "--", unit]);
+        spawn_detached(&mut command).map_err(|source| DmitriError::Spawn {
+            command: format!("systemctl {} {} {}", scope, verb, unit),
+            source,
+        })
+    }
+}
+
+impl ItemSink for SystemdSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        let (scope, unit) = target(&selection);
+        let active = process::Command::new("systemctl")
+            .args([&scope, "is-active", "--quiet", "--", unit])
+            .status()
+            .map_err(|source| DmitriError::Spawn {
+                command: format!("systemctl {} is-active {}", scope, unit),
+                source,
+            })?
+            .success();
+        self.run(&selection, if active { "stop" } else { "start" })
+    }
+
+    fn output_alternate(&mut self, selection: String) -> Result<(), DmitriError> {
+        self.run(&selection, "restart")
+    }
+}
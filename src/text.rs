@@ -1,235 +1,1351 @@
-use breadx::{
-    prelude::*,
-    protocol::xproto::{Gcontext, ImageFormat, Screen, VisualClass, Visualid, Window},
+use crate::{
+    bindings::Bindings,
+    error::DmitriError,
+    glyphs::{ColorGlyphs, ColorImage, Coverage},
+    icons,
+    items::{Badge, Item},
 };
 use font_loader::system_fonts;
-use rusttype::{point, Font, Scale, VMetrics};
-use std::{boxed::Box, error::Error};
-use x11rb::image::{Image, PixelLayout};
+use rusttype::{point, Font, GlyphId, Scale, VMetrics};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    mem,
+    ops::Range,
+    rc::Rc,
+    str::FromStr,
+    time::Duration,
+};
+use unicode_segmentation::UnicodeSegmentation;
+use x11rb::{
+    image::{Image, PixelLayout},
+    protocol::xproto::Rectangle,
+};
+
+/// The last resort when no system font can be loaded.
+const EMBEDDED_FONT: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+
+/// How many horizontal positions within a pixel glyphs are rasterized at.
+const SUBPIXEL_STEPS: f32 = 4.;
 
-pub type Color = (f32, f32, f32);
-pub fn color_from_u8(color: (u8, u8, u8)) -> (f32, f32, f32) {
+/// How many laid out lines are kept before starting over.
+const MAX_LAYOUTS: usize = 4096;
+
+/// Red, green, blue and alpha, not premultiplied.
+pub type Color = (f32, f32, f32, f32);
+pub fn color_from_u8(color: (u8, u8, u8, u8)) -> Color {
     (
         (((color.0 as u16) << 8) + 0xFF) as f32,
         ((color.1 as u16) << 8) as f32,
         ((color.2 as u16) << 8) as f32,
+        (((color.3 as u16) << 8) | color.3 as u16) as f32,
     )
 }
 
+/// Parse `#rgb`, `#rrggbb` or `#rrggbbaa`, the `#` is optional.
+pub fn parse_color(s: &str) -> Option<(u8, u8, u8, u8)> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize, len: usize| {
+        u8::from_str_radix(&hex[i * len..(i + 1) * len], 16)
+            .ok()
+            .map(|v| if len == 1 { v * 0x11 } else { v })
+    };
+    match hex.len() {
+        3 => Some((channel(0, 1)?, channel(1, 1)?, channel(2, 1)?, 0xff)),
+        6 => Some((channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, 0xff)),
+        8 => Some((
+            channel(0, 2)?,
+            channel(1, 2)?,
+            channel(2, 2)?,
+            channel(3, 2)?,
+        )),
+        _ => None,
+    }
+}
+
+/// Encode a color as a pixel value, premultiplied and with alpha if the image has an alpha
+/// channel in the bits that `pixel_layout` doesn't cover.
+fn encode(pixel_layout: PixelLayout, alpha_mask: u32, color: Color) -> u32 {
+    let alpha = color.3 / 65535.;
+    let pixel = pixel_layout.encode((
+        (color.0 * alpha) as u16,
+        (color.1 * alpha) as u16,
+        (color.2 * alpha) as u16,
+    ));
+    if alpha_mask == 0 {
+        return pixel;
+    }
+    pixel | (((color.3 as u32) >> 8) << alpha_mask.trailing_zeros() & alpha_mask)
+}
+
 pub struct RunOptions {
-    pub fontname: Option<String>,
+    pub font: Option<FontPattern>,
     pub fontsize: u16,
     pub color: Color,
+    /// Background of the window and color of the prompt and the unselected matches.
+    pub normal_bg: Color,
+    pub normal_fg: Color,
+    /// Background and text color of the selected match.
+    pub selected_bg: Color,
+    pub selected_fg: Color,
     pub margin: u16,
     pub precise_wheight: f32,
+    /// How many of the best matches are kept, to list or to page through.
+    pub max_matches: usize,
+    /// Tab copies the selected match, or the common prefix of the matches, into the input.
+    pub tab_complete: bool,
+    /// Number the first nine shown matches, for picking them with Alt and a digit.
+    pub hints: bool,
+    /// Show the number of the selected match and of all matches at the right edge.
+    pub counter: bool,
+    /// Only matches can be confirmed, not any other input.
+    pub only_match: bool,
+    /// While the input is empty, list the items in their order instead of the most used ones.
+    pub list_all: bool,
+    /// The keys of `next-mode` and `prev-mode` switch modes, see `Output::NextMode`.
+    pub switch_modes: bool,
+    /// The only match left is confirmed without Enter.
+    pub auto_accept: bool,
+    /// Close the window when nothing was done for this long.
+    pub timeout: Option<Duration>,
+    /// Several matches can be marked and confirmed together.
+    pub multi: bool,
+    /// Draw the icons of the items before them.
+    pub icons: bool,
+    /// The icon theme to look icons up in, before `hicolor`.
+    pub icon_theme: Option<String>,
+    /// Show the descriptions of the items next to the listed matches.
+    pub descriptions: bool,
+    /// Text color of the descriptions.
+    pub description_fg: Color,
+    /// Show the detail of the selected match at the end of its line.
+    pub details: bool,
+    pub lines: u16,
+    pub prompt: Option<String>,
+    /// A line shown above the input, like the question of `--confirm`.
+    pub message: Option<String>,
+    /// Hide the input behind this character, for passwords.
+    pub echo_char: Option<char>,
+    /// The selection is printed, so Enter on the input prints it as typed, and a result shown
+    /// for it only when its match is selected.
+    pub print: bool,
+    /// The URL templates of web searches by their bangs, like `g` for `!g rust`.
+    pub bangs: HashMap<String, String>,
+    /// Draw on the X server with the RENDER extension, instead of presenting images.
+    pub xrender: bool,
+    pub keys: Bindings,
+}
+
+/// What the renderer draws, for backends that draw on the display server instead of showing
+/// images. Glyphs are numbered so that they only need to be sent to the server once.
+pub enum DrawCommand {
+    /// Replace the rectangle with the color.
+    Fill(Rectangle, Color),
+    /// Blend the color over the image by the glyph's coverage, with its top left corner at `x`
+    /// and `y`, within `clip`.
+    Glyph {
+        id: u32,
+        coverage: Rc<Coverage>,
+        x: i32,
+        y: i32,
+        color: Color,
+        clip: Rectangle,
+    },
+    /// Draw the color glyph over the image, with its top left corner at `x` and `y`, within
+    /// `clip`. Color glyphs are numbered separately from the others.
+    ColorGlyph {
+        id: u32,
+        image: Rc<ColorImage>,
+        x: i32,
+        y: i32,
+        clip: Rectangle,
+    },
+}
+
+/// What to show in the window.
+pub enum Frame<'a> {
+    /// An image of the whole window.
+    Image(&'a Image<'a>),
+    /// The commands to draw since the last frame.
+    Commands(Vec<DrawCommand>),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Slant {
+    #[default]
+    Roman,
+    Italic,
+    Oblique,
+}
+
+/// A font in fontconfig's pattern syntax, like `JetBrains Mono-12:style=Bold Italic`. Only the
+/// first family of a list is used, and properties that don't select a face are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontPattern {
+    pub family: String,
+    /// In pixels.
+    pub size: Option<f32>,
+    pub bold: bool,
+    pub slant: Slant,
+}
+
+impl FromStr for FontPattern {
+    type Err = DmitriError;
+
+    fn from_str(s: &str) -> Result<FontPattern, DmitriError> {
+        let invalid = || DmitriError::Config(format!("Invalid font pattern: {}", s));
+        let mut properties = s.split(':');
+        let mut families = properties.next().unwrap_or_default();
+        let mut size = None;
+        // a size in points may follow the families, as in `Mono-12`
+        if let Some((rest, points)) = families.rsplit_once('-') {
+            if let Ok(points) = points.parse::<f32>() {
+                families = rest;
+                size = Some(points_to_pixels(points));
+            }
+        }
+        let family = families.split(',').next().unwrap_or_default().trim();
+        let mut pattern = FontPattern {
+            family: match family {
+                "" => String::from("monospace"),
+                family => family.to_string(),
+            },
+            size,
+            bold: false,
+            slant: Slant::Roman,
+        };
+        for property in properties {
+            let (name, value) = match property.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim().to_lowercase()),
+                // constants like `:bold` or `:italic` stand for `:weight=bold` or `:slant=italic`
+                None => {
+                    let constant = property.trim().to_lowercase();
+                    if matches!(constant.as_str(), "roman" | "italic" | "oblique") {
+                        ("slant", constant)
+                    } else {
+                        ("weight", constant)
+                    }
+                }
+            };
+            match name {
+                "size" => {
+                    let points = value.parse::<f32>().map_err(|_| invalid())?;
+                    pattern.size = Some(points_to_pixels(points));
+                }
+                "pixelsize" => pattern.size = Some(value.parse().map_err(|_| invalid())?),
+                "style" => {
+                    pattern.bold = ["bold", "black", "heavy"]
+                        .iter()
+                        .any(|weight| value.contains(weight));
+                    pattern.slant = if value.contains("italic") {
+                        Slant::Italic
+                    } else if value.contains("oblique") {
+                        Slant::Oblique
+                    } else {
+                        Slant::Roman
+                    };
+                }
+                "weight" => {
+                    pattern.bold = match value.parse::<u16>() {
+                        // fontconfig's numeric weights, where 200 is bold
+                        Ok(weight) => weight >= 180,
+                        Err(_) => value.contains("bold") || value == "black" || value == "heavy",
+                    }
+                }
+                "slant" => {
+                    pattern.slant = match value.as_str() {
+                        "roman" | "0" => Slant::Roman,
+                        "italic" | "100" => Slant::Italic,
+                        "oblique" | "110" => Slant::Oblique,
+                        _ => return Err(invalid()),
+                    }
+                }
+                // like antialias or hinting, which rusttype doesn't do
+                _ => (),
+            }
+        }
+        Ok(pattern)
+    }
+}
+
+/// fontconfig sizes are points, at the usual 96 DPI.
+fn points_to_pixels(points: f32) -> f32 {
+    points * 96. / 72.
 }
 
 trait FontRenderDest {
     fn set_pixel(&self, x: usize, y: usize, v: f32);
 }
 
+/// A font and its color glyphs, if it has any.
+struct Face {
+    font: Font<'static>,
+    colors: Option<ColorGlyphs>,
+}
+
+impl Face {
+    fn load(font_data: Vec<u8>, index: u32) -> Option<Face> {
+        let colors = ColorGlyphs::parse(&font_data, index);
+        let font = Font::try_from_vec_and_index(font_data, index)?;
+        Some(Face { font, colors })
+    }
+
+    fn has_glyph(&self, c: char) -> bool {
+        self.font.glyph(c).id() != GlyphId(0)
+    }
+}
+
+/// A rasterized glyph and its number, or `None` if there is nothing to draw.
+type Numbered<T> = Option<(u32, Rc<T>)>;
+
+/// A glyph of a laid out line, with its origin `x` pixels from the start of the line.
+#[derive(Clone, Copy)]
+struct LaidOutGlyph {
+    face: usize,
+    id: GlyphId,
+    x: f32,
+    /// Index of the first character of the grapheme cluster that the glyph belongs to.
+    cluster: usize,
+}
+
 pub struct FontRenderer<'a> {
-    font: Font<'a>,
+    /// The requested font, followed by the installed fonts that had glyphs it lacks.
+    faces: Vec<Face>,
+    /// Characters that were already searched for in the installed fonts.
+    searched: HashSet<char>,
+    /// Lines that were already laid out, by text, since matches are drawn again on each key.
+    layouts: HashMap<String, Rc<[LaidOutGlyph]>>,
+    /// Rasterized glyphs at `scale` by face, glyph and subpixel position, numbered in the order
+    /// they were rasterized, or `None` for glyphs without an outline.
+    coverages: HashMap<(usize, GlyphId, u8), Numbered<Coverage>>,
+    /// Color glyphs by face, glyph and text color, numbered like `coverages`, or `None` for
+    /// glyphs without colors.
+    color_images: HashMap<(usize, GlyphId, [u32; 4]), Numbered<ColorImage>>,
+    /// Icons by name, numbered together with `color_images`, or `None` for icons that weren't
+    /// found.
+    icon_images: HashMap<String, Numbered<ColorImage>>,
     image: Image<'a>,
+    /// Commands for the backend to draw, recorded instead of drawing into `image` after
+    /// `record_commands`.
+    commands: Option<Vec<DrawCommand>>,
+    /// A hash of what each line of the image shows, to redraw only the lines that changed.
+    drawn: Vec<Option<u64>>,
+    /// The first match shown after the input without a list, moved to keep the selection in view.
+    scroll: usize,
+    /// The horizontal span of each match drawn after the input, or the matches listed below it,
+    /// to find the match under the pointer.
+    spans: Vec<(usize, Range<u16>)>,
+    listed: Range<usize>,
+    /// Whether the blinking caret is drawn at the moment.
+    caret_shown: bool,
+    hints: bool,
+    counter: bool,
+    icons: bool,
+    icon_theme: Option<String>,
+    descriptions: bool,
+    details: bool,
+    /// The number of all items that match, not only of those kept.
+    total: usize,
+    /// The width at the right edge of the line that is kept free while drawing, for the counter.
+    reserved: u16,
     width: u16,
-    height: u16,
     margin: u16,
+    lines: u16,
+    prompt: Option<String>,
+    message: Option<String>,
+    line_height: u16,
     scale: Scale,
     color: Color,
     color_secondary: Color,
+    background: Color,
+    selected_bg: Color,
+    selected_fg: Color,
+    description_fg: Color,
     v_metrics: VMetrics,
     pixel_layout: PixelLayout,
+    alpha_mask: u32,
 }
 impl FontRenderer<'_> {
-    pub fn new<Dpy: Display + ?Sized>(
-        dpy: &mut Dpy,
-        depth: u8,
-        width: u16,
-        height: u16,
+    pub fn new(
+        image: Image<'static>,
+        pixel_layout: PixelLayout,
         options: &RunOptions,
-    ) -> Result<FontRenderer<'static>, Box<dyn Error>> {
-        let image = Image::allocate_native(width, height, depth, dpy.setup())?;
+    ) -> Result<FontRenderer<'static>, DmitriError> {
+        let width = image.width();
+        // 32 bit images have an alpha channel in the remaining bits, as with ARGB visuals
+        let alpha_mask = if image.depth() == 32 {
+            !pixel_layout.encode((0xffff, 0xffff, 0xffff))
+        } else {
+            0
+        };
 
-        let font = FontRenderer::font(&options.fontname)?;
+        let face = FontRenderer::face(&options.font)?;
 
         let scale = Scale::uniform(options.fontsize as f32);
 
         let color = options.color;
-        let color_secondary = (color.0 / 2., color.1 / 2., color.2 / 2.);
-
-        let v_metrics = font.v_metrics(scale);
+        let color_secondary = options.normal_fg;
 
-        let screen = &dpy.default_screen();
-        let pixel_layout = check_visual(screen, screen.root_visual);
+        let v_metrics = face.font.v_metrics(scale);
 
         Ok(FontRenderer {
-            font,
+            faces: vec![face],
+            searched: HashSet::new(),
+            layouts: HashMap::new(),
+            coverages: HashMap::new(),
+            color_images: HashMap::new(),
+            icon_images: HashMap::new(),
             image,
+            commands: None,
+            drawn: vec![None; options.lines as usize + 1 + options.message.is_some() as usize],
+            scroll: 0,
+            spans: vec![],
+            listed: 0..0,
+            caret_shown: true,
+            hints: options.hints,
+            counter: options.counter,
+            icons: options.icons,
+            icon_theme: options.icon_theme.clone(),
+            descriptions: options.descriptions,
+            details: options.details,
+            total: 0,
+            reserved: 0,
             width,
-            height,
             margin: options.margin,
+            lines: options.lines,
+            prompt: options.prompt.clone(),
+            message: options.message.clone(),
+            line_height: options.fontsize + options.margin * 2,
             scale,
             color,
             color_secondary,
+            background: options.normal_bg,
+            selected_bg: options.selected_bg,
+            selected_fg: options.selected_fg,
+            description_fg: options.description_fg,
             v_metrics,
             pixel_layout,
+            alpha_mask,
         })
     }
 
-    fn font(fontname: &Option<String>) -> Result<Font<'static>, Box<dyn Error>> {
-        let name = match fontname {
-            None => "monospace",
-            Some(name) => name,
+    /// Load the requested face, or else fontconfig's monospace font in the same style, or else
+    /// the embedded DejaVu Sans Mono. Falling back prints a warning.
+    fn face(pattern: &Option<FontPattern>) -> Result<Face, DmitriError> {
+        let (requested, bold, slant) = match pattern {
+            Some(pattern) => (pattern.family.as_str(), pattern.bold, pattern.slant),
+            None => ("monospace", false, Slant::Roman),
         };
+        let mut families = vec![requested, "monospace"];
+        families.dedup();
+        for family in families {
+            let mut builder = system_fonts::FontPropertyBuilder::new().monospace();
+            if bold {
+                builder = builder.bold();
+            }
+            builder = match slant {
+                Slant::Roman => builder,
+                Slant::Italic => builder.italic(),
+                Slant::Oblique => builder.oblique(),
+            };
+            let property = builder.family(family).build();
+            // nothing matches only when there are no fonts at all
+            let (font_data, index) = match system_fonts::get(&property) {
+                Some(font) => font,
+                None => break,
+            };
+            if family != "monospace" && !installed(family) {
+                eprintln!("dmitri: font {} not found, using monospace", family);
+                continue;
+            }
+            // only TrueType outlines are supported
+            match Face::load(font_data, index as u32) {
+                Some(face) => return Ok(face),
+                None => eprintln!("dmitri: font {} can't be loaded", family),
+            }
+        }
 
-        let property = system_fonts::FontPropertyBuilder::new()
-            .monospace()
-            .family(name)
-            .family("ProFontWindows Nerd Font Mono")
-            .build();
-        let (font_data, _) =
-            system_fonts::get(&property).ok_or("Could not get system fonts property")?;
+        eprintln!("dmitri: no usable system font, using the embedded DejaVu Sans Mono");
+        Face::load(EMBEDDED_FONT.to_vec(), 0)
+            .ok_or_else(|| DmitriError::Font(String::from("The embedded font is invalid")))
+    }
+
+    pub fn image(&self) -> &Image<'_> {
+        &self.image
+    }
+
+    /// Record drawing commands instead of drawing into the image, for backends that draw
+    /// themselves.
+    pub fn record_commands(&mut self) {
+        self.commands = Some(vec![]);
+    }
+
+    /// What was drawn, to be presented after `render_text`.
+    pub fn frame(&mut self) -> Frame<'_> {
+        match &mut self.commands {
+            Some(commands) => Frame::Commands(mem::take(commands)),
+            None => Frame::Image(&self.image),
+        }
+    }
 
-        let font: Font<'static> = Font::try_from_vec(font_data).expect("Error constructing Font");
-        Ok(font)
+    /// Draw all lines on the next render, as when the window contents were lost.
+    pub fn invalidate(&mut self) {
+        self.drawn.fill(None);
     }
 
-    pub fn render_text<Dpy: Display + ?Sized>(
+    /// Draw the message, the prompt and the input with a caret at the byte offset `caret` unless it
+    /// blinked off, followed by the matches. The characters at the indices in `highlights` of each match are drawn in the
+    /// primary color. Only lines that show something else than before are drawn again, and the
+    /// ranges of rows that changed are returned.
+    pub fn render_text(
         &mut self,
-        dpy: &mut Dpy,
-        window: Window,
-        gc: Gcontext,
         input: &str,
-        matches: &[String],
+        caret: Option<usize>,
+        matches: &[Item],
+        highlights: &[Vec<usize>],
+        marked: &[bool],
         matches_i: Option<usize>,
-    ) -> Result<(), Box<dyn Error>> {
-        // turn off checked mode to speed up painting
-        // dpy.set_checked(false);
+    ) -> Vec<Range<u16>> {
+        let mut damage = vec![];
+        let caret = caret.filter(|_| self.caret_shown);
+        if let Some(message) = self.message.clone() {
+            if self.redraw_line(0, hash(&message), &mut damage) {
+                self.render_glyphs(0, 0, &message, self.color);
+            }
+        }
+        let top = self.input_line();
+        let counter = self.counter.then(|| match matches_i {
+            Some(i) => format!("{}/{}", i + 1, self.total),
+            None => self.total.to_string(),
+        });
+        // without a list, the matches are on the input line
+        let input_line = if self.lines > 0 {
+            hash((input, caret, matches_i.is_none(), &counter))
+        } else {
+            hash((
+                input, caret, matches, highlights, marked, matches_i, &counter,
+            ))
+        };
+        if self.redraw_line(top, input_line, &mut damage) {
+            if let Some(counter) = &counter {
+                self.reserved = self.measure(&format!(" {}", counter));
+            }
+            self.render_input(input, caret, matches, highlights, marked, matches_i);
+            self.reserved = 0;
+            if let Some(counter) = &counter {
+                let y = top as u16 * self.line_height;
+                self.render_right(y, counter, self.color_secondary);
+            }
+        }
 
-        // clear image
-        let data = self.image.data_mut();
-        for i in data {
-            *i = 0;
+        if self.lines > 0 {
+            // show the page of matches that contains the selection
+            let lines = self.lines as usize;
+            let start = matches_i.map(|i| i / lines * lines).unwrap_or(0);
+            self.listed = start..matches.len().min(start + lines);
+            let column = self.description_column(matches, marked);
+            for line in 1..=lines {
+                let i = start + line - 1;
+                let shown = matches.get(i).map(|m| {
+                    let marked = marked.get(i) == Some(&true);
+                    (m, highlights.get(i), marked, matches_i == Some(i))
+                });
+                if self.redraw_line(top + line, hash((shown, column)), &mut damage) {
+                    if let Some((m, highlight, marked, selected)) = shown {
+                        let y = (top + line) as u16 * self.line_height;
+                        // the match and its description are cut off before the detail and the
+                        // badge
+                        let badge = m
+                            .badge()
+                            .map_or(0, |badge| self.measure(&format!("  {} ", badge.text)));
+                        // at most half of the line, so that the match stays in view
+                        let detail =
+                            m.detail()
+                                .filter(|_| self.details && selected)
+                                .map(|detail| {
+                                    let half = self.max_x().saturating_sub(self.margin + badge) / 2;
+                                    self.ellipsized(detail, half)
+                                        .unwrap_or_else(|| detail.to_string())
+                                });
+                        let detail_width = detail
+                            .as_ref()
+                            .map_or(0, |detail| self.measure(&format!("  {}", detail)));
+                        self.reserved = badge + detail_width;
+                        let decoration = self.decoration_width(line - 1, marked);
+                        let offset = decoration + self.icon_width();
+                        let end = self.render_match(offset, y, m.display(), highlight, selected);
+                        self.render_description(end, column, y, m.description(), selected);
+                        self.reserved = badge;
+                        if let Some(detail) = &detail {
+                            self.render_detail(y, detail);
+                        }
+                        self.reserved = 0;
+                        if let Some(badge) = m.badge() {
+                            self.render_badge(y, badge);
+                        }
+                        // over the box of a selected match
+                        self.render_decoration(0, y, line - 1, marked, selected);
+                        self.render_icon(decoration, y, m.icon(), selected);
+                    }
+                }
+            }
         }
+        damage
+    }
+
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        self.prompt = prompt;
+        let input_line = self.input_line();
+        self.drawn[input_line] = None;
+    }
 
-        if input.is_empty() {
-            self.render_glyphs(0, "_", self.color);
+    /// Set the number of all items that match the input, for the counter.
+    pub fn set_total(&mut self, total: usize) {
+        self.total = total;
+    }
+
+    /// Hide the caret if it is shown and show it otherwise, from the next render on.
+    pub fn blink(&mut self) {
+        self.caret_shown = !self.caret_shown;
+    }
+
+    /// Show the caret again, as it stays while typing.
+    pub fn show_caret(&mut self) {
+        self.caret_shown = true;
+    }
+
+    /// The index of the match drawn at `x`, `y` in the window, if any.
+    pub fn match_at(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 {
+            return None;
+        }
+        let line = (y as usize / self.line_height as usize).checked_sub(self.input_line())?;
+        if self.lines == 0 {
+            let x = x.min(u16::MAX as i32) as u16;
+            return self
+                .spans
+                .iter()
+                .find(|(_, span)| line == 0 && span.contains(&x))
+                .map(|(i, _)| *i);
+        }
+        let i = (self.listed.start + line).checked_sub(1)?;
+        (line > 0 && self.listed.contains(&i)).then_some(i)
+    }
+
+    /// The `n`th shown match, counting from 0.
+    pub fn shown(&self, n: usize) -> Option<usize> {
+        if self.lines == 0 {
+            return self.spans.get(n).map(|(i, _)| *i);
+        }
+        let i = self.listed.start + n;
+        self.listed.contains(&i).then_some(i)
+    }
+
+    /// The line of the input, below the message if there is one.
+    fn input_line(&self) -> usize {
+        self.message.is_some() as usize
+    }
+
+    /// Clear the line if it shows something else than what was drawn, and add its rows to the
+    /// damage.
+    fn redraw_line(&mut self, line: usize, content: u64, damage: &mut Vec<Range<u16>>) -> bool {
+        if self.drawn[line] == Some(content) {
+            return false;
+        }
+        self.drawn[line] = Some(content);
+        let y = line as u16 * self.line_height;
+        let end = (y + self.line_height).min(self.image.height());
+        self.fill_rect(0, y, self.width, self.line_height, self.background);
+        match damage.last_mut() {
+            Some(last) if last.end == y => last.end = end,
+            _ => damage.push(y..end),
+        }
+        true
+    }
+
+    /// Draw the prompt and the input, and the matches after them if there is no list.
+    fn render_input(
+        &mut self,
+        input: &str,
+        caret: Option<usize>,
+        matches: &[Item],
+        highlights: &[Vec<usize>],
+        marked: &[bool],
+        matches_i: Option<usize>,
+    ) {
+        let mut x: u16 = 0;
+        let y = self.input_line() as u16 * self.line_height;
+        if let Some(prompt) = self.prompt.clone() {
+            x = self.render_glyphs(x, y, &(prompt + " "), self.color_secondary);
+        }
+        let color = if matches_i.is_none() {
+            self.color
         } else {
-            let mut x: u16 = 0;
-            let color = if matches_i.is_none() {
-                self.color
+            self.color_secondary
+        };
+        match caret {
+            Some(caret) => {
+                x = self.render_glyphs(x, y, &input[..caret], color);
+                self.render_caret(x, y, color);
+                x = self.render_glyphs(x, y, &input[caret..], color);
+            }
+            None => x = self.render_glyphs(x, y, input, color),
+        }
+
+        if self.lines == 0 {
+            match matches_i {
+                Some(i) => self.scroll_to(i, x, matches, marked),
+                None => self.scroll = 0,
+            }
+            if self.scroll > 0 {
+                x = self.render_glyphs(x, y, " <", self.color_secondary);
+            }
+            self.spans.clear();
+            let shown_matches = matches.iter().enumerate().skip(self.scroll);
+            for (shown, (i, item)) in shown_matches.enumerate() {
+                let m = item.display();
+                x = self.render_glyphs(x, y, " ", self.color_secondary);
+                let is_marked = marked.get(i) == Some(&true);
+                x = self.render_decoration(x, y, shown, is_marked, false);
+                // the match that reaches the end of the line is the last one, if any of it fits
+                let available = self
+                    .max_x()
+                    .saturating_sub(self.margin + x + self.icon_width());
+                let cut = self.ellipsized(m, available);
+                if cut.as_deref() == Some("…") {
+                    break;
+                }
+                let start = self.margin + x;
+                x = self.render_icon(x, y, item.icon(), false);
+                x = self.render_match(x, y, m, highlights.get(i), matches_i == Some(i));
+                self.spans.push((i, start..self.margin + x));
+                if cut.is_some() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Scroll the matches after the input, which ends at `x`, so that the selected match `i` is
+    /// the first one or fits in the width.
+    fn scroll_to(&mut self, i: usize, x: u16, matches: &[Item], marked: &[bool]) {
+        if i <= self.scroll {
+            self.scroll = i;
+            return;
+        }
+        let max_x = self.max_x();
+        let (space, arrow) = (self.measure(" "), self.measure(" <"));
+        while self.scroll < i {
+            let mut end = x + arrow;
+            for (shown, j) in (self.scroll..=i).enumerate() {
+                let decoration = self.decoration_width(shown, marked.get(j) == Some(&true));
+                let width = decoration + self.icon_width() + self.measure(matches[j].display());
+                end = end.saturating_add(space + width);
+            }
+            if end <= max_x {
+                break;
+            }
+            self.scroll += 1;
+        }
+    }
+
+    /// What is drawn before the `shown`th shown match: its number with hints, and a star if it is
+    /// marked.
+    fn decoration(&self, shown: usize, marked: bool) -> String {
+        let mut decoration = String::new();
+        if self.hints && shown < 9 {
+            decoration += &format!("{} ", shown + 1);
+        }
+        if marked {
+            decoration += "* ";
+        }
+        decoration
+    }
+
+    fn decoration_width(&mut self, shown: usize, marked: bool) -> u16 {
+        match self.decoration(shown, marked) {
+            decoration if decoration.is_empty() => 0,
+            decoration => self.measure(&decoration),
+        }
+    }
+
+    /// Draw the decoration of the `shown`th shown match in the colors of the match, with the star
+    /// of a marked match in the primary color.
+    fn render_decoration(
+        &mut self,
+        offset: u16,
+        y: u16,
+        shown: usize,
+        marked: bool,
+        selected: bool,
+    ) -> u16 {
+        let decoration = self.decoration(shown, marked);
+        if decoration.is_empty() {
+            return offset;
+        }
+        let (background, color, star) = if selected {
+            (self.selected_bg, self.selected_fg, self.selected_fg)
+        } else {
+            (self.background, self.color_secondary, self.color)
+        };
+        let star_at = decoration.len() - 2;
+        self.render_glyphs_colored(offset, y, &decoration, background, |i| {
+            if marked && i >= star_at {
+                star
+            } else {
+                color
+            }
+        });
+        offset + self.measure(&decoration)
+    }
+
+    /// The width of an icon and the space after it, if icons are drawn.
+    fn icon_width(&self) -> u16 {
+        if self.icons {
+            self.line_height - self.margin
+        } else {
+            0
+        }
+    }
+
+    /// Draw the icon of a match, if icons are drawn and it has one that is found, and leave its
+    /// width free either way so that the matches line up.
+    fn render_icon(&mut self, offset: u16, y: u16, icon: Option<&str>, selected: bool) -> u16 {
+        if !self.icons {
+            return offset;
+        }
+        let end = offset + self.icon_width();
+        if self.margin + end > self.max_x() {
+            return end;
+        }
+        if let Some(image) = icon.and_then(|icon| self.icon_image(icon)) {
+            let background = if selected {
+                self.selected_bg
             } else {
-                self.color_secondary
+                self.background
             };
-            x = self.render_glyphs(x, input, color);
+            let rows = y as i32..(y + self.line_height) as i32;
+            let x = (self.margin + offset) as i32 + image.1.x;
+            let y = (y + self.margin) as i32 + image.1.y;
+            self.draw_color_image(x, y, &rows, &image, background);
+        }
+        end
+    }
 
-            for (i, m) in matches.iter().enumerate() {
-                x = self.render_glyphs(x, " ", self.color_secondary);
-                let color = if let Some(m_i) = matches_i {
-                    if m_i == i {
-                        self.color
-                    } else {
-                        self.color_secondary
-                    }
-                } else {
-                    self.color_secondary
+    /// Where the descriptions of the listed matches start: after the widest match, but at most
+    /// halfway through the line, so that one long match doesn't push all of them out of view.
+    fn description_column(&mut self, matches: &[Item], marked: &[bool]) -> u16 {
+        if !self.descriptions {
+            return 0;
+        }
+        let mut column = 0;
+        for (line, i) in self.listed.clone().enumerate() {
+            let decoration = self.decoration_width(line, marked.get(i) == Some(&true));
+            let width = decoration + self.icon_width() + self.measure(matches[i].display());
+            column = column.max(width);
+        }
+        let half = self.max_x().saturating_sub(self.margin) / 2;
+        (column + self.measure("  ")).min(half)
+    }
+
+    /// Draw the description of a listed match, which ends at `end`, in its own color at the
+    /// column, or further right if the match is wider. It is cut off where the line ends.
+    fn render_description(
+        &mut self,
+        end: u16,
+        column: u16,
+        y: u16,
+        description: Option<&str>,
+        selected: bool,
+    ) {
+        let description = match description {
+            Some(description) if self.descriptions => description,
+            _ => return,
+        };
+        let x = column.max(end + self.measure("  "));
+        let available = self.max_x().saturating_sub(self.margin + x);
+        let ellipsized = self.ellipsized(description, available);
+        let text = ellipsized.as_deref().unwrap_or(description);
+        if text == "…" {
+            return;
+        }
+        let (background, color) = if selected {
+            (self.selected_bg, self.selected_fg)
+        } else {
+            (self.background, self.description_fg)
+        };
+        self.render_glyphs_colored(x, y, text, background, |_| color);
+    }
+
+    /// The text cut off with an ellipsis so that it fits in the width, or `None` if it fits as it
+    /// is.
+    fn ellipsized(&mut self, text: &str, width: u16) -> Option<String> {
+        if self.measure(text) <= width {
+            return None;
+        }
+        let space = width.saturating_sub(self.measure("…")) as f32;
+        let glyphs = self.layout(&(text.to_string() + " "));
+        // glyph `n` starts where the first `n` characters end, and the cut can't split a letter
+        // from its combining marks
+        let kept = glyphs
+            .iter()
+            .take_while(|glyph| glyph.x <= space)
+            .count()
+            .saturating_sub(1);
+        let kept = glyphs.get(kept).map_or(kept, |glyph| glyph.cluster);
+        Some(text.chars().take(kept).chain(['…']).collect())
+    }
+
+    /// Draw a match, cut off with an ellipsis where the line ends. It is drawn on a filled box if it
+    /// is selected, otherwise with its highlighted characters in the primary color.
+    fn render_match(
+        &mut self,
+        offset: u16,
+        y: u16,
+        text: &str,
+        highlight: Option<&Vec<usize>>,
+        selected: bool,
+    ) -> u16 {
+        let available = self.max_x().saturating_sub(self.margin + offset);
+        let ellipsized = self.ellipsized(text, available);
+        let text = ellipsized.as_deref().unwrap_or(text);
+        if selected {
+            let (x, width) = if self.lines > 0 {
+                (0, self.width)
+            } else {
+                let padding = self.margin / 2;
+                (
+                    (self.margin + offset).saturating_sub(padding),
+                    self.measure(text) + padding * 2,
+                )
+            };
+            let (background, color) = (self.selected_bg, self.selected_fg);
+            self.fill_rect(x, y, width, self.line_height, background);
+            return self.render_glyphs_colored(offset, y, text, background, |_| color);
+        }
+        let (color, color_secondary) = (self.color, self.color_secondary);
+        self.render_glyphs_colored(offset, y, text, self.background, |i| {
+            if highlight.is_some_and(|highlight| highlight.contains(&i)) {
+                color
+            } else {
+                color_secondary
+            }
+        })
+    }
+
+    /// Width of the text when drawn with `render_glyphs`, without the trailing space.
+    fn measure(&mut self, text: &str) -> u16 {
+        self.layout(&(text.to_string() + " "))
+            .last()
+            .map_or(0, |glyph| glyph.x as u16)
+    }
+
+    /// Lay out the text, one glyph per character, each from the first face that has it. Combining
+    /// marks are taken from the face of the character they combine with if it has them. Kerning
+    /// only applies between glyphs of the same face.
+    fn layout(&mut self, text: &str) -> Rc<[LaidOutGlyph]> {
+        if let Some(glyphs) = self.layouts.get(text) {
+            return glyphs.clone();
+        }
+        let mut glyphs = Vec::with_capacity(text.len());
+        let mut x = 0.0;
+        let mut last: Option<(usize, GlyphId)> = None;
+        let mut cluster = 0;
+        for grapheme in text.graphemes(true) {
+            let mut base: Option<usize> = None;
+            for c in grapheme.chars() {
+                let i = match base {
+                    Some(i) if self.faces[i].has_glyph(c) => i,
+                    _ => self.face_for(c),
                 };
-                x = self.render_glyphs(x, m, color);
-                if x > self.width as _ {
-                    break;
+                base.get_or_insert(i);
+                let font = &self.faces[i].font;
+                let glyph = font.glyph(c).scaled(self.scale);
+                if let Some((last_i, last_id)) = last {
+                    if last_i == i {
+                        x += font.pair_kerning(self.scale, last_id, glyph.id());
+                    }
                 }
+                last = Some((i, glyph.id()));
+                glyphs.push(LaidOutGlyph {
+                    face: i,
+                    id: glyph.id(),
+                    x,
+                    cluster,
+                });
+                x += glyph.h_metrics().advance_width;
             }
+            cluster += grapheme.chars().count();
         }
+        let glyphs: Rc<[LaidOutGlyph]> = glyphs.into();
+        if self.layouts.len() >= MAX_LAYOUTS {
+            self.layouts.clear();
+        }
+        self.layouts.insert(text.to_string(), glyphs.clone());
+        glyphs
+    }
 
-        dpy.put_image(
-            ImageFormat::Z_PIXMAP,
-            window,
-            gc,
-            self.width,
-            self.height,
-            0,
-            0,
-            0,
-            self.pixel_layout.depth(),
-            &self.image.data(),
-        )?;
-        dpy.flush()?;
+    /// The index in `faces` of the first face with a glyph for the character, loading an
+    /// installed font that has one if none does. Emoji are taken from color fonts if possible.
+    /// Characters without any glyph are drawn with the requested font's missing glyph.
+    fn face_for(&mut self, c: char) -> usize {
+        let emoji = is_emoji(c);
+        let found = self
+            .faces
+            .iter()
+            .position(|face| face.has_glyph(c) && (!emoji || face.colors.is_some()));
+        if let Some(i) = found {
+            return i;
+        }
+        if !c.is_control() && self.searched.insert(c) {
+            if let Some(face) = fallback_face(c, emoji) {
+                self.faces.push(face);
+                return self.faces.len() - 1;
+            }
+        }
+        self.faces
+            .iter()
+            .position(|face| face.has_glyph(c))
+            .unwrap_or(0)
+    }
+
+    /// The glyph rasterized at the nearest subpixel position and its number, rasterizing it only
+    /// once.
+    fn coverage(&mut self, glyph: LaidOutGlyph) -> Numbered<Coverage> {
+        let subpixel = (glyph.x.fract() * SUBPIXEL_STEPS) as u8;
+        let (font, scale) = (&self.faces[glyph.face].font, self.scale);
+        // the baseline is at the ascent, which may not be a whole pixel either
+        let offset = point(
+            subpixel as f32 / SUBPIXEL_STEPS,
+            self.v_metrics.ascent.fract(),
+        );
+        let id = self.coverages.len() as u32;
+        self.coverages
+            .entry((glyph.face, glyph.id, subpixel))
+            .or_insert_with(|| {
+                Coverage::rasterize(font.glyph(glyph.id).scaled(scale), offset)
+                    .map(|coverage| (id, Rc::new(coverage)))
+            })
+            .clone()
+    }
+
+    /// The color version of the glyph and its number, if its face has one.
+    fn color_image(&mut self, glyph: LaidOutGlyph, foreground: Color) -> Numbered<ColorImage> {
+        let face = glyph.face;
+        let colors = self.faces[face].colors.as_ref()?;
+        let key = (
+            face,
+            glyph.id,
+            [
+                foreground.0.to_bits(),
+                foreground.1.to_bits(),
+                foreground.2.to_bits(),
+                foreground.3.to_bits(),
+            ],
+        );
+        let (font, scale) = (&self.faces[face].font, self.scale);
+        let id = (self.color_images.len() + self.icon_images.len()) as u32;
+        self.color_images
+            .entry(key)
+            .or_insert_with(|| {
+                colors
+                    .rasterize(font, glyph.id, scale, foreground)
+                    .map(|image| (id, Rc::new(image)))
+            })
+            .clone()
+    }
+
+    /// The icon scaled to the height of the text and its number, if it is found.
+    fn icon_image(&mut self, icon: &str) -> Numbered<ColorImage> {
+        let size = (self.line_height - self.margin * 2) as u32;
+        let id = (self.color_images.len() + self.icon_images.len()) as u32;
+        let theme = self.icon_theme.as_deref();
+        self.icon_images
+            .entry(icon.to_string())
+            .or_insert_with(|| icons::load(icon, theme, size).map(|image| (id, Rc::new(image))))
+            .clone()
+    }
 
-        Ok(())
+    fn fill_rect(&mut self, x: u16, y: u16, width: u16, height: u16, color: Color) {
+        let max_x = (x + width).min(self.width);
+        let max_y = (y + height).min(self.image.height());
+        if let Some(commands) = &mut self.commands {
+            let rect = Rectangle {
+                x: x as i16,
+                y: y as i16,
+                width: max_x.saturating_sub(x),
+                height: max_y.saturating_sub(y),
+            };
+            commands.push(DrawCommand::Fill(rect, color));
+            return;
+        }
+        let pixel = encode(self.pixel_layout, self.alpha_mask, color);
+        for y in y..max_y {
+            for x in x..max_x {
+                self.image.put_pixel(x, y, pixel);
+            }
+        }
     }
 
-    fn render_glyphs(&mut self, offset: u16, text: &str, color: Color) -> u16 {
-        let glyphs: Vec<_> = self
-            .font
-            .layout(
-                &(text.to_string() + " "),
-                self.scale,
-                point(0.0, 0.0 + self.v_metrics.ascent),
-            )
-            .collect();
+    /// Draw a bar as high as the text, just before the character at `x`.
+    fn render_caret(&mut self, x: u16, y: u16, color: Color) {
+        let x = self.margin + x;
+        let max_x = self.max_x();
+        let height = self.line_height - self.margin * 2;
+        self.fill_rect(
+            x,
+            y + self.margin,
+            (x + 2).min(max_x).saturating_sub(x),
+            height,
+            color,
+        );
+    }
+
+    /// Draw the badge of a listed match at the end of its line: its text in the background color
+    /// on a box of its own color.
+    fn render_badge(&mut self, y: u16, badge: &Badge) {
+        let color = match parse_color(&badge.color) {
+            Some(color) => color_from_u8(color),
+            None => return,
+        };
+        let padding = self.measure(" ");
+        let width = self.measure(&badge.text);
+        let offset = self.max_x().saturating_sub(self.margin + width + padding);
+        self.fill_rect(
+            (self.margin + offset).saturating_sub(padding),
+            y + self.margin,
+            width + padding * 2,
+            self.line_height - self.margin * 2,
+            color,
+        );
+        let background = self.background;
+        self.render_glyphs_colored(offset, y, &badge.text, color, |_| background);
+    }
+
+    /// Draw the detail of the selected match so that it ends where text is cut off, on the box
+    /// of the selection.
+    fn render_detail(&mut self, y: u16, detail: &str) {
+        let offset = self
+            .max_x()
+            .saturating_sub(self.margin + self.measure(detail));
+        let (background, color) = (self.selected_bg, self.selected_fg);
+        self.render_glyphs_colored(offset, y, detail, background, |_| color);
+    }
+
+    /// Draw the text so that it ends where text is cut off.
+    fn render_right(&mut self, y: u16, text: &str, color: Color) {
+        let offset = self
+            .max_x()
+            .saturating_sub(self.margin + self.measure(text));
+        self.render_glyphs(offset, y, text, color);
+    }
+
+    /// Where text is cut off, before the reserved width.
+    fn max_x(&self) -> u16 {
+        self.width
+            .saturating_sub(self.margin * 2)
+            .saturating_sub(self.reserved)
+    }
+
+    fn render_glyphs(&mut self, offset: u16, y: u16, text: &str, color: Color) -> u16 {
+        self.render_glyphs_colored(offset, y, text, self.background, |_| color)
+    }
+
+    /// Draw the text over the given background color, with the color of each character given by
+    /// its index. Combining marks have the color of the character they combine with.
+    fn render_glyphs_colored(
+        &mut self,
+        offset: u16,
+        y: u16,
+        text: &str,
+        background: Color,
+        colors: impl Fn(usize) -> Color,
+    ) -> u16 {
+        let glyphs = self.layout(&(text.to_string() + " "));
+        let baseline = (self.margin + y) as i32 + self.v_metrics.ascent as i32;
+        let max_x = self.max_x() as i32;
+        // glyphs of fallback fonts may reach into the lines around, which are drawn separately
+        let rows = y as i32..(y + self.line_height) as i32;
 
         let mut next_x = offset;
-        for glyph in glyphs {
-            if let Some(bounding_box) = glyph.pixel_bounding_box() {
-                let mut outside = false;
-                let dst_x = self.margin + offset + (bounding_box.min.x as u16);
-                let dst_y = self.margin + (bounding_box.min.y as u16);
-                let max_x = self.width - self.margin * 2;
-                glyph.draw(|p_x, p_y, v| {
-                    let x = dst_x + p_x as u16;
-                    let y = dst_y + p_y as u16;
-                    if x < max_x {
-                        let pixel = self.pixel_layout.encode((
-                            (color.0 * v) as u16,
-                            (color.1 * v) as u16,
-                            (color.2 * v) as u16,
-                        ));
-                        self.image.put_pixel(x, y, pixel);
-                    } else {
-                        outside = true;
-                    }
-                });
-                if outside {
+        for &glyph in glyphs.iter() {
+            let color = colors(glyph.cluster);
+            let origin = (self.margin + offset) as i32 + glyph.x as i32;
+            if let Some(image) = self.color_image(glyph, color) {
+                let x = origin + image.1.x;
+                if x + image.1.width as i32 > max_x {
                     break;
-                } else {
-                    next_x = offset + bounding_box.max.x as u16;
                 }
+                self.draw_color_image(x, baseline + image.1.y, &rows, &image, background);
+                next_x = next_x.max((x + image.1.width as i32) as u16 - self.margin);
+            } else if let Some(coverage) = self.coverage(glyph) {
+                let x = origin + coverage.1.x;
+                let y = baseline + coverage.1.y;
+                if !self.draw_coverage(x, y, &rows, &coverage, color, background) {
+                    break;
+                }
+                // a combining mark may end before the character it is drawn over
+                next_x = next_x.max((x + coverage.1.width as i32) as u16 - self.margin);
             } else {
-                next_x = offset + glyph.position().x as u16;
+                next_x = next_x.max(offset + glyph.x as u16);
             }
         }
         next_x
     }
-}
 
-/// Check that the given visual is "as expected" (pixel values are 0xRRGGBB with RR/GG/BB being the
-/// colors). Otherwise, this exits the process.
-fn check_visual(screen: &Screen, id: Visualid) -> PixelLayout {
-    // Find the information about the visual and at the same time check its depth.
-    let visual_info = screen.allowed_depths.iter().find_map(|depth| {
-        let info = depth.visuals.iter().find(|depth| depth.visual_id == id);
-        info.map(|info| (depth.depth, info))
-    });
-    let (depth, visual_type) = match visual_info {
-        Some(info) => info,
-        None => {
-            eprintln!("Did not find the root visual's description?!");
-            std::process::exit(1);
+    /// Blend the color over the background color by the coverage, with its top left corner at
+    /// `x` and `y`, within `rows`. Whether it fit in the width.
+    fn draw_coverage(
+        &mut self,
+        x: i32,
+        y: i32,
+        rows: &Range<i32>,
+        (id, coverage): &(u32, Rc<Coverage>),
+        color: Color,
+        background: Color,
+    ) -> bool {
+        let max_x = self.max_x() as i32;
+        // a glyph that reaches past the width is cut off there, and ends the line
+        let fits = coverage.values.iter().enumerate().all(|(i, &v)| {
+            let column = x + (i as u32 % coverage.width) as i32;
+            let row = y + (i as u32 / coverage.width) as i32;
+            v == 0. || column < max_x || !rows.contains(&row)
+        });
+        if let Some(commands) = &mut self.commands {
+            commands.push(DrawCommand::Glyph {
+                id: *id,
+                coverage: coverage.clone(),
+                x,
+                y,
+                color,
+                clip: clip(max_x, rows),
+            });
+            return fits;
         }
-    };
-    // Check that the pixels have red/green/blue components that we can set directly.
-    match visual_type.class {
-        VisualClass::TRUE_COLOR | VisualClass::DIRECT_COLOR => {}
-        _ => {
-            eprintln!(
-                "The root visual is not true / direct color, but {:?}",
-                visual_type,
+        for (i, &v) in coverage.values.iter().enumerate() {
+            let x = x + (i as u32 % coverage.width) as i32;
+            let y = y + (i as u32 / coverage.width) as i32;
+            if v == 0. || x < 0 || x >= max_x || !rows.contains(&y) {
+                continue;
+            }
+            let pixel = encode(
+                self.pixel_layout,
+                self.alpha_mask,
+                (
+                    background.0 + (color.0 - background.0) * v,
+                    background.1 + (color.1 - background.1) * v,
+                    background.2 + (color.2 - background.2) * v,
+                    background.3 + (color.3 - background.3) * v,
+                ),
+            );
+            self.image.put_pixel(x as u16, y as u16, pixel);
+        }
+        fits
+    }
+
+    /// Draw the image with its top left corner at `x` and `y` within `rows`, blended over the
+    /// background color.
+    fn draw_color_image(
+        &mut self,
+        x: i32,
+        y: i32,
+        rows: &Range<i32>,
+        (id, image): &(u32, Rc<ColorImage>),
+        background: Color,
+    ) {
+        let max_x = self.max_x() as i32;
+        if let Some(commands) = &mut self.commands {
+            commands.push(DrawCommand::ColorGlyph {
+                id: *id,
+                image: image.clone(),
+                x,
+                y,
+                clip: clip(max_x, rows),
+            });
+            return;
+        }
+        for (i, color) in image.pixels.iter().enumerate() {
+            let x = x + (i as u32 % image.width) as i32;
+            let y = y + (i as u32 / image.width) as i32;
+            if x < 0 || !rows.contains(&y) {
+                continue;
+            }
+            let v = color.3 / 65535.;
+            let pixel = encode(
+                self.pixel_layout,
+                self.alpha_mask,
+                (
+                    background.0 + (color.0 - background.0) * v,
+                    background.1 + (color.1 - background.1) * v,
+                    background.2 + (color.2 - background.2) * v,
+                    background.3 + (65535. - background.3) * v,
+                ),
             );
-            std::process::exit(1);
+            self.image.put_pixel(x as u16, y as u16, pixel);
+        }
+    }
+}
+
+/// The part of a line left of `max_x`, for the given rows of the image.
+fn clip(max_x: i32, rows: &Range<i32>) -> Rectangle {
+    Rectangle {
+        x: 0,
+        y: rows.start as i16,
+        width: max_x as u16,
+        height: (rows.end - rows.start) as u16,
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Whether fontconfig has the family, since it matches some font for any name. font-loader can't
+/// list zero fonts, so this must only be called when some font is installed.
+fn installed(family: &str) -> bool {
+    system_fonts::query_all()
+        .iter()
+        .any(|installed| installed.eq_ignore_ascii_case(family))
+}
+
+/// Characters that are presented as emoji by default, roughly.
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x231a..=0x231b | 0x23e9..=0x23fa | 0x2600..=0x27bf | 0x2b50..=0x2b55 | 0x1f000..=0x1faff
+    )
+}
+
+/// An installed font with a glyph for the character. font-loader can't ask fontconfig for a
+/// character, so each family is loaded in turn until one has it. For emoji, fontconfig's emoji
+/// family is tried first, and a font with color glyphs is preferred.
+fn fallback_face(c: char, emoji: bool) -> Option<Face> {
+    // font-loader can't list zero fonts
+    system_fonts::get(&system_fonts::FontPropertyBuilder::new().build())?;
+    let mut families = system_fonts::query_all();
+    if emoji {
+        families.insert(0, String::from("emoji"));
+    }
+    let mut monochrome = None;
+    for family in families {
+        let property = system_fonts::FontPropertyBuilder::new()
+            .family(&family)
+            .build();
+        let face = match system_fonts::get(&property)
+            .and_then(|(font_data, index)| Face::load(font_data, index as u32))
+        {
+            Some(face) if face.has_glyph(c) => face,
+            _ => continue,
+        };
+        if !emoji || face.colors.is_some() {
+            return Some(face);
         }
+        monochrome.get_or_insert(face);
     }
-    let result = PixelLayout::from_visual_type(*visual_type)
-        .expect("The server sent a malformed visual type");
-    assert_eq!(result.depth(), depth);
-    result
+    monochrome
 }
@@ -0,0 +1,120 @@
+use crate::{
+    error::DmitriError,
+    items::{spawn_detached, Item, ItemSink, ItemSource, Terminal},
+};
+use std::{env, process};
+
+/// The sessions of the tmux server, each followed by its windows as `session:index name`, with
+/// the number of windows of sessions and the command running in windows as descriptions. There
+/// are none if no server runs.
+pub struct TmuxSource;
+
+impl ItemSource for TmuxSource {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        let sessions = tmux(&[
+            "list-sessions",
+            "-F",
+            "#{session_name}\t#{session_windows}\t#{session_attached}",
+        ])?;
+        let windows = tmux(&[
+            "list-windows",
+            "-a",
+            "-F",
+            "#{session_name}\t#{window_index}\t#{window_name}\t#{pane_current_command}",
+        ])?;
+        let mut items = vec![];
+        for line in sessions.lines() {
+            let mut fields = line.split('\t');
+            let (session, count, attached) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(session), Some(count), Some(attached)) => (session, count, attached),
+                _ => continue,
+            };
+            let plural = if count == "1" { "" } else { "s" };
+            let attached = if attached == "0" { "" } else { ", attached" };
+            items.push(
+                Item::with_value(session.to_string(), session.to_string())
+                    .with_description(Some(format!("{} window{}{}", count, plural, attached))),
+            );
+            for line in windows.lines() {
+                let mut fields = line.split('\t');
+                if fields.next() != Some(session) {
+                    continue;
+                }
+                let (index, name, command) = match (fields.next(), fields.next(), fields.next()) {
+                    (Some(index), Some(name), Some(command)) => (index, name, command),
+                    _ => continue,
+                };
+                let target = format!("{}:{}", session, index);
+                items.push(
+                    Item::with_value(format!("{} {}", target, name), target)
+                        .with_description(Some(command.to_string())),
+                );
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// The output of a tmux command, which is empty when it fails, like when no server runs.
+fn tmux(args: &[&str]) -> Result<String, DmitriError> {
+    let output = process::Command::new("tmux")
+        .args(args)
+        .stdin(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .output()
+        .map_err(|source| DmitriError::Spawn {
+            command: format!("tmux {}", args.join(" ")),
+            source,
+        })?;
+    if !output.status.success() {
+        return Ok(String::new());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Switches the client to the selected session or window when dmitri runs inside tmux, or else
+/// attaches to it in a new terminal.
+pub struct TmuxSink {
+    pub terminal: Terminal,
+}
+
+impl ItemSink for TmuxSink {
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        // sessions are selected as they are, and windows as session:index
+        let session = match selection.rsplit_once(':') {
+            Some((session, _)) => session,
+            None => &selection,
+        };
+        let inside = env::var_os("TMUX").is_some();
+        let switch = if inside {
+            "switch-client"
+        } else {
+            "attach-session"
+        };
+        let mut tmux = vec![
+            String::from("tmux"),
+            String::from(switch),
+            String::from("-t"),
+            session.to_string(),
+        ];
+        if session != selection {
+            tmux.extend([
+                String::from(";"),
+                String::from("select-window"),
+                String::from("-t"),
+                selection.clone(),
+            ]);
+        }
+        let argv = if inside {
+            tmux
+        } else {
+            self.terminal.command(&tmux)
+        };
+        let mut command = process::Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        spawn_detached(&mut command).map_err(|source| DmitriError::Spawn {
+            command: argv.join(" "),
+            source,
+        })
+    }
+}
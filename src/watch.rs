@@ -0,0 +1,221 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{CString, OsStr},
+    fs::File,
+    io::{self, Read},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    path::{Path, PathBuf},
+    ptr,
+    sync::{Arc, Mutex, PoisonError, Weak},
+    thread,
+};
+
+/// The events of files that are added, removed, renamed, written or have their permissions
+/// changed, and of the watched directory itself going away.
+const MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_ATTRIB
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF
+    | libc::IN_ONLYDIR;
+
+/// The events of directories that are created in the parent of a directory that doesn't exist
+/// yet.
+const PARENT_MASK: u32 = libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_ONLYDIR;
+
+/// What changed in the watched directories since it was last asked.
+pub enum Changes {
+    /// The files that were added, changed or removed.
+    Files(Vec<PathBuf>),
+    /// Anything, as a directory was added, removed or went away, or there were more events than
+    /// the kernel queues.
+    All,
+}
+
+/// Watches directories with inotify on a thread of its own, and collects the files that change
+/// in them. The thread ends when the watcher is dropped.
+pub struct Watcher {
+    pending: Arc<Mutex<Pending>>,
+    /// The write end of a pipe that the thread polls, which hangs up when it is closed.
+    _stop: File,
+}
+
+#[derive(Default)]
+struct Pending {
+    files: HashSet<PathBuf>,
+    all: bool,
+}
+
+impl Watcher {
+    /// Watch the directories, not their subdirectories. A directory that doesn't exist yet is
+    /// awaited in its nearest parent that does, and everything changes when it is created.
+    pub fn start(dirs: &[PathBuf]) -> io::Result<Watcher> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // closes the descriptor when the thread ends
+        let inotify = unsafe { File::from_raw_fd(fd) };
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let (stopped, stop) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let mut watches = Watches::default();
+        for dir in dirs {
+            if dir.is_dir() {
+                if let Some(wd) = add_watch(fd, dir, MASK) {
+                    watches.dirs.insert(wd, dir.clone());
+                }
+                continue;
+            }
+            let parent = dir.ancestors().skip(1).find(|parent| parent.is_dir());
+            if let Some(parent) = parent {
+                if let Some(wd) = add_watch(fd, parent, PARENT_MASK) {
+                    let (_, missing) = watches
+                        .parents
+                        .entry(wd)
+                        .or_insert_with(|| (parent.to_path_buf(), vec![]));
+                    missing.push(dir.clone());
+                }
+            }
+        }
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let collected = Arc::downgrade(&pending);
+        thread::spawn(move || read_events(inotify, stopped, watches, collected));
+        Ok(Watcher {
+            pending,
+            _stop: stop,
+        })
+    }
+
+    /// What changed since the last time, or `None` if nothing did.
+    pub fn take(&self) -> Option<Changes> {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let Pending { files, all } = mem::take(&mut *pending);
+        if all {
+            Some(Changes::All)
+        } else if !files.is_empty() {
+            Some(Changes::Files(files.into_iter().collect()))
+        } else {
+            None
+        }
+    }
+}
+
+/// The watched directories, and the parents of those that don't exist yet, by watch descriptor.
+#[derive(Default)]
+struct Watches {
+    dirs: HashMap<i32, PathBuf>,
+    parents: HashMap<i32, (PathBuf, Vec<PathBuf>)>,
+}
+
+/// Add the events of the mask to those watched in the directory. A directory can be watched
+/// and be a parent too, and both share a watch descriptor.
+fn add_watch(fd: i32, dir: &Path, mask: u32) -> Option<i32> {
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let wd = unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask | libc::IN_MASK_ADD) };
+    (wd != -1).then_some(wd)
+}
+
+fn read_events(
+    mut inotify: File,
+    stopped: File,
+    mut watches: Watches,
+    pending: Weak<Mutex<Pending>>,
+) {
+    let header = mem::size_of::<libc::inotify_event>();
+    // room for many events at once, and at least one with the longest name
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut fds = [
+        libc::pollfd {
+            fd: inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: stopped.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    loop {
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            eprintln!("Could not watch for changes: {}", err);
+            return;
+        }
+        // the watcher was dropped
+        if fds[1].revents != 0 {
+            return;
+        }
+        let read = match inotify.read(&mut buffer) {
+            Ok(0) => return,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                eprintln!("Could not watch for changes: {}", err);
+                return;
+            }
+        };
+        let pending = match pending.upgrade() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let mut pending = pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut offset = 0;
+        while offset + header <= read {
+            let event: libc::inotify_event =
+                unsafe { ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+            let name = &buffer[offset + header..offset + header + event.len as usize];
+            offset += header + event.len as usize;
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                pending.all = true;
+                continue;
+            }
+            if event.mask & (libc::IN_DELETE_SELF | libc::IN_MOVE_SELF | libc::IN_IGNORED) != 0 {
+                watches.dirs.remove(&event.wd);
+                watches.parents.remove(&event.wd);
+                pending.all = true;
+                continue;
+            }
+            // the name is padded with nul bytes
+            let end = name
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(name.len());
+            let name = OsStr::from_bytes(&name[..end]);
+            // a directory that was awaited, or one on the way to it, was created
+            if let Some((parent, missing)) = watches.parents.get(&event.wd) {
+                if event.mask & libc::IN_ISDIR != 0 && !name.is_empty() {
+                    let created = parent.join(name);
+                    pending.all |= missing.iter().any(|dir| dir.starts_with(&created));
+                }
+            }
+            let dir = match watches.dirs.get(&event.wd) {
+                Some(dir) => dir,
+                None => continue,
+            };
+            // subdirectories that come and go may hold any files
+            if event.mask & libc::IN_ISDIR != 0 {
+                pending.all |= event.mask
+                    & (libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO)
+                    != 0;
+                continue;
+            }
+            if !name.is_empty() {
+                pending.files.insert(dir.join(name));
+            }
+        }
+    }
+}
@@ -0,0 +1,673 @@
+use crate::{
+    backend::{wait_readable, Backend, Event, Key, Placement},
+    error::DmitriError,
+    text::Frame,
+};
+use smithay_client_toolkit::{
+    compositor::{CompositorHandler, CompositorState},
+    data_device_manager::{
+        data_device::{DataDevice, DataDeviceHandler},
+        data_offer::{DataOfferHandler, DragOffer},
+        data_source::DataSourceHandler,
+        DataDeviceManagerState, WritePipe,
+    },
+    delegate_compositor, delegate_data_device, delegate_keyboard, delegate_layer, delegate_output,
+    delegate_pointer, delegate_primary_selection, delegate_registry, delegate_seat, delegate_shm,
+    output::{OutputHandler, OutputState},
+    primary_selection::{
+        device::{PrimarySelectionDevice, PrimarySelectionDeviceHandler},
+        selection::PrimarySelectionSourceHandler,
+        PrimarySelectionManagerState,
+    },
+    reexports::protocols::wp::primary_selection::zv1::client::{
+        zwp_primary_selection_device_v1::ZwpPrimarySelectionDeviceV1,
+        zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+    },
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+    seat::{
+        keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers},
+        pointer::{PointerEvent, PointerEventKind, PointerHandler, BTN_LEFT, BTN_MIDDLE},
+        Capability, SeatHandler, SeatState,
+    },
+    shell::{
+        wlr_layer::{
+            Anchor, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
+            LayerSurfaceConfigure,
+        },
+        WaylandSurface,
+    },
+    shm::{slot::SlotPool, Shm, ShmHandler},
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    ops::Range,
+    os::fd::BorrowedFd,
+    time::Instant,
+};
+use wayland_client::{
+    backend::WaylandError,
+    globals::registry_queue_init,
+    protocol::{
+        wl_data_device::WlDataDevice, wl_data_device_manager::DndAction,
+        wl_data_source::WlDataSource, wl_keyboard, wl_output, wl_pointer, wl_seat, wl_shm,
+        wl_surface,
+    },
+    Connection, EventQueue, QueueHandle,
+};
+use x11rb::image::{BitsPerPixel, ColorComponent, Image, ImageOrder, PixelLayout, ScanlinePad};
+
+/// A bar on a wlr-layer-shell overlay layer, for compositors like sway or Hyprland.
+pub struct WaylandBackend {
+    event_queue: EventQueue<State>,
+    state: State,
+}
+
+struct State {
+    registry_state: RegistryState,
+    seat_state: SeatState,
+    output_state: OutputState,
+    shm: Shm,
+    pool: SlotPool,
+    layer: LayerSurface,
+    keyboard: Option<wl_keyboard::WlKeyboard>,
+    pointer: Option<wl_pointer::WlPointer>,
+    /// A middle click asked to paste the primary selection.
+    paste_primary: bool,
+    data_device_manager: Option<DataDeviceManagerState>,
+    data_device: Option<DataDevice>,
+    primary_selection_manager: Option<PrimarySelectionManagerState>,
+    primary_selection_device: Option<PrimarySelectionDevice>,
+    modifiers: Modifiers,
+    width: u32,
+    height: u32,
+    configured: bool,
+    events: VecDeque<Event>,
+}
+
+impl WaylandBackend {
+    /// Create a layer surface of the given height, by default anchored to the top edge of the
+    /// output that the compositor picks, usually the focused one.
+    pub fn new(height: u16, placement: &Placement) -> Result<WaylandBackend, DmitriError> {
+        let conn = Connection::connect_to_env()?;
+        let (globals, mut event_queue) = registry_queue_init(&conn)?;
+        let qh = event_queue.handle();
+
+        let compositor = CompositorState::bind(&globals, &qh)?;
+        let layer_shell = LayerShell::bind(&globals, &qh)?;
+        let shm = Shm::bind(&globals, &qh)?;
+        let output_state = OutputState::new(&globals, &qh);
+
+        let output = match placement.monitor {
+            Some(i) => Some(
+                output_state
+                    .outputs()
+                    .nth(i)
+                    .ok_or_else(|| DmitriError::Config(format!("No monitor {}", i)))?,
+            ),
+            None => None,
+        };
+
+        let surface = compositor.create_surface(&qh);
+        let layer = layer_shell.create_layer_surface(
+            &qh,
+            surface,
+            Layer::Overlay,
+            Some("dmitri"),
+            output.as_ref(),
+        );
+        if placement.bottom && placement.y.is_none() {
+            layer.set_anchor(Anchor::BOTTOM | Anchor::LEFT | Anchor::RIGHT);
+        } else {
+            layer.set_anchor(Anchor::TOP | Anchor::LEFT | Anchor::RIGHT);
+        }
+        layer.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+        layer.set_size(0, height as u32);
+        // the initial commit without a buffer makes the compositor send the first configure
+        layer.commit();
+
+        let pool = SlotPool::new(height as usize * 4, &shm)?;
+
+        let mut state = State {
+            registry_state: RegistryState::new(&globals),
+            seat_state: SeatState::new(&globals, &qh),
+            output_state,
+            shm,
+            pool,
+            layer,
+            keyboard: None,
+            pointer: None,
+            paste_primary: false,
+            // both are optional, pasting just doesn't work without them
+            data_device_manager: DataDeviceManagerState::bind(&globals, &qh).ok(),
+            data_device: None,
+            primary_selection_manager: PrimarySelectionManagerState::bind(&globals, &qh).ok(),
+            primary_selection_device: None,
+            modifiers: Modifiers::default(),
+            width: 0,
+            height: height as u32,
+            configured: false,
+            events: VecDeque::new(),
+        };
+        while !state.configured {
+            event_queue.blocking_dispatch(&mut state)?;
+        }
+
+        if placement.x.is_some() || placement.y.is_some() || placement.width.is_some() {
+            // the first configure told the output's width, the height is only in the output info
+            let output_height = output
+                .or_else(|| state.output_state.outputs().next())
+                .and_then(|output| state.output_state.info(&output))
+                .and_then(|info| info.logical_size)
+                .map_or(0, |(_, height)| height as u16);
+            let (x, y, width) =
+                placement.resolve((0, 0, state.width as u16, output_height), height);
+            state.layer.set_anchor(Anchor::TOP | Anchor::LEFT);
+            state.layer.set_margin(i32::from(y), 0, 0, i32::from(x));
+            state.layer.set_size(u32::from(width), height as u32);
+            state.layer.commit();
+            state.configured = false;
+            while !state.configured {
+                event_queue.blocking_dispatch(&mut state)?;
+            }
+        }
+        Ok(WaylandBackend { event_queue, state })
+    }
+}
+
+impl Backend for WaylandBackend {
+    fn size(&self) -> (u16, u16) {
+        (self.state.width as u16, self.state.height as u16)
+    }
+
+    fn create_image(&mut self) -> Result<(Image<'static>, PixelLayout), DmitriError> {
+        // the same memory layout as wl_shm's little endian ARGB8888, with alpha in the top byte
+        let (width, height) = self.size();
+        let image = Image::allocate(
+            width,
+            height,
+            ScanlinePad::Pad32,
+            32,
+            BitsPerPixel::B32,
+            ImageOrder::LsbFirst,
+        );
+        let pixel_layout = PixelLayout::new(
+            ColorComponent::new(8, 16)?,
+            ColorComponent::new(8, 8)?,
+            ColorComponent::new(8, 0)?,
+        );
+        Ok((image, pixel_layout))
+    }
+
+    fn present(&mut self, frame: Frame, rows: &[Range<u16>]) -> Result<(), DmitriError> {
+        let image = match frame {
+            Frame::Image(image) => image,
+            Frame::Commands(_) => {
+                return Err(DmitriError::Render(String::from(
+                    "Wayland can only present images",
+                )))
+            }
+        };
+        let width = image.width() as i32;
+        let height = image.height() as i32;
+        let (buffer, canvas) =
+            self.state
+                .pool
+                .create_buffer(width, height, width * 4, wl_shm::Format::Argb8888)?;
+        canvas.copy_from_slice(image.data());
+
+        let surface = self.state.layer.wl_surface();
+        for rows in rows {
+            surface.damage_buffer(0, rows.start as i32, width, (rows.end - rows.start) as i32);
+        }
+        buffer.attach_to(surface)?;
+        self.state.layer.commit();
+        self.event_queue.flush()?;
+        Ok(())
+    }
+
+    fn paste(&mut self, primary: bool) -> Result<(), DmitriError> {
+        let mut pipe = if primary {
+            let offer = match self
+                .state
+                .primary_selection_device
+                .as_ref()
+                .and_then(|device| device.data().selection_offer())
+            {
+                Some(offer) => offer,
+                None => return Ok(()),
+            };
+            match offer.with_mime_types(text_mime_type) {
+                Some(mime_type) => offer.receive(mime_type)?,
+                None => return Ok(()),
+            }
+        } else {
+            let offer = match self
+                .state
+                .data_device
+                .as_ref()
+                .and_then(|device| device.data().selection_offer())
+            {
+                Some(offer) => offer,
+                None => return Ok(()),
+            };
+            match offer.with_mime_types(text_mime_type) {
+                Some(mime_type) => offer.receive(mime_type)?,
+                None => return Ok(()),
+            }
+        };
+        // the source only starts writing once the receive request reached the compositor
+        self.event_queue.flush()?;
+        let mut text = String::new();
+        pipe.read_to_string(&mut text)?;
+        self.state.events.push_back(Event::Paste(text));
+        Ok(())
+    }
+
+    fn next_event(
+        &mut self,
+        wake: BorrowedFd<'_>,
+        deadline: Option<Instant>,
+    ) -> Result<Event, DmitriError> {
+        loop {
+            if let Some(event) = self.state.events.pop_front() {
+                return Ok(event);
+            }
+            if self.state.paste_primary {
+                self.state.paste_primary = false;
+                self.paste(true)?;
+                continue;
+            }
+            // like blocking_dispatch, but also waiting for `wake`
+            if self.event_queue.dispatch_pending(&mut self.state)? > 0 {
+                continue;
+            }
+            self.event_queue.flush()?;
+            let guard = match self.event_queue.prepare_read() {
+                Some(guard) => guard,
+                None => continue,
+            };
+            if let Some(event) = wait_readable(&guard.connection_fd(), &wake, deadline)? {
+                return Ok(event);
+            }
+            match guard.read() {
+                Ok(_) => (),
+                Err(WaylandError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn hide(&mut self) -> Result<(), DmitriError> {
+        let surface = self.state.layer.wl_surface();
+        surface.attach(None, 0, 0);
+        surface.commit();
+        self.event_queue.flush()?;
+        Ok(())
+    }
+
+    fn show(&mut self) -> Result<(), DmitriError> {
+        // an unmapped layer surface has to be configured again before a buffer can be attached
+        self.state.configured = false;
+        self.state.layer.commit();
+        while !self.state.configured {
+            self.event_queue.blocking_dispatch(&mut self.state)?;
+        }
+        Ok(())
+    }
+}
+
+/// The preferred plain text type among the offered mime types.
+fn text_mime_type(mime_types: &[String]) -> Option<String> {
+    ["text/plain;charset=utf-8", "UTF8_STRING", "text/plain"]
+        .iter()
+        .find(|mime_type| mime_types.iter().any(|offered| offered == *mime_type))
+        .map(|mime_type| mime_type.to_string())
+}
+
+impl CompositorHandler for State {
+    fn scale_factor_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _new_factor: i32,
+    ) {
+    }
+
+    fn transform_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _new_transform: wl_output::Transform,
+    ) {
+    }
+
+    fn frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _time: u32,
+    ) {
+    }
+}
+
+impl OutputHandler for State {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _output: wl_output::WlOutput,
+    ) {
+    }
+
+    fn update_output(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _output: wl_output::WlOutput,
+    ) {
+    }
+
+    fn output_destroyed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _output: wl_output::WlOutput,
+    ) {
+    }
+}
+
+impl LayerShellHandler for State {
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
+        self.events.push_back(Event::Close);
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _serial: u32,
+    ) {
+        // the size is only taken while waiting for a configure, images are allocated once
+        if !self.configured {
+            self.width = configure.new_size.0;
+            if configure.new_size.1 != 0 {
+                self.height = configure.new_size.1;
+            }
+            self.configured = true;
+        } else {
+            self.events.push_back(Event::Redraw);
+        }
+    }
+}
+
+impl SeatHandler for State {
+    fn seat_state(&mut self) -> &mut SeatState {
+        &mut self.seat_state
+    }
+
+    fn new_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+
+    fn new_capability(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        seat: wl_seat::WlSeat,
+        capability: Capability,
+    ) {
+        if capability == Capability::Keyboard && self.keyboard.is_none() {
+            self.keyboard = self.seat_state.get_keyboard(qh, &seat, None).ok();
+            self.data_device = self
+                .data_device_manager
+                .as_ref()
+                .map(|manager| manager.get_data_device(qh, &seat));
+            self.primary_selection_device = self
+                .primary_selection_manager
+                .as_ref()
+                .map(|manager| manager.get_selection_device(qh, &seat));
+        }
+        if capability == Capability::Pointer && self.pointer.is_none() {
+            self.pointer = self.seat_state.get_pointer(qh, &seat).ok();
+        }
+    }
+
+    fn remove_capability(
+        &mut self,
+        _conn: &Connection,
+        _: &QueueHandle<Self>,
+        _: wl_seat::WlSeat,
+        capability: Capability,
+    ) {
+        if capability == Capability::Keyboard {
+            if let Some(keyboard) = self.keyboard.take() {
+                keyboard.release();
+            }
+        }
+        if capability == Capability::Pointer {
+            if let Some(pointer) = self.pointer.take() {
+                pointer.release();
+            }
+        }
+    }
+
+    fn remove_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+}
+
+impl KeyboardHandler for State {
+    fn enter(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        _: &wl_surface::WlSurface,
+        _: u32,
+        _: &[u32],
+        _: &[Keysym],
+    ) {
+    }
+
+    fn leave(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        _: &wl_surface::WlSurface,
+        _: u32,
+    ) {
+    }
+
+    fn press_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        _: u32,
+        event: KeyEvent,
+    ) {
+        let text = event
+            .utf8
+            .filter(|text| !text.chars().any(char::is_control))
+            .unwrap_or_default();
+        self.events.push_back(Event::Key(Key {
+            keysym: event.keysym.raw(),
+            text,
+            shift: self.modifiers.shift,
+            ctrl: self.modifiers.ctrl,
+            alt: self.modifiers.alt,
+        }));
+    }
+
+    fn release_key(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        _: u32,
+        _: KeyEvent,
+    ) {
+    }
+
+    fn update_modifiers(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_keyboard::WlKeyboard,
+        _serial: u32,
+        modifiers: Modifiers,
+    ) {
+        self.modifiers = modifiers;
+    }
+}
+
+impl PointerHandler for State {
+    fn pointer_frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _pointer: &wl_pointer::WlPointer,
+        events: &[PointerEvent],
+    ) {
+        // the surface is the only one, and clicks outside of it can't be seen
+        for event in events {
+            let (x, y) = (event.position.0 as i32, event.position.1 as i32);
+            match event.kind {
+                PointerEventKind::Motion { .. } => self.events.push_back(Event::Motion(x, y)),
+                PointerEventKind::Press { button, .. } if button == BTN_LEFT => {
+                    self.events.push_back(Event::Click(x, y));
+                }
+                PointerEventKind::Press { button, .. } if button == BTN_MIDDLE => {
+                    self.paste_primary = true;
+                }
+                PointerEventKind::Axis { vertical, .. } if vertical.discrete != 0 => {
+                    self.events.push_back(Event::Scroll(vertical.discrete));
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+impl DataDeviceHandler for State {
+    fn enter(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn leave(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn motion(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn selection(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+
+    fn drop_performed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataDevice) {}
+}
+
+impl DataOfferHandler for State {
+    fn source_actions(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &mut DragOffer,
+        _: DndAction,
+    ) {
+    }
+
+    fn selected_action(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &mut DragOffer,
+        _: DndAction,
+    ) {
+    }
+}
+
+impl DataSourceHandler for State {
+    fn accept_mime(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &WlDataSource,
+        _: Option<String>,
+    ) {
+    }
+
+    fn send_request(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &WlDataSource,
+        _: String,
+        _: WritePipe,
+    ) {
+    }
+
+    fn cancelled(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+    fn dnd_dropped(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+    fn dnd_finished(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource) {}
+
+    fn action(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &WlDataSource, _: DndAction) {}
+}
+
+impl PrimarySelectionDeviceHandler for State {
+    fn selection(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &ZwpPrimarySelectionDeviceV1,
+    ) {
+    }
+}
+
+impl PrimarySelectionSourceHandler for State {
+    fn send_request(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &ZwpPrimarySelectionSourceV1,
+        _: String,
+        _: WritePipe,
+    ) {
+    }
+
+    fn cancelled(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &ZwpPrimarySelectionSourceV1,
+    ) {
+    }
+}
+
+impl ShmHandler for State {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+delegate_compositor!(State);
+delegate_output!(State);
+delegate_shm!(State);
+delegate_seat!(State);
+delegate_keyboard!(State);
+delegate_pointer!(State);
+delegate_layer!(State);
+delegate_data_device!(State);
+delegate_primary_selection!(State);
+delegate_registry!(State);
+
+impl ProvidesRegistryState for State {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState, SeatState];
+}
@@ -0,0 +1,134 @@
+use crate::{
+    error::DmitriError,
+    items::{Item, ItemSink, ItemSource},
+};
+use breadx::{
+    display::{DisplayConnection, DisplayFunctionsExt},
+    prelude::*,
+    protocol::xproto::{
+        Atom, AtomEnum, ClientMessageEvent, EventMask, GetPropertyReply, GetPropertyRequest,
+        SendEventRequest, Time, Window,
+    },
+};
+use std::{borrow::Cow, collections::HashMap};
+
+/// The top level windows that the window manager lists in `_NET_CLIENT_LIST`, by title.
+#[derive(Clone)]
+pub struct Windows {
+    windows: Vec<(String, Window)>,
+}
+
+impl Windows {
+    pub fn load() -> Result<Windows, DmitriError> {
+        let mut conn = DisplayConnection::connect(None).map_err(|err| {
+            DmitriError::Connection(format!("--window needs X11: {}", err).into())
+        })?;
+        let root = conn.default_screen().root;
+
+        let net_client_list = conn.intern_atom(false, "_NET_CLIENT_LIST")?;
+        let net_wm_name = conn.intern_atom(false, "_NET_WM_NAME")?;
+        let utf8_string = conn.intern_atom(false, "UTF8_STRING")?;
+        let net_client_list = conn.wait_for_reply(net_client_list)?.atom;
+        let net_wm_name = conn.wait_for_reply(net_wm_name)?.atom;
+        let utf8_string = conn.wait_for_reply(utf8_string)?.atom;
+
+        let clients = get_property(&mut conn, root, net_client_list, AtomEnum::WINDOW.into())?;
+        let clients: Vec<Window> = match clients.value32() {
+            Some(clients) => clients.collect(),
+            None => {
+                return Err(DmitriError::Connection(
+                    "The window manager does not support _NET_CLIENT_LIST".into(),
+                ))
+            }
+        };
+
+        let mut windows = Vec::with_capacity(clients.len());
+        let mut titles: HashMap<String, usize> = HashMap::new();
+        for window in clients {
+            // fall back to the legacy name, which is usually Latin-1 but mostly ASCII anyway
+            let mut name = get_property(&mut conn, window, net_wm_name, utf8_string)?.value;
+            if name.is_empty() {
+                name = get_property(
+                    &mut conn,
+                    window,
+                    AtomEnum::WM_NAME.into(),
+                    AtomEnum::STRING.into(),
+                )?
+                .value;
+            }
+            let name = String::from_utf8_lossy(&name).trim().to_string();
+            if name.is_empty() {
+                continue;
+            }
+            // number windows with the same title, like several terminals, so each can be picked
+            let count = titles.entry(name.clone()).or_insert(0);
+            *count += 1;
+            let name = match *count {
+                1 => name,
+                n => format!("{} ({})", name, n),
+            };
+            windows.push((name, window));
+        }
+        Ok(Windows { windows })
+    }
+}
+
+impl ItemSource for Windows {
+    fn items(&mut self) -> Result<Vec<Item>, DmitriError> {
+        Ok(self
+            .windows
+            .iter()
+            .map(|(name, _)| Item::new(name.clone()))
+            .collect())
+    }
+}
+
+impl ItemSink for Windows {
+    /// Ask the window manager to switch to the window's desktop, raise and focus it.
+    fn output(&mut self, selection: String) -> Result<(), DmitriError> {
+        let window = match self.windows.iter().find(|(name, _)| *name == selection) {
+            Some((_, window)) => *window,
+            None => {
+                eprintln!("No window named {}", selection);
+                return Ok(());
+            }
+        };
+        let mut conn = DisplayConnection::connect(None)?;
+        let root = conn.default_screen().root;
+        let net_active_window = conn.intern_atom(false, "_NET_ACTIVE_WINDOW")?;
+        let net_active_window = conn.wait_for_reply(net_active_window)?.atom;
+        // source indication 2 is a pager, which window managers don't second-guess
+        let data: [u32; 5] = [2, Time::CURRENT_TIME.into(), 0, 0, 0];
+        let event = ClientMessageEvent::new(32, window, net_active_window, data);
+        conn.send_void_request(
+            SendEventRequest {
+                propagate: false,
+                destination: root,
+                event_mask: u32::from(
+                    EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+                ),
+                event: Cow::Owned(event.into()),
+            },
+            true,
+        )?;
+        conn.flush()?;
+        Ok(())
+    }
+}
+
+fn get_property(
+    conn: &mut DisplayConnection,
+    window: Window,
+    property: Atom,
+    type_: Atom,
+) -> Result<GetPropertyReply, DmitriError> {
+    let cookie = conn.send_reply_request(GetPropertyRequest {
+        delete: false,
+        window,
+        property,
+        type_,
+        long_offset: 0,
+        long_length: u32::MAX / 4,
+    })?;
+    Ok(conn.wait_for_reply(cookie)?)
+}
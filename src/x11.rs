@@ -0,0 +1,666 @@
+use crate::{
+    backend::{wait_readable, Backend, Event, Key, Placement},
+    error::DmitriError,
+    keyboard::Keyboard,
+    text::Frame,
+    xrender::Render,
+};
+use breadx::{
+    display::{DisplayConnection, DisplayFunctionsExt},
+    prelude::*,
+    protocol::{
+        shm,
+        xinerama::QueryScreensRequest,
+        xproto::{
+            self, Atom, AtomEnum, ColormapAlloc, ConvertSelectionRequest, CreateColormapRequest,
+            EventMask, Gcontext, GetPropertyRequest, GetSelectionOwnerRequest, GrabKeyboardRequest,
+            GrabMode, GrabPointerRequest, GrabStatus, ImageFormat, InputFocus, KeyButMask,
+            QueryPointerRequest, Screen, SetInputFocusRequest, Time, UngrabKeyboardRequest,
+            UngrabPointerRequest, VisualClass, Visualid, Window,
+        },
+        Event as XEvent,
+    },
+};
+use std::{
+    ops::Range,
+    os::fd::BorrowedFd,
+    ptr, thread,
+    time::{Duration, Instant},
+};
+use x11rb::image::{Image, PixelLayout};
+
+pub struct X11Backend {
+    conn: DisplayConnection,
+    root: Window,
+    wid: Window,
+    gc: Gcontext,
+    width: u16,
+    height: u16,
+    depth: u8,
+    visual: Visualid,
+    wm_delete_window: Atom,
+    clipboard: Atom,
+    utf8_string: Atom,
+    /// The property of the window where selection owners put the pasted text.
+    paste_property: Atom,
+    keyboard: Keyboard,
+    /// Where images are presented from, if the server can share memory with this client.
+    shm: Option<SharedMemory>,
+    /// Drawing on the server, with `--xrender`.
+    render: Option<Render>,
+}
+
+/// A shared memory segment that the X server has attached, to present images without sending
+/// them through the socket.
+struct SharedMemory {
+    seg: shm::Seg,
+    addr: *mut u8,
+    size: usize,
+}
+
+impl SharedMemory {
+    /// A new segment of the given size, or `None` if the server lacks the MIT-SHM extension or
+    /// can't reach this client's memory, as when it is remote.
+    fn attach(conn: &mut DisplayConnection, size: usize) -> Option<SharedMemory> {
+        let cookie = conn.send_reply_request(shm::QueryVersionRequest).ok()?;
+        conn.wait_for_reply(cookie).ok()?;
+
+        // Safety: a new private segment, which is only accessed within its size.
+        let id = unsafe { libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600) };
+        if id < 0 {
+            return None;
+        }
+        let addr = unsafe { libc::shmat(id, ptr::null(), 0) };
+        if addr as isize == -1 {
+            unsafe { libc::shmctl(id, libc::IPC_RMID, ptr::null_mut()) };
+            return None;
+        }
+        let seg = conn.generate_xid().ok();
+        let attached = seg.and_then(|seg| {
+            let request = shm::AttachRequest {
+                shmseg: seg,
+                shmid: id as u32,
+                read_only: true,
+            };
+            let cookie = conn.send_void_request(request, false).ok()?;
+            conn.wait_for_reply(cookie).ok()
+        });
+        // the segment is freed as soon as both sides detach, even if dmitri crashes
+        unsafe { libc::shmctl(id, libc::IPC_RMID, ptr::null_mut()) };
+        match (seg, attached) {
+            (Some(seg), Some(())) => Some(SharedMemory {
+                seg,
+                addr: addr as *mut u8,
+                size,
+            }),
+            _ => {
+                unsafe { libc::shmdt(addr) };
+                None
+            }
+        }
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        // the server detaches when the connection closes
+        unsafe { libc::shmdt(self.addr as *const libc::c_void) };
+    }
+}
+
+impl X11Backend {
+    /// Create and map a window of the given height, by default across the top of the monitor
+    /// containing the pointer.
+    /// With `xrender`, text is drawn on the server if it has the RENDER extension.
+    pub fn new(
+        height: u16,
+        placement: &Placement,
+        xrender: bool,
+    ) -> Result<X11Backend, DmitriError> {
+        let mut conn = DisplayConnection::connect(None)?;
+
+        let root = conn.default_screen().root;
+        //
+        // let cookie = conn.send_request(GetInputFocusRequest {
+        // ..Default::default()
+        // })?;
+        // let reply = conn.resolve_request(cookie)?;
+        // let focus_window = reply.focus;
+        //
+        // let screens = conn.screens().to_owned();
+        // 'out: for screen in screens {
+        // let tree = screen.root.query_tree_immediate(&mut conn)?;
+        // for child in tree.children.iter() {
+        // if *child == focus_window {
+        // println!("it is child");
+        // root = screen.root;
+        // break 'out;
+        // }
+        // }
+        // }
+
+        let monitor = monitor_geometry(&mut conn, root, placement.monitor)?;
+        let (x, y, width) = placement.resolve(monitor, height);
+
+        // with a compositor running, an ARGB visual makes transparent colors see-through
+        let mut window_aux = xproto::CreateWindowAux::new();
+        let (depth, visual) = match argb_visual(&mut conn)? {
+            Some(visual) => {
+                let colormap = conn.generate_xid()?;
+                conn.send_void_request(
+                    CreateColormapRequest {
+                        alloc: ColormapAlloc::NONE,
+                        mid: colormap,
+                        window: root,
+                        visual,
+                    },
+                    true,
+                )?;
+                // a border pixel and colormap are required when the depth differs from the root
+                window_aux = window_aux.colormap(colormap).border_pixel(0);
+                (32, visual)
+            }
+            None => (0, 0),
+        };
+
+        let wid = conn.generate_xid()?;
+        conn.create_window_checked(
+            depth,
+            wid,
+            root,   // parent
+            x,      // x
+            y,      // y
+            width,  // width
+            height, // height
+            0,      // border width
+            xproto::WindowClass::INPUT_OUTPUT,
+            visual,
+            window_aux
+                .background_pixel(if depth == 32 {
+                    0
+                } else {
+                    conn.default_screen().black_pixel
+                })
+                .override_redirect(1)
+                .event_mask(
+                    EventMask::EXPOSURE
+                        | EventMask::KEY_PRESS
+                        | EventMask::KEY_RELEASE
+                        | EventMask::BUTTON_PRESS
+                        | EventMask::POINTER_MOTION
+                        | EventMask::VISIBILITY_CHANGE,
+                ),
+        )?;
+
+        conn.map_window(wid)?;
+        // window.set_title(&mut conn, "Hello World!")?;
+
+        let gc = conn.generate_xid()?;
+        conn.create_gc_checked(
+            gc,
+            wid,
+            xproto::CreateGCAux::new()
+                .foreground(conn.default_screen().black_pixel)
+                .graphics_exposures(0)
+                .line_width(10),
+        )?;
+
+        let geometry = conn.get_geometry_immediate(wid)?;
+        let visual = if visual == 0 {
+            conn.default_screen().root_visual
+        } else {
+            visual
+        };
+        let render = if xrender {
+            let render = Render::new(
+                &mut conn,
+                wid,
+                visual,
+                geometry.depth,
+                geometry.width,
+                geometry.height,
+            )?;
+            if render.is_none() {
+                eprintln!("dmitri: the X server can't draw with RENDER, presenting images instead");
+            }
+            render
+        } else {
+            None
+        };
+
+        // set up an exit strategy
+        let wm_protocols = conn.intern_atom(false, "WM_PROTOCOLS")?;
+        let wm_delete_window = conn.intern_atom(false, "WM_DELETE_WINDOW")?;
+        conn.flush()?;
+        let wm_protocols = conn.wait_for_reply(wm_protocols)?.atom;
+        let wm_delete_window = conn.wait_for_reply(wm_delete_window)?.atom;
+
+        conn.change_property(
+            xproto::PropMode::REPLACE,
+            wid,
+            wm_protocols,
+            xproto::AtomEnum::ATOM.into(),
+            32,
+            1,
+            &wm_delete_window,
+        )?;
+
+        let clipboard = conn.intern_atom(false, "CLIPBOARD")?;
+        let utf8_string = conn.intern_atom(false, "UTF8_STRING")?;
+        let paste_property = conn.intern_atom(false, "DMITRI_PASTE")?;
+        let clipboard = conn.wait_for_reply(clipboard)?.atom;
+        let utf8_string = conn.wait_for_reply(utf8_string)?.atom;
+        let paste_property = conn.wait_for_reply(paste_property)?.atom;
+
+        let keyboard = Keyboard::new(&mut conn, root)?;
+
+        let mut backend = X11Backend {
+            conn,
+            root,
+            wid,
+            gc,
+            width: geometry.width,
+            height: geometry.height,
+            depth: geometry.depth,
+            visual,
+            wm_delete_window,
+            clipboard,
+            utf8_string,
+            paste_property,
+            keyboard,
+            shm: None,
+            render,
+        };
+        backend.grab_keyboard()?;
+        backend.grab_pointer()?;
+        Ok(backend)
+    }
+
+    /// Focus the window and grab the keyboard, so that no keystrokes leak to other windows even
+    /// if the window manager takes the focus back. Another client may still hold a grab right after
+    /// the launching keybinding, so this retries for up to a second like dmenu.
+    fn grab_keyboard(&mut self) -> Result<(), DmitriError> {
+        self.conn.send_void_request(
+            SetInputFocusRequest {
+                focus: self.wid,
+                revert_to: InputFocus::PARENT,
+                time: Time::CURRENT_TIME.into(),
+            },
+            true,
+        )?;
+        for _ in 0..1000 {
+            let cookie = self.conn.send_reply_request(GrabKeyboardRequest {
+                owner_events: true,
+                grab_window: self.root,
+                time: Time::CURRENT_TIME.into(),
+                pointer_mode: GrabMode::ASYNC,
+                keyboard_mode: GrabMode::ASYNC,
+            })?;
+            if self.conn.wait_for_reply(cookie)?.status == GrabStatus::SUCCESS {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        Err(DmitriError::Connection(
+            "Could not grab the keyboard".into(),
+        ))
+    }
+
+    /// Grab the pointer too, so that clicks outside the window are reported to it and close it.
+    /// The window works without, so a grab that fails is not retried.
+    fn grab_pointer(&mut self) -> Result<(), DmitriError> {
+        let cookie = self.conn.send_reply_request(GrabPointerRequest {
+            // events in the window still arrive as usual
+            owner_events: true,
+            grab_window: self.wid,
+            event_mask: u32::from(EventMask::BUTTON_PRESS | EventMask::POINTER_MOTION) as u16,
+            pointer_mode: GrabMode::ASYNC,
+            keyboard_mode: GrabMode::ASYNC,
+            confine_to: 0,
+            cursor: 0,
+            time: Time::CURRENT_TIME.into(),
+        })?;
+        self.conn.wait_for_reply(cookie)?;
+        Ok(())
+    }
+
+    /// Whether the position relative to the window is inside of it.
+    fn contains(&self, x: i16, y: i16) -> bool {
+        (0..self.width as i32).contains(&i32::from(x))
+            && (0..self.height as i32).contains(&i32::from(y))
+    }
+}
+
+impl Backend for X11Backend {
+    fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    fn create_image(&mut self) -> Result<(Image<'static>, PixelLayout), DmitriError> {
+        let image = Image::allocate_native(self.width, self.height, self.depth, self.conn.setup())?;
+        let pixel_layout = check_visual(self.conn.default_screen(), self.visual)?;
+        if self.render.is_none() {
+            self.shm = SharedMemory::attach(&mut self.conn, image.data().len());
+        }
+        Ok((image, pixel_layout))
+    }
+
+    fn draws_commands(&self) -> bool {
+        self.render.is_some()
+    }
+
+    fn present(&mut self, frame: Frame, rows: &[Range<u16>]) -> Result<(), DmitriError> {
+        let image = match (frame, &mut self.render) {
+            (Frame::Image(image), _) => image,
+            (Frame::Commands(commands), Some(render)) => {
+                render.draw(&mut self.conn, &commands)?;
+                return render.present(&mut self.conn, self.wid, self.gc, rows);
+            }
+            (Frame::Commands(_), None) => {
+                return Err(DmitriError::Render(String::from(
+                    "Drawing commands need the RENDER extension",
+                )))
+            }
+        };
+        let data = image.data();
+        let stride = data.len() / image.height() as usize;
+        if let Some(shm) = self.shm.as_ref().filter(|shm| data.len() <= shm.size) {
+            for rows in rows {
+                let bytes = rows.start as usize * stride..rows.end as usize * stride;
+                // Safety: the segment is large enough, and the server is done reading the previous
+                // image since it replied after copying it.
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        data[bytes.clone()].as_ptr(),
+                        shm.addr.add(bytes.start),
+                        bytes.len(),
+                    )
+                };
+                self.conn.send_void_request(
+                    shm::PutImageRequest {
+                        drawable: self.wid,
+                        gc: self.gc,
+                        total_width: image.width(),
+                        total_height: image.height(),
+                        src_x: 0,
+                        src_y: rows.start,
+                        src_width: image.width(),
+                        src_height: rows.end - rows.start,
+                        dst_x: 0,
+                        dst_y: rows.start as i16,
+                        depth: image.depth(),
+                        format: ImageFormat::Z_PIXMAP.into(),
+                        send_event: false,
+                        shmseg: shm.seg,
+                        offset: 0,
+                    },
+                    true,
+                )?;
+            }
+            // a round trip, so the next image isn't written while this one is read
+            self.conn.get_input_focus_immediate()?;
+            return Ok(());
+        }
+
+        for rows in rows {
+            self.conn.put_image(
+                ImageFormat::Z_PIXMAP,
+                self.wid,
+                self.gc,
+                image.width(),
+                rows.end - rows.start,
+                0,
+                rows.start as i16,
+                0,
+                image.depth(),
+                &data[rows.start as usize * stride..rows.end as usize * stride],
+            )?;
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn paste(&mut self, primary: bool) -> Result<(), DmitriError> {
+        self.conn.send_void_request(
+            ConvertSelectionRequest {
+                requestor: self.wid,
+                selection: if primary {
+                    AtomEnum::PRIMARY.into()
+                } else {
+                    self.clipboard
+                },
+                target: self.utf8_string,
+                property: self.paste_property,
+                time: Time::CURRENT_TIME.into(),
+            },
+            true,
+        )?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn next_event(
+        &mut self,
+        wake: BorrowedFd<'_>,
+        deadline: Option<Instant>,
+    ) -> Result<Event, DmitriError> {
+        loop {
+            let event = match self.conn.poll_for_event()? {
+                Some(event) => event,
+                None => {
+                    self.conn.flush()?;
+                    if let Some(event) = wait_readable(&self.conn, &wake, deadline)? {
+                        return Ok(event);
+                    }
+                    continue;
+                }
+            };
+            match event {
+                XEvent::ClientMessage(cme) => {
+                    if cme.data.as_data32()[0] == self.wm_delete_window {
+                        return Ok(Event::Close);
+                    }
+                }
+                XEvent::Expose(_) => return Ok(Event::Redraw),
+                // the pointer grab reports presses outside the window, relative to it
+                XEvent::ButtonPress(bp) if !self.contains(bp.event_x, bp.event_y) => {
+                    return Ok(Event::Close);
+                }
+                XEvent::ButtonPress(bp) => match bp.detail {
+                    1 => return Ok(Event::Click(bp.event_x.into(), bp.event_y.into())),
+                    // middle click pastes the primary selection
+                    2 => self.paste(true)?,
+                    4 => return Ok(Event::Scroll(-1)),
+                    5 => return Ok(Event::Scroll(1)),
+                    _ => (),
+                },
+                XEvent::MotionNotify(mn) if self.contains(mn.event_x, mn.event_y) => {
+                    return Ok(Event::Motion(mn.event_x.into(), mn.event_y.into()));
+                }
+                XEvent::SelectionNotify(sn) => {
+                    // the property is none if the selection is empty or can't be converted
+                    if sn.property == u32::from(AtomEnum::NONE) {
+                        continue;
+                    }
+                    // large selections that are transferred incrementally are not supported
+                    let cookie = self.conn.send_reply_request(GetPropertyRequest {
+                        delete: true,
+                        window: self.wid,
+                        property: sn.property,
+                        type_: AtomEnum::ANY.into(),
+                        long_offset: 0,
+                        long_length: u32::MAX / 4,
+                    })?;
+                    let reply = self.conn.wait_for_reply(cookie)?;
+                    return Ok(Event::Paste(
+                        String::from_utf8_lossy(&reply.value).to_string(),
+                    ));
+                }
+                XEvent::KeyPress(kp) => {
+                    self.keyboard.update(kp.state);
+                    let (keysym, text) = self.keyboard.press(kp.detail);
+                    return Ok(Event::Key(Key {
+                        keysym,
+                        text,
+                        shift: kp.state & u16::from(KeyButMask::SHIFT) != 0,
+                        ctrl: kp.state & u16::from(KeyButMask::CONTROL) != 0,
+                        alt: kp.state & u16::from(KeyButMask::MOD1) != 0,
+                    }));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn hide(&mut self) -> Result<(), DmitriError> {
+        self.conn.send_void_request(
+            UngrabKeyboardRequest {
+                time: Time::CURRENT_TIME.into(),
+            },
+            true,
+        )?;
+        self.conn.send_void_request(
+            UngrabPointerRequest {
+                time: Time::CURRENT_TIME.into(),
+            },
+            true,
+        )?;
+        self.conn.unmap_window(self.wid)?;
+        self.conn.flush()?;
+        // window.free(conn)?;
+        Ok(())
+    }
+
+    fn show(&mut self) -> Result<(), DmitriError> {
+        self.keyboard.reset();
+        self.conn.map_window(self.wid)?;
+        self.grab_keyboard()?;
+        self.grab_pointer()?;
+        self.conn.flush()?;
+        Ok(())
+    }
+}
+
+/// Position and size of the monitor with the given Xinerama index, or else the one containing the
+/// pointer. Without Xinerama, or with a single screen, this is the whole root window.
+fn monitor_geometry(
+    conn: &mut DisplayConnection,
+    root: Window,
+    monitor: Option<usize>,
+) -> Result<(i16, i16, u16, u16), DmitriError> {
+    let screens = match conn.send_reply_request(QueryScreensRequest) {
+        Ok(cookie) => conn
+            .wait_for_reply(cookie)
+            .map(|reply| reply.screen_info)
+            .unwrap_or_default(),
+        Err(_) => vec![],
+    };
+    if screens.is_empty() {
+        let root_geometry = conn.get_geometry_immediate(root)?;
+        return Ok((0, 0, root_geometry.width, root_geometry.height));
+    }
+
+    let screen = match monitor {
+        Some(i) => screens.get(i).ok_or_else(|| {
+            DmitriError::Config(format!("No monitor {}, there are {}", i, screens.len()))
+        })?,
+        None => {
+            let cookie = conn.send_reply_request(QueryPointerRequest { window: root })?;
+            let pointer = conn.wait_for_reply(cookie)?;
+            let (px, py) = (i32::from(pointer.root_x), i32::from(pointer.root_y));
+            screens
+                .iter()
+                .find(|screen| {
+                    let (x, y) = (i32::from(screen.x_org), i32::from(screen.y_org));
+                    px >= x
+                        && px < x + i32::from(screen.width)
+                        && py >= y
+                        && py < y + i32::from(screen.height)
+                })
+                .unwrap_or(&screens[0])
+        }
+    };
+    Ok((screen.x_org, screen.y_org, screen.width, screen.height))
+}
+
+/// The resources loaded with xrdb, from the `RESOURCE_MANAGER` property of the root window. None
+/// without an X server or when no resources were loaded.
+pub fn resource_manager() -> Option<String> {
+    let mut conn = DisplayConnection::connect(None).ok()?;
+    let root = conn.default_screen().root;
+    let cookie = conn
+        .send_reply_request(GetPropertyRequest {
+            delete: false,
+            window: root,
+            property: AtomEnum::RESOURCE_MANAGER.into(),
+            type_: AtomEnum::STRING.into(),
+            long_offset: 0,
+            long_length: u32::MAX / 4,
+        })
+        .ok()?;
+    let reply = conn.wait_for_reply(cookie).ok()?;
+    if reply.value.is_empty() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&reply.value).to_string())
+}
+
+/// A 32 bit TrueColor visual, if the screen has one and a compositing manager is running.
+fn argb_visual(conn: &mut DisplayConnection) -> Result<Option<Visualid>, DmitriError> {
+    let name = format!("_NET_WM_CM_S{}", conn.default_screen_index());
+    let cm_selection = conn.intern_atom(false, &name)?;
+    let cm_selection = conn.wait_for_reply(cm_selection)?.atom;
+    let cookie = conn.send_reply_request(GetSelectionOwnerRequest {
+        selection: cm_selection,
+    })?;
+    // the selection is owned by the compositing manager of the screen
+    if conn.wait_for_reply(cookie)?.owner == 0 {
+        return Ok(None);
+    }
+    Ok(conn
+        .default_screen()
+        .allowed_depths
+        .iter()
+        .filter(|depth| depth.depth == 32)
+        .flat_map(|depth| depth.visuals.iter())
+        .find(|visual| visual.class == VisualClass::TRUE_COLOR)
+        .map(|visual| visual.visual_id))
+}
+
+/// Check that the given visual is "as expected" (pixel values are 0xRRGGBB with RR/GG/BB being the
+/// colors), and return its layout.
+fn check_visual(screen: &Screen, id: Visualid) -> Result<PixelLayout, DmitriError> {
+    // Find the information about the visual and at the same time check its depth.
+    let visual_info = screen.allowed_depths.iter().find_map(|depth| {
+        let info = depth.visuals.iter().find(|depth| depth.visual_id == id);
+        info.map(|info| (depth.depth, info))
+    });
+    let (depth, visual_type) = match visual_info {
+        Some(info) => info,
+        None => {
+            return Err(DmitriError::Render(String::from(
+                "Did not find the visual's description",
+            )))
+        }
+    };
+    // Check that the pixels have red/green/blue components that we can set directly.
+    match visual_type.class {
+        VisualClass::TRUE_COLOR | VisualClass::DIRECT_COLOR => {}
+        _ => {
+            return Err(DmitriError::Render(format!(
+                "The visual is not true / direct color, but {:?}",
+                visual_type,
+            )))
+        }
+    }
+    let result = PixelLayout::from_visual_type(*visual_type)?;
+    // the alpha channel of ARGB visuals is not part of the layout
+    if result.depth() > depth {
+        return Err(DmitriError::Render(format!(
+            "The visual has {} bits of color but a depth of {}",
+            result.depth(),
+            depth
+        )));
+    }
+    Ok(result)
+}
@@ -0,0 +1,438 @@
+use crate::{
+    error::DmitriError,
+    glyphs::{ColorImage, Coverage},
+    text::{Color, DrawCommand},
+};
+use breadx::{
+    display::DisplayConnection,
+    prelude::*,
+    protocol::{
+        render::{
+            self, AddGlyphsRequest, CompositeGlyphs32Request, CreateGlyphSetRequest,
+            CreatePictureAux, CreatePictureRequest, CreateSolidFillRequest, FillRectanglesRequest,
+            Glyphinfo, Glyphset, PictOp, PictType, Pictformat, Pictforminfo, Picture,
+            QueryPictFormatsRequest, QueryVersionRequest, SetPictureClipRectanglesRequest,
+        },
+        xproto::{
+            CopyAreaRequest, CreatePixmapRequest, Gcontext, ImageOrder, Pixmap, Rectangle,
+            Visualid, Window,
+        },
+    },
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+/// Drawing with the RENDER extension. The window is drawn into a pixmap on the server, which
+/// keeps the glyphs in glyph sets and blends them itself, and the rows that changed are copied
+/// to the window when presenting.
+pub struct Render {
+    pixmap: Pixmap,
+    picture: Picture,
+    /// Coverages as 8 bit alpha.
+    glyphs: Glyphset,
+    /// Color glyphs as premultiplied ARGB.
+    color_glyphs: Glyphset,
+    /// The numbers of the glyphs that were added to the glyph sets.
+    added: HashSet<u32>,
+    added_colors: HashSet<u32>,
+    /// Pictures of a single color, which glyphs are drawn with.
+    fills: HashMap<render::Color, Picture>,
+    /// What `picture` is clipped to.
+    clip: Rectangle,
+    width: u16,
+    height: u16,
+    byte_order: ImageOrder,
+}
+
+impl Render {
+    /// Set up drawing for a window with the given visual, depth and size. `None` if the server
+    /// lacks RENDER 0.10, which added solid fills, or the picture formats to draw glyphs with.
+    pub fn new(
+        conn: &mut DisplayConnection,
+        window: Window,
+        visual: Visualid,
+        depth: u8,
+        width: u16,
+        height: u16,
+    ) -> Result<Option<Render>, DmitriError> {
+        let version = match conn.send_reply_request(QueryVersionRequest {
+            client_major_version: 0,
+            client_minor_version: 11,
+        }) {
+            Ok(cookie) => conn.wait_for_reply(cookie).ok(),
+            Err(_) => None,
+        };
+        match version {
+            Some(version) if version.major_version > 0 || version.minor_version >= 10 => (),
+            _ => return Ok(None),
+        }
+
+        let cookie = conn.send_reply_request(QueryPictFormatsRequest)?;
+        let formats = conn.wait_for_reply(cookie)?;
+        let window_format = formats
+            .screens
+            .iter()
+            .flat_map(|screen| &screen.depths)
+            .flat_map(|depth| &depth.visuals)
+            .find(|pict_visual| pict_visual.visual == visual)
+            .map(|pict_visual| pict_visual.format);
+        let a8 = find_format(&formats.formats, 8, [0, 0, 0, 0], 0);
+        let argb32 = find_format(&formats.formats, 32, [16, 8, 0, 24], 0xff);
+        let (window_format, a8, argb32) = match (window_format, a8, argb32) {
+            (Some(window_format), Some(a8), Some(argb32)) => (window_format, a8, argb32),
+            _ => return Ok(None),
+        };
+
+        let pixmap = conn.generate_xid()?;
+        conn.send_void_request(
+            CreatePixmapRequest {
+                depth,
+                pid: pixmap,
+                drawable: window,
+                width,
+                height,
+            },
+            true,
+        )?;
+        let picture = conn.generate_xid()?;
+        conn.send_void_request(
+            CreatePictureRequest {
+                pid: picture,
+                drawable: pixmap,
+                format: window_format,
+                value_list: Cow::Owned(CreatePictureAux::new()),
+            },
+            true,
+        )?;
+        let glyphs = conn.generate_xid()?;
+        conn.send_void_request(
+            CreateGlyphSetRequest {
+                gsid: glyphs,
+                format: a8,
+            },
+            true,
+        )?;
+        let color_glyphs = conn.generate_xid()?;
+        conn.send_void_request(
+            CreateGlyphSetRequest {
+                gsid: color_glyphs,
+                format: argb32,
+            },
+            true,
+        )?;
+
+        Ok(Some(Render {
+            pixmap,
+            picture,
+            glyphs,
+            color_glyphs,
+            added: HashSet::new(),
+            added_colors: HashSet::new(),
+            fills: HashMap::new(),
+            clip: bounds(width, height),
+            width,
+            height,
+            byte_order: conn.setup().image_byte_order,
+        }))
+    }
+
+    /// Draw the commands into the pixmap, adding the glyphs that the server doesn't have yet.
+    pub fn draw(
+        &mut self,
+        conn: &mut DisplayConnection,
+        commands: &[DrawCommand],
+    ) -> Result<(), DmitriError> {
+        // consecutive glyphs of the same color and clip are drawn with a single request
+        let mut batch: Option<(Color, Rectangle, Vec<u8>)> = None;
+        let mut point = (0, 0);
+        for command in commands {
+            match command {
+                DrawCommand::Glyph {
+                    id,
+                    coverage,
+                    x,
+                    y,
+                    color,
+                    clip,
+                } => {
+                    self.add_coverage(conn, *id, coverage)?;
+                    let same = matches!(&batch, Some((c, r, _)) if c == color && r == clip);
+                    if !same {
+                        self.draw_batch(conn, batch.take())?;
+                        batch = Some((*color, *clip, vec![]));
+                        point = (0, 0);
+                    }
+                    if let Some((_, _, glyphcmds)) = &mut batch {
+                        push_glyph(glyphcmds, *id, (x - point.0, y - point.1));
+                    }
+                    point = (*x, *y);
+                }
+                DrawCommand::ColorGlyph {
+                    id,
+                    image,
+                    x,
+                    y,
+                    clip,
+                } => {
+                    self.draw_batch(conn, batch.take())?;
+                    self.add_color_image(conn, *id, image)?;
+                    self.set_clip(conn, *clip)?;
+                    // the glyph's colors are taken by a white source
+                    let src = self.fill(conn, (65535., 65535., 65535., 65535.))?;
+                    let mut glyphcmds = vec![];
+                    push_glyph(&mut glyphcmds, *id, (*x, *y));
+                    conn.send_void_request(
+                        CompositeGlyphs32Request {
+                            op: PictOp::OVER,
+                            src,
+                            dst: self.picture,
+                            mask_format: 0,
+                            glyphset: self.color_glyphs,
+                            src_x: 0,
+                            src_y: 0,
+                            glyphcmds: Cow::Owned(glyphcmds),
+                        },
+                        true,
+                    )?;
+                }
+                DrawCommand::Fill(rect, color) => {
+                    self.draw_batch(conn, batch.take())?;
+                    self.set_clip(conn, bounds(self.width, self.height))?;
+                    conn.send_void_request(
+                        FillRectanglesRequest {
+                            op: PictOp::SRC,
+                            dst: self.picture,
+                            color: premultiply(*color),
+                            rects: Cow::Borrowed(&[*rect]),
+                        },
+                        true,
+                    )?;
+                }
+            }
+        }
+        self.draw_batch(conn, batch)
+    }
+
+    /// Copy the given rows of the pixmap to the window.
+    pub fn present(
+        &self,
+        conn: &mut DisplayConnection,
+        window: Window,
+        gc: Gcontext,
+        rows: &[Range<u16>],
+    ) -> Result<(), DmitriError> {
+        for rows in rows {
+            conn.send_void_request(
+                CopyAreaRequest {
+                    src_drawable: self.pixmap,
+                    dst_drawable: window,
+                    gc,
+                    src_x: 0,
+                    src_y: rows.start as i16,
+                    dst_x: 0,
+                    dst_y: rows.start as i16,
+                    width: self.width,
+                    height: rows.end - rows.start,
+                },
+                true,
+            )?;
+        }
+        conn.flush()?;
+        Ok(())
+    }
+
+    /// Draw glyphs of the coverage glyph set in the color, within the clip.
+    fn draw_batch(
+        &mut self,
+        conn: &mut DisplayConnection,
+        batch: Option<(Color, Rectangle, Vec<u8>)>,
+    ) -> Result<(), DmitriError> {
+        let (color, clip, glyphcmds) = match batch {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+        self.set_clip(conn, clip)?;
+        let src = self.fill(conn, color)?;
+        conn.send_void_request(
+            CompositeGlyphs32Request {
+                op: PictOp::OVER,
+                src,
+                dst: self.picture,
+                mask_format: 0,
+                glyphset: self.glyphs,
+                src_x: 0,
+                src_y: 0,
+                glyphcmds: Cow::Owned(glyphcmds),
+            },
+            true,
+        )?;
+        Ok(())
+    }
+
+    fn set_clip(
+        &mut self,
+        conn: &mut DisplayConnection,
+        clip: Rectangle,
+    ) -> Result<(), DmitriError> {
+        if clip == self.clip {
+            return Ok(());
+        }
+        conn.send_void_request(
+            SetPictureClipRectanglesRequest {
+                picture: self.picture,
+                clip_x_origin: 0,
+                clip_y_origin: 0,
+                rectangles: Cow::Borrowed(&[clip]),
+            },
+            true,
+        )?;
+        self.clip = clip;
+        Ok(())
+    }
+
+    /// A picture of the color, creating it the first time.
+    fn fill(&mut self, conn: &mut DisplayConnection, color: Color) -> Result<Picture, DmitriError> {
+        let color = premultiply(color);
+        if let Some(&picture) = self.fills.get(&color) {
+            return Ok(picture);
+        }
+        let picture = conn.generate_xid()?;
+        conn.send_void_request(CreateSolidFillRequest { picture, color }, true)?;
+        self.fills.insert(color, picture);
+        Ok(picture)
+    }
+
+    fn add_coverage(
+        &mut self,
+        conn: &mut DisplayConnection,
+        id: u32,
+        coverage: &Coverage,
+    ) -> Result<(), DmitriError> {
+        if !self.added.insert(id) {
+            return Ok(());
+        }
+        let width = coverage.width as usize;
+        let height = coverage.values.len() / width;
+        // rows are padded to 4 bytes
+        let stride = (width + 3) & !3;
+        let mut data = vec![0; stride * height];
+        for (y, row) in coverage.values.chunks(width).enumerate() {
+            for (x, v) in row.iter().enumerate() {
+                data[y * stride + x] = (v * 255.).round() as u8;
+            }
+        }
+        add_glyph(conn, self.glyphs, id, (width, height), data)
+    }
+
+    fn add_color_image(
+        &mut self,
+        conn: &mut DisplayConnection,
+        id: u32,
+        image: &ColorImage,
+    ) -> Result<(), DmitriError> {
+        if !self.added_colors.insert(id) {
+            return Ok(());
+        }
+        let width = image.width as usize;
+        let height = image.pixels.len() / width;
+        let mut data = Vec::with_capacity(image.pixels.len() * 4);
+        for &color in &image.pixels {
+            let color = premultiply(color);
+            let pixel = (color.alpha as u32 >> 8) << 24
+                | (color.red as u32 >> 8) << 16
+                | (color.green as u32 >> 8) << 8
+                | color.blue as u32 >> 8;
+            // glyph images are in the server's byte order, like other images
+            if self.byte_order == ImageOrder::LSB_FIRST {
+                data.extend(pixel.to_le_bytes());
+            } else {
+                data.extend(pixel.to_be_bytes());
+            }
+        }
+        add_glyph(conn, self.color_glyphs, id, (width, height), data)
+    }
+}
+
+/// Add a glyph with its origin at its top left corner, so that it is drawn at the point given
+/// for it.
+fn add_glyph(
+    conn: &mut DisplayConnection,
+    glyphset: Glyphset,
+    id: u32,
+    (width, height): (usize, usize),
+    data: Vec<u8>,
+) -> Result<(), DmitriError> {
+    conn.send_void_request(
+        AddGlyphsRequest {
+            glyphset,
+            glyphids: Cow::Borrowed(&[id]),
+            glyphs: Cow::Borrowed(&[Glyphinfo {
+                width: width as u16,
+                height: height as u16,
+                x: 0,
+                y: 0,
+                x_off: 0,
+                y_off: 0,
+            }]),
+            data: Cow::Owned(data),
+        },
+        true,
+    )?;
+    Ok(())
+}
+
+/// Append a glyph element with a single glyph, `delta` from the previous one, or from the
+/// picture's origin for the first.
+fn push_glyph(glyphcmds: &mut Vec<u8>, id: u32, delta: (i32, i32)) {
+    glyphcmds.extend([1, 0, 0, 0]);
+    glyphcmds.extend((delta.0 as i16).to_ne_bytes());
+    glyphcmds.extend((delta.1 as i16).to_ne_bytes());
+    glyphcmds.extend(id.to_ne_bytes());
+}
+
+/// The direct format of the depth with the given red, green, blue and alpha shifts, and 8 bit
+/// channels where `color_mask` isn't 0.
+fn find_format(
+    formats: &[Pictforminfo],
+    depth: u8,
+    shifts: [u16; 4],
+    color_mask: u16,
+) -> Option<Pictformat> {
+    formats
+        .iter()
+        .find(|format| {
+            let direct = &format.direct;
+            format.type_ == PictType::DIRECT
+                && format.depth == depth
+                && direct.alpha_mask == 0xff
+                && direct.alpha_shift == shifts[3]
+                && [direct.red_mask, direct.green_mask, direct.blue_mask] == [color_mask; 3]
+                && (color_mask == 0
+                    || [direct.red_shift, direct.green_shift, direct.blue_shift]
+                        == [shifts[0], shifts[1], shifts[2]])
+        })
+        .map(|format| format.id)
+}
+
+fn bounds(width: u16, height: u16) -> Rectangle {
+    Rectangle {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    }
+}
+
+/// RENDER's colors are premultiplied.
+fn premultiply(color: Color) -> render::Color {
+    let alpha = color.3 / 65535.;
+    render::Color {
+        red: (color.0 * alpha) as u16,
+        green: (color.1 * alpha) as u16,
+        blue: (color.2 * alpha) as u16,
+        alpha: color.3 as u16,
+    }
+}